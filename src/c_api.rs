@@ -85,6 +85,27 @@ pub fn json_api_get_at<M: Manager>(_: M, json: *const c_void, index: size_t) ->
     }
 }
 
+pub fn json_api_get_object_value<M: Manager>(
+    _: M,
+    json: *const c_void,
+    key: *const c_char,
+    value: *mut *const c_void,
+) -> c_int {
+    let json = unsafe { &*(json as *const M::V) };
+    let key = unsafe { CStr::from_ptr(key).to_str() };
+    match (json.get_type(), key) {
+        (SelectValueType::Object, Ok(key)) => {
+            let child = match json.get_key(key) {
+                Some(v) => v as *const M::V as *const c_void,
+                None => null(),
+            };
+            unsafe { *value = child };
+            Status::Ok as c_int
+        }
+        _ => Status::Err as c_int,
+    }
+}
+
 pub fn json_api_get_len<M: Manager>(_: M, json: *const c_void, count: *mut libc::size_t) -> c_int {
     let json = unsafe { &*(json as *const M::V) };
     let len = match json.get_type() {
@@ -227,6 +248,11 @@ pub fn json_api_next<M: Manager>(_: M, iter: *mut c_void) -> *const c_void {
     }
 }
 
+pub fn json_api_reset_iter<M: Manager>(_: M, iter: *mut c_void) {
+    let iter = unsafe { &mut *(iter as *mut ResultsIterator<M::V>) };
+    iter.pos = 0;
+}
+
 pub fn json_api_len<M: Manager>(_: M, iter: *const c_void) -> size_t {
     let iter = unsafe { &*(iter as *mut ResultsIterator<M::V>) };
     iter.results.len() as size_t
@@ -345,6 +371,22 @@ macro_rules! redis_json_module_export_shared_api {
             }
         }
 
+        #[no_mangle]
+        pub extern "C" fn JSONAPI_resetIter(iter: *mut c_void) {
+            $pre_command_function_expr(&get_llapi_ctx(), &Vec::new());
+
+            let m = $get_manager_expr;
+            match m {
+                Some(mngr) => json_api_reset_iter(mngr, iter),
+                None => json_api_reset_iter(
+                    manager::RedisJsonKeyManager {
+                        phantom: PhantomData,
+                    },
+                    iter,
+                ),
+            }
+        }
+
         #[no_mangle]
         pub extern "C" fn JSONAPI_len(iter: *const c_void) -> size_t {
             $pre_command_function_expr(&get_llapi_ctx(), &Vec::new());
@@ -538,6 +580,28 @@ macro_rules! redis_json_module_export_shared_api {
             }
         }
 
+        #[no_mangle]
+        pub extern "C" fn JSONAPI_getObjectValue(
+            json: *const c_void,
+            key: *const c_char,
+            value: *mut *const c_void,
+        ) -> c_int {
+            $pre_command_function_expr(&get_llapi_ctx(), &Vec::new());
+
+            let m = $get_manager_expr;
+            match m {
+                Some(mngr) => json_api_get_object_value(mngr, json, key, value),
+                None => json_api_get_object_value(
+                    manager::RedisJsonKeyManager {
+                        phantom: PhantomData,
+                    },
+                    json,
+                    key,
+                    value,
+                ),
+            }
+        }
+
         static REDISJSON_GETAPI: &str = concat!("RedisJSON_V1", "\0");
 
         pub fn export_shared_api(ctx: &Context) {
@@ -569,6 +633,8 @@ macro_rules! redis_json_module_export_shared_api {
             getString: JSONAPI_getString,
             getJSON: JSONAPI_getJSON,
             isJSON: JSONAPI_isJSON,
+            resetIter: JSONAPI_resetIter,
+            getObjectValue: JSONAPI_getObjectValue,
         };
 
         #[repr(C)]
@@ -602,6 +668,76 @@ macro_rules! redis_json_module_export_shared_api {
                 str: *mut *mut rawmod::RedisModuleString,
             ) -> c_int,
             pub isJSON: extern "C" fn(key: *mut rawmod::RedisModuleKey) -> c_int,
+            pub resetIter: extern "C" fn(iter: *mut c_void),
+            pub getObjectValue: extern "C" fn(
+                json: *const c_void,
+                key: *const c_char,
+                value: *mut *const c_void,
+            ) -> c_int,
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manager::RedisJsonKeyManager;
+    use serde_json::json;
+    use std::marker::PhantomData;
+
+    fn manager<'a>() -> RedisJsonKeyManager<'a> {
+        RedisJsonKeyManager {
+            phantom: PhantomData,
+        }
+    }
+
+    fn drain(iter: *mut c_void) -> Vec<Value> {
+        let mut res = Vec::new();
+        loop {
+            let v = json_api_next(manager(), iter);
+            if v.is_null() {
+                return res;
+            }
+            res.push(unsafe { &*(v as *const Value) }.clone());
+        }
+    }
+
+    #[test]
+    fn test_reset_iter() {
+        let doc = json!({"a": [1, "b", {"c": null}]});
+        let path = CString::new("$.a[*]").unwrap();
+        let iter = json_api_get(
+            manager(),
+            &doc as *const Value as *const c_void,
+            path.as_ptr(),
+        ) as *mut c_void;
+
+        let first = drain(iter);
+        assert_eq!(first, vec![json!(1), json!("b"), json!({"c": null})]);
+        json_api_reset_iter(manager(), iter);
+        assert_eq!(drain(iter), first);
+
+        json_api_free_iter(manager(), iter);
+    }
+
+    #[test]
+    fn test_get_object_value() {
+        let doc = json!({"a": {"b": 1}, "c": [2]});
+        let get = |json: &Value, key: &str| {
+            let key = CString::new(key).unwrap();
+            let mut value = null();
+            let status = json_api_get_object_value(
+                manager(),
+                json as *const Value as *const c_void,
+                key.as_ptr(),
+                &mut value,
+            );
+            (status, unsafe { (value as *const Value).as_ref() }.cloned())
+        };
+
+        assert_eq!(get(&doc, "a"), (Status::Ok as c_int, Some(json!({"b": 1}))));
+        assert_eq!(get(&doc["a"], "b"), (Status::Ok as c_int, Some(json!(1))));
+        assert_eq!(get(&doc, "b"), (Status::Ok as c_int, None));
+        assert_eq!(get(&doc["c"], "0").0, Status::Err as c_int);
+    }
+}
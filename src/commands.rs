@@ -1,22 +1,42 @@
 use crate::formatter::RedisJsonFormatter;
-use crate::manager::{AddUpdateInfo, Manager, ReadHolder, SetUpdateInfo, UpdateInfo, WriteHolder};
-use crate::redisjson::{Format, Path};
+use crate::manager::{
+    check_append_only, AddUpdateInfo, ExtendUpdateInfo, Manager, ReadHolder, SetUpdateInfo,
+    UpdateInfo, WriteHolder,
+};
+use crate::redisjson::{Format, Path, RedisJSON};
 use jsonpath_lib::select::select_value::{SelectValue, SelectValueType};
-use redis_module::{Context, RedisValue};
+use redis_module::{raw, Context, RedisValue};
 use redis_module::{NextArg, RedisError, RedisResult, RedisString, REDIS_OK};
 
 use jsonpath_lib::select::Selector;
 
 use crate::nodevisitor::{StaticPathElement, StaticPathParser, VisitStatus};
 
+use crate::config;
+use crate::config::MissingKeyBehavior;
 use crate::error::Error;
+use crate::patch;
 
 use crate::redisjson::SetOptions;
+use crate::transform::{
+    is_valid_schema, IterativeSerialize, PathValue, TransformOptions, TransformValue,
+};
 
 use serde_json::{Map, Value};
 
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use regex::Regex;
 use serde::Serialize;
+use serde_json::ser::CompactFormatter;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::ffi::CString;
+use std::io;
+use std::io::Write;
+use std::os::raw::c_int;
+use std::slice;
+use std::time::Duration;
 
 const JSON_ROOT_PATH: &str = "$";
 const CMD_ARG_NOESCAPE: &str = "NOESCAPE";
@@ -24,6 +44,54 @@ const CMD_ARG_INDENT: &str = "INDENT";
 const CMD_ARG_NEWLINE: &str = "NEWLINE";
 const CMD_ARG_SPACE: &str = "SPACE";
 const CMD_ARG_FORMAT: &str = "FORMAT";
+const CMD_ARG_PRETTY: &str = "PRETTY";
+const CMD_ARG_STRINGNUMS: &str = "STRINGNUMS";
+const CMD_ARG_WITHCOUNT: &str = "WITHCOUNT";
+const CMD_ARG_WITHPATHS: &str = "WITHPATHS";
+const CMD_ARG_PATHS: &str = "PATHS";
+const CMD_ARG_FROMKEY: &str = "FROMKEY";
+const CMD_ARG_COMPRESS: &str = "COMPRESS";
+const CMD_ARG_IDEMPOTENT: &str = "IDEMPOTENT";
+const CMD_ARG_TYPED: &str = "TYPED";
+const CMD_ARG_WHERE: &str = "WHERE";
+const CMD_ARG_UPSERT: &str = "UPSERT";
+const CMD_ARG_KEEP: &str = "KEEP";
+const CMD_ARG_VERBOSE: &str = "VERBOSE";
+const CMD_ARG_INDEXOF: &str = "INDEXOF";
+const CMD_ARG_BASE: &str = "BASE";
+const CMD_ARG_THEN: &str = "THEN";
+const CMD_ARG_WRAP: &str = "WRAP";
+const CMD_ARG_SKIPNULL: &str = "SKIPNULL";
+const CMD_ARG_MATCHLIMIT: &str = "MATCHLIMIT";
+const CMD_ARG_ONLYKEYS: &str = "ONLYKEYS";
+const CMD_ARG_COERCENUM: &str = "COERCENUM";
+const CMD_ARG_NDJSON: &str = "NDJSON";
+const CMD_ARG_CASEINSENSITIVE: &str = "CASEINSENSITIVE";
+const CMD_ARG_SIZE: &str = "SIZE";
+const CMD_ARG_FLATMAP: &str = "FLATMAP";
+const CMD_ARG_WITHINDICES: &str = "WITHINDICES";
+const CMD_ARG_ETAG: &str = "ETAG";
+const CMD_ARG_EXCLUDE: &str = "EXCLUDE";
+const CMD_ARG_POINTER: &str = "POINTER";
+const CMD_ARG_COERCESCHEMA: &str = "COERCESCHEMA";
+const CMD_ARG_BY: &str = "BY";
+const CMD_ARG_DEFAULT: &str = "DEFAULT";
+const CMD_ARG_WITHVALUE: &str = "WITHVALUE";
+const CMD_ARG_STRICT: &str = "STRICT";
+const CMD_ARG_WITHVERSION: &str = "WITHVERSION";
+const CMD_ARG_SIMPLETYPES: &str = "SIMPLETYPES";
+const CMD_ARG_STATS: &str = "STATS";
+const CMD_ARG_MAXLEN: &str = "MAXLEN";
+const CMD_ARG_REDACT: &str = "REDACT";
+const CMD_ARG_CSV: &str = "CSV";
+const COMPRESS_GZIP: &str = "GZIP";
+
+// 64-bit FNV-1a, a hash that never changes between versions or platforms
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// The longest array JSON.SET EXTEND pads to when json-max-array-len is unlimited
+const MAX_EXTEND_LEN: usize = 1 << 20;
 
 // Compile time evaluation of the max len() of all elements of the array
 const fn max_strlen(arr: &[&str]) -> usize {
@@ -44,7 +112,7 @@ const fn max_strlen(arr: &[&str]) -> usize {
 }
 
 // We use this constant to further optimize json_get command, by calculating the max subcommand length
-// Any subcommand added to JSON.GET should be included on the following array
+// Any subcommand JSON.GET reads outside JSONPath mode should be included on the following array
 const JSONGET_SUBCOMMANDS_MAXSTRLEN: usize = max_strlen(&[
     CMD_ARG_NOESCAPE,
     CMD_ARG_INDENT,
@@ -53,6 +121,136 @@ const JSONGET_SUBCOMMANDS_MAXSTRLEN: usize = max_strlen(&[
     CMD_ARG_FORMAT,
 ]);
 
+// The JSON.GET subcommands followed by a value, the only ones outside JSONPath mode
+const JSONGET_LEGACY_VALUE_SUBCOMMANDS: [&str; 4] = [
+    CMD_ARG_INDENT,
+    CMD_ARG_NEWLINE,
+    CMD_ARG_SPACE,
+    CMD_ARG_FORMAT,
+];
+
+///
+/// A serialization sink that fails once more than `limit` bytes are written (0 is unlimited)
+///
+struct LimitedWriter {
+    buf: Vec<u8>,
+    limit: usize,
+    written: usize,
+    // Only count the bytes, e.g. for JSON.GET SIZE
+    discard: bool,
+    // Hash of the bytes, replied instead of them for JSON.GET ETAG
+    etag: Option<u64>,
+}
+
+impl LimitedWriter {
+    fn new(limit: usize) -> Self {
+        LimitedWriter {
+            buf: Vec::new(),
+            limit,
+            written: 0,
+            discard: false,
+            etag: None,
+        }
+    }
+
+    ///
+    /// The sink of a JSON.GET reply, counting its bytes only for SIZE or hashing them only for
+    /// ETAG (which send nothing, so nothing is limited either)
+    ///
+    fn for_reply(options: &TransformOptions) -> Self {
+        if options.size_only || options.etag {
+            LimitedWriter {
+                discard: true,
+                etag: Some(FNV_OFFSET_BASIS).filter(|_| options.etag),
+                ..LimitedWriter::new(0)
+            }
+        } else {
+            LimitedWriter::new(config::get().max_reply_bytes)
+        }
+    }
+
+    ///
+    /// The reply with the written bytes, or with their count when they are discarded
+    ///
+    fn into_reply(self) -> Result<RedisValue, Error> {
+        if let Some(hash) = self.etag {
+            return Ok(RedisValue::BulkString(format!("{:016x}", hash)));
+        }
+        if self.discard {
+            return Ok(RedisValue::Integer(self.written as i64));
+        }
+        // The serializers only write valid UTF-8, but a broken one fails the command instead of
+        // sending a bad reply
+        String::from_utf8(self.buf)
+            .map(RedisValue::BulkString)
+            .map_err(|e| format!("ERR invalid UTF-8 in serialized reply: {}", e).into())
+    }
+}
+
+impl io::Write for LimitedWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.limit > 0 && self.written + data.len() > self.limit {
+            return Err(io::Error::new(io::ErrorKind::Other, "ERR reply too large"));
+        }
+        if !self.discard {
+            self.buf.extend_from_slice(data);
+        }
+        if let Some(hash) = self.etag.as_mut() {
+            for b in data {
+                *hash = (*hash ^ u64::from(*b)).wrapping_mul(FNV_PRIME);
+            }
+        }
+        self.written += data.len();
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+///
+/// Replaces the serialized JSON strings of a reply with their gzip-compressed bytes
+///
+fn gzip_reply(value: RedisValue) -> Result<RedisValue, Error> {
+    match value {
+        RedisValue::BulkString(s) => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder
+                .write_all(s.as_bytes())
+                .and_then(|_| encoder.finish())
+                .map(RedisValue::StringBuffer)
+                .map_err(|e| format!("ERR failed compressing reply: {}", e).into())
+        }
+        RedisValue::Array(values) => Ok(RedisValue::Array(
+            values
+                .into_iter()
+                .map(gzip_reply)
+                .collect::<Result<_, _>>()?,
+        )),
+        v => Ok(v),
+    }
+}
+
+///
+/// Formats an object key as a normalized JSONPath segment, e.g. `['key']`
+///
+fn key_segment(key: &str) -> String {
+    format!("['{}']", key.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+///
+/// Whether an integer and a double are the same number, without rounding the integer
+///
+fn long_equals_double(l: i64, d: f64) -> bool {
+    d.fract() == 0.0 && d >= i64::MIN as f64 && d < i64::MAX as f64 && d as i64 == l
+}
+
+enum RespFrame<'a, V: SelectValue> {
+    Array(Box<dyn Iterator<Item = &'a V> + 'a>),
+    Object(Box<dyn Iterator<Item = (&'a str, &'a V)> + 'a>),
+}
+
 pub struct KeyValue<'a, V: SelectValue> {
     val: &'a V,
 }
@@ -87,6 +285,10 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
     }
 
     fn get_first<'b>(&'a self, path: &'b str) -> Result<&'a V, Error> {
+        // The root needs no selector run (and no results vector)
+        if path == JSON_ROOT_PATH {
+            return Ok(self.val);
+        }
         let results = self.get_values(path)?;
         match results.first() {
             Some(s) => Ok(s),
@@ -96,7 +298,61 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
 
     fn resp_serialize(&'a self, path: &'a str) -> RedisResult {
         let v = self.get_first(path)?;
-        Ok(self.resp_serialize_inner(v))
+        if config::get().iterative_serializer {
+            Ok(self.resp_serialize_iterative(v))
+        } else {
+            Ok(self.resp_serialize_inner(v))
+        }
+    }
+
+    ///
+    /// Builds the same reply as `resp_serialize_inner` with an explicit stack instead of recursion
+    ///
+    fn resp_serialize_iterative(&'a self, v: &'a V) -> RedisValue {
+        // The containers being built, with their remaining values
+        let mut stack: Vec<(RespFrame<'a, V>, Vec<RedisValue>)> = Vec::new();
+        let mut next = v;
+        loop {
+            let mut done = match next.get_type() {
+                SelectValueType::Array => {
+                    let mut res = Vec::with_capacity(next.len().unwrap() + 1);
+                    res.push(RedisValue::SimpleStringStatic("["));
+                    stack.push((RespFrame::Array(next.values().unwrap()), res));
+                    None
+                }
+                SelectValueType::Object => {
+                    let mut res = Vec::with_capacity(2 * next.len().unwrap() + 1);
+                    res.push(RedisValue::SimpleStringStatic("{"));
+                    stack.push((RespFrame::Object(next.items().unwrap()), res));
+                    None
+                }
+                _ => Some(self.resp_serialize_inner(next)),
+            };
+            // Adds the finished value to its container, finishing the containers with no more values
+            loop {
+                let (frame, res) = match stack.last_mut() {
+                    Some(top) => top,
+                    None => return done.unwrap(),
+                };
+                if let Some(v) = done.take() {
+                    res.push(v);
+                }
+                let value = match frame {
+                    RespFrame::Array(values) => values.next(),
+                    RespFrame::Object(items) => items.next().map(|(k, v)| {
+                        res.push(RedisValue::BulkString(k.to_string()));
+                        v
+                    }),
+                };
+                match value {
+                    Some(v) => {
+                        next = v;
+                        break;
+                    }
+                    None => done = stack.pop().map(|(_, res)| RedisValue::Array(res)),
+                }
+            }
+        }
     }
 
     fn resp_serialize_inner(&'a self, v: &V) -> RedisValue {
@@ -146,18 +402,24 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
         Ok(results)
     }
 
-    fn serialize_object<O: Serialize>(
+    fn serialize_object<O: Serialize + IterativeSerialize>(
         &'a self,
         o: &O,
         indent: Option<String>,
         newline: Option<String>,
         space: Option<String>,
-    ) -> String {
-        let formatter = RedisJsonFormatter::new(indent, space, newline);
+        options: &TransformOptions,
+    ) -> Result<RedisValue, Error> {
+        let mut formatter = RedisJsonFormatter::new(indent, space, newline);
 
-        let mut out = serde_json::Serializer::with_formatter(Vec::new(), formatter);
-        o.serialize(&mut out).unwrap();
-        String::from_utf8(out.into_inner()).unwrap()
+        let mut writer = LimitedWriter::for_reply(options);
+        if config::get().iterative_serializer {
+            o.write_iterative(&mut writer, &mut formatter)?;
+            return writer.into_reply();
+        }
+        let mut out = serde_json::Serializer::with_formatter(writer, formatter);
+        o.serialize(&mut out)?;
+        out.into_inner().into_reply()
     }
 
     fn to_json(
@@ -167,6 +429,7 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
         newline: Option<String>,
         space: Option<String>,
         format: Format,
+        options: &TransformOptions,
     ) -> Result<RedisValue, Error> {
         if format == Format::BSON {
             return Err("Soon to come...".into());
@@ -183,36 +446,236 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
                     return acc;
                 }
                 let value = match selector.select() {
-                    Ok(s) => s.first().map(|v| *v),
+                    Ok(s) => s.first().map(|v| TransformValue::new(*v, options)),
                     Err(_) => None,
                 };
                 acc.insert(path.take_original(), value);
                 acc
             });
-            Ok(self
-                .serialize_object(&temp_doc, indent, newline, space)
-                .into())
+            self.serialize_object(&temp_doc, indent, newline, space, options)
         } else {
             let path = &paths[0];
-            if path.is_legacy() {
-                Ok(self
-                    .serialize_object(
-                        self.get_first(&paths[0].get_path())?,
-                        indent,
-                        newline,
-                        space,
-                    )
-                    .into())
+            if options.csv {
+                let value = if path.is_legacy() {
+                    self.get_first(path.get_path())?
+                } else {
+                    match self.get_values(path.get_path())?.as_slice() {
+                        [value] => *value,
+                        _ => return Err("ERR CSV requires a single array match".into()),
+                    }
+                };
+                return self.serialize_csv(value, options);
+            }
+            if path.is_legacy() && !options.wrap {
+                self.serialize_object(
+                    &TransformValue::new(self.get_first(&paths[0].get_path())?, options),
+                    indent,
+                    newline,
+                    space,
+                    options,
+                )
+            } else {
+                let mut values = self.get_values(path.get_path())?;
+                values.truncate(options.max_matches());
+                if options.ndjson {
+                    return self.serialize_lines(values, options);
+                }
+                self.serialize_values(values, indent, newline, space, options)
+            }
+        }
+    }
+
+    fn to_json_with_count(
+        &'a self,
+        path: &Path,
+        indent: Option<String>,
+        newline: Option<String>,
+        space: Option<String>,
+        format: Format,
+        options: &TransformOptions,
+    ) -> Result<RedisValue, Error> {
+        if format == Format::BSON {
+            return Err("Soon to come...".into());
+        }
+        let mut values = self.get_values(path.get_path())?;
+        // The count is of all the matches, telling whether MATCHLIMIT truncated them
+        let count = values.len();
+        values.truncate(options.max_matches());
+        Ok(RedisValue::Array(vec![
+            count.into(),
+            self.serialize_values(values, indent, newline, space, options)?,
+        ]))
+    }
+
+    fn to_json_with_paths(
+        &'a self,
+        path: &Path,
+        indent: Option<String>,
+        newline: Option<String>,
+        space: Option<String>,
+        format: Format,
+        options: &'a TransformOptions,
+    ) -> Result<RedisValue, Error> {
+        if format == Format::BSON {
+            return Err("Soon to come...".into());
+        }
+        let values = Selector::default()
+            .str_path(path.get_path())?
+            .value(self.val)
+            .select_with_paths(|_| true)?
+            .iter()
+            .take(options.max_matches())
+            .filter_map(|p| self.walk_path(p))
+            .map(|(path, v)| PathValue {
+                path,
+                value: TransformValue::new(v, options),
+            })
+            .collect::<Vec<_>>();
+        self.serialize_object(&values, indent, newline, space, options)
+    }
+
+    fn to_json_rebased(
+        &'a self,
+        rebased: &(Path, Path),
+        indent: Option<String>,
+        newline: Option<String>,
+        space: Option<String>,
+        format: Format,
+        options: &TransformOptions,
+    ) -> Result<RedisValue, Error> {
+        if format == Format::BSON {
+            return Err("Soon to come...".into());
+        }
+        let (base, path) = rebased;
+        // Every node matched by the base is the root of its own query
+        let mut results = self
+            .get_values(base.get_path())?
+            .into_iter()
+            .map(|root| {
+                Ok(Selector::default()
+                    .str_path(path.get_path())?
+                    .value(root)
+                    .select()?
+                    .into_iter()
+                    .take(options.max_matches())
+                    .map(|v| TransformValue::new(v, options))
+                    .collect::<Vec<_>>())
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        if results.len() == 1 {
+            self.serialize_object(&results.pop().unwrap(), indent, newline, space, options)
+        } else {
+            self.serialize_object(&results, indent, newline, space, options)
+        }
+    }
+
+    ///
+    /// Follows the segments of a matched path, returning the value and its normalized JSONPath
+    ///
+    fn walk_path(&self, segments: &[String]) -> Option<(String, &'a V)> {
+        let mut path = JSON_ROOT_PATH.to_string();
+        let mut curr = self.val;
+        for segment in segments {
+            if curr.get_type() == SelectValueType::Array {
+                let index = segment.parse::<usize>().ok()?;
+                path.push_str(&format!("[{}]", index));
+                curr = curr.get_index(index)?;
+            } else {
+                path.push_str(&key_segment(segment));
+                curr = curr.get_key(segment)?;
+            }
+        }
+        Some((path, curr))
+    }
+
+    fn serialize_values(
+        &'a self,
+        values: Vec<&'a V>,
+        indent: Option<String>,
+        newline: Option<String>,
+        space: Option<String>,
+        options: &TransformOptions,
+    ) -> Result<RedisValue, Error> {
+        let values = values
+            .into_iter()
+            .map(|v| TransformValue::new(v, options))
+            .collect::<Vec<_>>();
+        self.serialize_object(&values, indent, newline, space, options)
+    }
+
+    ///
+    /// Resolves the object keys of a static path to the document's keys that equal them ignoring
+    /// case (exact matches first), e.g. `$.User.Name` to `$["user"]["name"]`
+    ///
+    fn resolve_ignore_case(&self, path: &str) -> Result<String, Error> {
+        let parsed = StaticPathParser::check(path)?;
+        if parsed.valid != VisitStatus::Valid {
+            return Err(
+                "ERR CASEINSENSITIVE requires paths of object keys and array indexes only".into(),
+            );
+        }
+        let mut curr = Some(self.val);
+        let mut resolved = String::new();
+        for element in parsed.static_path_elements {
+            let element = match element {
+                StaticPathElement::ObjectKey(key) => {
+                    let found = curr.filter(|v| v.get_key(&key).is_none()).and_then(|v| {
+                        v.keys()?
+                            .find(|k| k.to_lowercase() == key.to_lowercase())
+                            .map(str::to_string)
+                    });
+                    let key = found.unwrap_or(key);
+                    curr = curr.and_then(|v| v.get_key(&key));
+                    StaticPathElement::ObjectKey(key)
+                }
+                StaticPathElement::ArrayIndex(index) => {
+                    curr = curr.and_then(|v| v.get_index(index as usize));
+                    StaticPathElement::ArrayIndex(index)
+                }
+                StaticPathElement::Root => StaticPathElement::Root,
+            };
+            resolved.push_str(&element.to_string());
+        }
+        Ok(resolved)
+    }
+
+    ///
+    /// Serializes every value as compact JSON terminated by a newline (NDJSON)
+    ///
+    fn serialize_lines(
+        &'a self,
+        values: Vec<&'a V>,
+        options: &TransformOptions,
+    ) -> Result<RedisValue, Error> {
+        let mut writer = LimitedWriter::for_reply(options);
+        for v in values {
+            let value = TransformValue::new(v, options);
+            if config::get().iterative_serializer {
+                value.write_iterative(&mut writer, &mut CompactFormatter)?;
             } else {
-                let values = self.get_values(path.get_path())?;
-                Ok(self
-                    .serialize_object(&values, indent, newline, space)
-                    .into())
+                value.serialize(&mut serde_json::Serializer::new(&mut writer))?;
             }
+            writer
+                .write_all(b"\n")
+                .map_err(|e| Error::from(e.to_string()))?;
         }
+        writer.into_reply()
+    }
+
+    fn serialize_csv(
+        &'a self,
+        value: &'a V,
+        options: &TransformOptions,
+    ) -> Result<RedisValue, Error> {
+        let csv = TransformValue::new(value, options).to_csv()?;
+        let mut writer = LimitedWriter::for_reply(options);
+        writer
+            .write_all(csv.as_bytes())
+            .map_err(|e| Error::from(e.to_string()))?;
+        writer.into_reply()
     }
 
-    fn find_add_paths(&mut self, path: &str) -> Result<Vec<UpdateInfo>, Error> {
+    fn find_add_paths(&mut self, path: &str, extend: bool) -> Result<Vec<UpdateInfo>, Error> {
         let mut parsed_static_path = StaticPathParser::check(path)?;
 
         if parsed_static_path.valid != VisitStatus::Valid {
@@ -225,52 +688,108 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
         let last = parsed_static_path.static_path_elements.pop().unwrap();
 
         if let StaticPathElement::ObjectKey(key) = last {
-            if let StaticPathElement::Root = parsed_static_path.static_path_elements.last().unwrap()
-            {
-                // Adding to the root
-                Ok(vec![UpdateInfo::AUI(AddUpdateInfo {
-                    path: Vec::new(),
-                    key,
-                })])
-            } else {
-                // Adding somewhere in existing object, use jsonpath_lib::replace_with
-                let mut selector = Selector::default();
-                if let Err(e) = selector.str_path(
-                    &parsed_static_path
-                        .static_path_elements
-                        .iter()
-                        .map(|e| e.to_string())
-                        .collect::<Vec<String>>()
-                        .join(""),
-                ) {
-                    return Err(e.into());
-                }
-                selector.value(self.val);
-                let mut res = selector.select_with_paths(|_| true)?;
-                Ok(res
-                    .drain(..)
-                    .map(|v| {
-                        UpdateInfo::AUI(AddUpdateInfo {
-                            path: v,
-                            key: key.to_string(),
-                        })
+            Ok(self
+                .find_parent_paths(&parsed_static_path.static_path_elements, |_| true)?
+                .drain(..)
+                .map(|v| {
+                    UpdateInfo::AUI(AddUpdateInfo {
+                        path: v,
+                        key: key.to_string(),
+                    })
+                })
+                .collect())
+        } else if let StaticPathElement::ArrayIndex(index) = last {
+            // Existing elements are only reached here with NX, which leaves them as they are
+            let (in_range, out_of_range): (Vec<_>, Vec<_>) = self
+                .find_parent_paths(&parsed_static_path.static_path_elements, |v| {
+                    v.get_type() == SelectValueType::Array
+                })?
+                .into_iter()
+                .partition(|p| {
+                    self.walk_path(p).map_or(false, |(_, v)| {
+                        let len = v.len().unwrap_or(0) as f64;
+                        index.fract() == 0.0 && index >= -len && index < len
                     })
-                    .collect())
+                });
+            if out_of_range.is_empty() && !in_range.is_empty() {
+                return Ok(Vec::new());
+            }
+            // A negative index before the start of the array has nothing to extend
+            if !extend || index < 0.0 || index.fract() != 0.0 {
+                return Err("ERR array index out of range".into());
+            }
+            // The client picks the index, so the padding is bounded like any array growth
+            let max_len = match config::get().max_array_len {
+                0 => MAX_EXTEND_LEN,
+                max => max,
+            };
+            if index >= max_len as f64 {
+                return Err("ERR array length exceeds max".into());
             }
-        } else if let StaticPathElement::ArrayIndex(_) = last {
-            // if we reach here with array path we must be out of range
-            // otherwise the path would be valid to be set and we would not
-            // have reached here!!
-            Err("array index out of range".into())
+            Ok(out_of_range
+                .into_iter()
+                .map(|v| {
+                    UpdateInfo::EUI(ExtendUpdateInfo {
+                        path: v,
+                        index: index as usize,
+                    })
+                })
+                .collect())
         } else {
             Err("path not an object or array".into())
         }
     }
 
+    ///
+    /// Fails unless the parent that an update adds the value to is a container of the right type
+    ///
+    fn check_update(&self, update_info: &UpdateInfo) -> Result<(), Error> {
+        let (path, expected) = match update_info {
+            UpdateInfo::SUI(_) => return Ok(()),
+            UpdateInfo::AUI(aui) => (&aui.path, "object"),
+            UpdateInfo::EUI(eui) => (&eui.path, "array"),
+        };
+        match self.walk_path(path) {
+            Some((_, v)) if Self::value_name(v) == expected => Ok(()),
+            Some((_, v)) => Err(Self::wrong_type(v, expected)),
+            None => Err("ERR path does not exist".into()),
+        }
+    }
+
+    fn find_parent_paths<F: FnMut(&V) -> bool>(
+        &self,
+        parent: &[StaticPathElement],
+        mut f: F,
+    ) -> Result<Vec<Vec<String>>, Error> {
+        if let StaticPathElement::Root = parent.last().unwrap() {
+            // Adding to the root
+            if f(self.val) {
+                Ok(vec![Vec::new()])
+            } else {
+                Ok(Vec::new())
+            }
+        } else {
+            // Adding somewhere in existing value, use jsonpath_lib::replace_with
+            let mut selector = Selector::default();
+            if let Err(e) = selector.str_path(
+                &parent
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join(""),
+            ) {
+                return Err(e.into());
+            }
+            selector.value(self.val);
+            Ok(selector.select_with_paths(f)?)
+        }
+    }
+
     pub fn find_paths(
         &mut self,
         path: &str,
         option: &SetOptions,
+        extend: bool,
     ) -> Result<Vec<UpdateInfo>, Error> {
         if SetOptions::NotExists != *option {
             let mut selector = Selector::default();
@@ -286,7 +805,13 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
             }
         }
         if SetOptions::AlreadyExists != *option {
-            self.find_add_paths(path)
+            // Every target is checked here, so that a bad one fails the command before any
+            // of the updates is applied
+            let res = self.find_add_paths(path, extend)?;
+            for update_info in &res {
+                self.check_update(update_info)?;
+            }
+            Ok(res)
         } else {
             Ok(Vec::new()) // empty vector means no updates
         }
@@ -294,6 +819,12 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
 
     pub fn serialize(results: &V, format: Format) -> Result<String, Error> {
         let res = match format {
+            Format::JSON if config::get().iterative_serializer => {
+                let mut buf = Vec::new();
+                Self::write_json(results, &mut buf)?;
+                // Only valid JSON (and so UTF-8) is written
+                String::from_utf8(buf).map_err(|e| e.to_string())?
+            }
             Format::JSON => serde_json::to_string(results)?,
             Format::BSON => return Err("Soon to come...".into()), //results.into() as Bson,
         };
@@ -305,12 +836,66 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
         Self::serialize(results, format)
     }
 
-    pub fn get_type(&self, path: &str) -> Result<String, Error> {
-        let s = Self::value_name(self.get_first(path)?);
-        Ok(s.to_string())
+    ///
+    /// Like `to_string` for JSON, but serializes into `buf` (cleared first) so a single buffer
+    /// can be reused across many values, leaving only the exact-size copy to allocate
+    ///
+    pub fn to_string_with_buffer(&self, path: &str, buf: &mut Vec<u8>) -> Result<String, Error> {
+        buf.clear();
+        Self::write_json(self.get_first(path)?, &mut *buf)?;
+        std::str::from_utf8(buf)
+            .map(str::to_string)
+            .map_err(|e| format!("ERR invalid UTF-8 in serialized reply: {}", e).into())
+    }
+
+    ///
+    /// Writes a value as compact JSON, without recursing into it when so configured
+    ///
+    fn write_json<W: io::Write>(value: &V, mut writer: W) -> Result<(), Error> {
+        if config::get().iterative_serializer {
+            TransformValue::new(value, &TransformOptions::default())
+                .write_iterative(&mut writer, &mut CompactFormatter)?;
+        } else {
+            serde_json::to_writer(writer, value)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_type(&self, path: &str, simple: bool) -> Result<&'static str, Error> {
+        Ok(Self::type_name(self.get_first(path)?, simple))
+    }
+
+    ///
+    /// The type of a legacy path's value, or the array of the types of a JSONPath's matches
+    ///
+    pub fn type_reply(&self, path: &Path, simple: bool) -> RedisValue {
+        if path.is_legacy() {
+            return self
+                .get_type(path.get_path(), simple)
+                .map_or(RedisValue::Null, RedisValue::SimpleStringStatic);
+        }
+        match self.get_values(path.get_path()) {
+            Ok(values) => RedisValue::Array(
+                values
+                    .into_iter()
+                    .map(|v| RedisValue::SimpleStringStatic(Self::type_name(v, simple)))
+                    .collect(),
+            ),
+            Err(_) => RedisValue::Null,
+        }
+    }
+
+    ///
+    /// The type JSON.TYPE reports, "number" for integers too when `simple`
+    ///
+    fn type_name(value: &V, simple: bool) -> &'static str {
+        match value.get_type() {
+            SelectValueType::Long if simple => "number",
+            _ => Self::value_name(value),
+        }
     }
 
-    pub fn value_name(value: &V) -> &str {
+    pub fn value_name(value: &V) -> &'static str {
         match value.get_type() {
             SelectValueType::Null => "null",
             SelectValueType::Bool => "boolean",
@@ -322,11 +907,19 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
         }
     }
 
+    fn wrong_type(value: &V, expected: &str) -> Error {
+        Error::from(format!(
+            "ERR path value is {}, expected {}",
+            Self::value_name(value),
+            expected
+        ))
+    }
+
     pub fn str_len(&self, path: &str) -> Result<usize, Error> {
         let first = self.get_first(path)?;
         match first.get_type() {
             SelectValueType::String => Ok(first.get_str().len()),
-            _ => Err("ERR wrong type of path value".into()),
+            _ => Err(Self::wrong_type(first, "string")),
         }
     }
 
@@ -334,15 +927,31 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
         let first = self.get_first(path)?;
         match first.get_type() {
             SelectValueType::Array => Ok(first.len().unwrap()),
-            _ => Err("ERR wrong type of path value".into()),
+            _ => Err(Self::wrong_type(first, "array")),
         }
     }
 
-    pub fn obj_len(&self, path: &str) -> Result<usize, Error> {
+    pub fn obj_len(&self, path: &str, deep: bool) -> Result<usize, Error> {
         let first = self.get_first(path)?;
         match first.get_type() {
+            SelectValueType::Object if deep => Ok(Self::deep_key_count(first)),
             SelectValueType::Object => Ok(first.len().unwrap()),
-            _ => Err("ERR wrong type of path value".into()),
+            _ => Err(Self::wrong_type(first, "object")),
+        }
+    }
+
+    ///
+    /// Counts the keys of a value's objects at every nesting level, including objects in arrays
+    ///
+    fn deep_key_count(value: &V) -> usize {
+        match value.get_type() {
+            SelectValueType::Object => value
+                .items()
+                .unwrap()
+                .map(|(_, v)| 1 + Self::deep_key_count(v))
+                .sum(),
+            SelectValueType::Array => value.values().unwrap().map(Self::deep_key_count).sum(),
+            _ => 0,
         }
     }
 
@@ -352,6 +961,13 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
             (SelectValueType::Bool, SelectValueType::Bool) => a.get_bool() == b.get_bool(),
             (SelectValueType::Long, SelectValueType::Long) => a.get_long() == b.get_long(),
             (SelectValueType::Double, SelectValueType::Double) => a.get_double() == b.get_double(),
+            // Numbers are equal by value, e.g. 1 and 1.0
+            (SelectValueType::Long, SelectValueType::Double) => {
+                long_equals_double(a.get_long(), b.get_double())
+            }
+            (SelectValueType::Double, SelectValueType::Long) => {
+                long_equals_double(b.get_long(), a.get_double())
+            }
             (SelectValueType::String, SelectValueType::String) => a.get_str() == b.get_str(),
             (SelectValueType::Array, SelectValueType::Array) => {
                 if a.len().unwrap() != b.len().unwrap() {
@@ -388,20 +1004,29 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
         }
     }
 
+    ///
+    /// Searches for `needle` in the array at `path`, either a JSON value to compare elements
+    /// with or, with `predicate`, a JSONPath filter expression the element must satisfy
+    ///
     pub fn arr_index(
         &self,
         path: &str,
-        scalar_json: &str,
+        needle: &str,
+        predicate: bool,
         start: i64,
         end: i64,
     ) -> Result<i64, Error> {
         let res = self.get_first(path)?;
         if res.get_type() == SelectValueType::Array {
-            // end=-1/0 means INFINITY to support backward with RedisJSON
-            if res.len().unwrap() == 0 || end < -1 {
+            if res.len().unwrap() == 0 {
                 return Ok(-1);
             }
-            let v: Value = serde_json::from_str(scalar_json)?;
+            // The needle is any JSON value, arrays and objects being compared member by member
+            let v: Option<Value> = if predicate {
+                None
+            } else {
+                Some(RedisJSON::parse_str(needle, Format::JSON)?)
+            };
 
             let len = res.len().unwrap() as i64;
 
@@ -413,7 +1038,8 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
                 start.min(len - 1)
             };
 
-            // Normalize end
+            // Normalize end: 0 means the end of the array (to support backward with RedisJSON),
+            // and like start, a negative end counts from the end of the array
             let end = match end {
                 0 => len,
                 e if e < 0 => len + end,
@@ -424,6 +1050,10 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
                 // don't search at all
                 return Ok(-1);
             }
+            let v = match v {
+                Some(v) => v,
+                None => return Self::arr_index_predicate(res, needle, start, end),
+            };
             let mut i = -1;
             for index in start..end {
                 if self.is_eqaul(res.get_index(index as usize).unwrap(), &v) {
@@ -438,11 +1068,108 @@ impl<'a, V: SelectValue> KeyValue<'a, V> {
         }
     }
 
+    fn arr_index_predicate(arr: &V, filter: &str, start: i64, end: i64) -> Result<i64, Error> {
+        Ok(Self::filter_indexes(arr, filter)?
+            .into_iter()
+            .map(|i| i as i64)
+            .filter(|i| (start..end).contains(i))
+            .min()
+            .unwrap_or(-1))
+    }
+
+    ///
+    /// The sorted indexes of the array elements satisfying a filter, in which `@` is the element
+    ///
+    fn filter_indexes(arr: &V, filter: &str) -> Result<Vec<usize>, Error> {
+        // The filter runs once over the whole array, its matches are element indexes
+        let matches = Selector::default()
+            .str_path(&format!("$[?({})]", filter))?
+            .value(arr)
+            .select_with_paths(|_| true)?;
+        let mut indexes = matches
+            .iter()
+            .filter_map(|p| p.last()?.parse::<usize>().ok())
+            .collect::<Vec<_>>();
+        indexes.sort_unstable();
+        Ok(indexes)
+    }
+
     pub fn obj_keys(&self, path: &str) -> Result<Box<dyn Iterator<Item = &'_ str> + '_>, Error> {
-        self.get_first(path)?
+        let first = self.get_first(path)?;
+        first
             .keys()
-            .ok_or_else(|| "ERR wrong type of path value".into())
+            .ok_or_else(|| Self::wrong_type(first, "object"))
+    }
+}
+
+///
+/// A path argument as a String. Unlike `next_string`, which replaces the bytes that aren't valid
+/// UTF-8 (so the path could match keys holding the replacement character), this fails on them:
+/// object keys are always valid UTF-8, and the selector only takes UTF-8 paths
+///
+fn path_string(arg: &RedisString) -> Result<String, RedisError> {
+    arg.try_as_str()
+        .map(str::to_string)
+        .map_err(|_| RedisError::Str("ERR path is not valid UTF-8"))
+}
+
+///
+/// The reply of JSON.GET for `doc`, with the formatting given as its indent, newline, space and format
+///
+fn json_get_reply<'a, V: SelectValue>(
+    doc: &'a KeyValue<'a, V>,
+    paths: &mut Vec<Path>,
+    rebased: &Option<(Path, Path)>,
+    with_paths: bool,
+    with_count: bool,
+    formatting: (Option<String>, Option<String>, Option<String>, Format),
+    options: &'a TransformOptions,
+) -> Result<RedisValue, Error> {
+    let (indent, newline, space, format) = formatting;
+    match rebased {
+        Some(rebased) => doc.to_json_rebased(rebased, indent, newline, space, format, options),
+        None if with_paths => {
+            doc.to_json_with_paths(&paths[0], indent, newline, space, format, options)
+        }
+        None if with_count => {
+            doc.to_json_with_count(&paths[0], indent, newline, space, format, options)
+        }
+        None => doc.to_json(paths, indent, newline, space, format, options),
+    }
+}
+
+///
+/// Whether JSON.GET reads its arguments in JSONPath mode, where a path is a JSONPath or a JSON
+/// Pointer is given. Only then are the subcommands added after `FORMAT` recognized, outside it
+/// such bare words are legacy paths as they always were
+///
+fn is_jsonpath_get(args: &[RedisString]) -> bool {
+    let args: Vec<&str> = args
+        .iter()
+        .map(|arg| arg.try_as_str().unwrap_or_default())
+        .collect();
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i];
+        if arg.starts_with('$') {
+            return true;
+        }
+        if arg.eq_ignore_ascii_case(CMD_ARG_POINTER)
+            && args
+                .get(i + 1)
+                .map_or(false, |p| p.is_empty() || p.starts_with('/'))
+        {
+            return true;
+        }
+        if JSONGET_LEGACY_VALUE_SUBCOMMANDS
+            .iter()
+            .any(|cmd| arg.eq_ignore_ascii_case(cmd))
+        {
+            i += 1;
+        }
+        i += 1;
     }
+    false
 }
 
 pub fn command_json_get<M: Manager>(
@@ -450,6 +1177,7 @@ pub fn command_json_get<M: Manager>(
     ctx: &Context,
     args: Vec<RedisString>,
 ) -> RedisResult {
+    let jsonpath = args.get(2..).map_or(false, is_jsonpath_get);
     let mut args = args.into_iter().skip(1);
     let key = args.next_arg()?;
 
@@ -459,50 +1187,432 @@ pub fn command_json_get<M: Manager>(
     let mut indent = None;
     let mut space = None;
     let mut newline = None;
-    while let Ok(arg) = args.next_string() {
-        match arg {
+    let mut pretty = false;
+    let mut options = TransformOptions::default();
+    let mut with_count = false;
+    let mut with_paths = false;
+    let mut gzip = false;
+    let mut base = None;
+    let mut then = None;
+    let mut case_insensitive = false;
+    let mut excludes = Vec::new();
+    let mut pointer = None;
+    let mut with_version = false;
+    while let Some(arg) = args.next() {
+        match path_string(&arg)? {
+            arg if arg.starts_with('$') => paths.push(Path::new(arg)),
             // fast way to consider arg a path by using the max length of all possible subcommands
             // See #390 for the comparison of this function with/without this optimization
-            arg if arg.len() > JSONGET_SUBCOMMANDS_MAXSTRLEN => paths.push(Path::new(arg)),
+            arg if !jsonpath && arg.len() > JSONGET_SUBCOMMANDS_MAXSTRLEN => {
+                paths.push(Path::new(arg))
+            }
             arg if arg.eq_ignore_ascii_case(CMD_ARG_INDENT) => indent = Some(args.next_string()?),
             arg if arg.eq_ignore_ascii_case(CMD_ARG_NEWLINE) => newline = Some(args.next_string()?),
             arg if arg.eq_ignore_ascii_case(CMD_ARG_SPACE) => space = Some(args.next_string()?),
-            // Silently ignore. Compatibility with ReJSON v1.0 which has this option. See #168 TODO add support
+            // Compatibility with ReJSON v1.0, whose NOESCAPE kept non-ASCII characters unescaped,
+            // as every reply does
             arg if arg.eq_ignore_ascii_case(CMD_ARG_NOESCAPE) => continue,
             arg if arg.eq_ignore_ascii_case(CMD_ARG_FORMAT) => {
                 format = Format::from_str(args.next_string()?.as_str())?
             }
+            arg if !jsonpath => paths.push(Path::new(arg)),
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_PRETTY) => pretty = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_STRINGNUMS) => options.string_nums = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_WITHCOUNT) => with_count = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_WITHPATHS) => with_paths = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_WRAP) => options.wrap = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_SKIPNULL) => options.skip_null = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_ONLYKEYS) => options.only_keys = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_COERCENUM) => options.coerce_nums = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_NDJSON) => options.ndjson = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_SIZE) => options.size_only = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_FLATMAP) => options.flat_map = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_WITHINDICES) => options.with_indices = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_STATS) => options.stats = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_CSV) => options.csv = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_ETAG) => options.etag = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_CASEINSENSITIVE) => case_insensitive = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_WITHVERSION) => with_version = true,
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_MATCHLIMIT) => {
+                options.match_limit = match args.next_i64()? {
+                    n if n > 0 => Some(n as usize),
+                    _ => return Err(RedisError::Str("ERR MATCHLIMIT must be positive")),
+                }
+            }
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_BASE) => {
+                base = Some(Path::new(path_string(&args.next_arg()?)?))
+            }
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_THEN) => {
+                then = Some(Path::new(path_string(&args.next_arg()?)?))
+            }
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_COERCESCHEMA) => {
+                let schema = serde_json::from_str(&args.next_string()?)
+                    .ok()
+                    .filter(is_valid_schema)
+                    .ok_or(RedisError::Str(
+                        "ERR COERCESCHEMA must be an object of member types",
+                    ))?;
+                options.schema = Some(schema);
+            }
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_REDACT) => {
+                let pattern = args.next_string()?;
+                options.redact =
+                    Some(Regex::new(&pattern).map_err(|e| {
+                        RedisError::String(format!("ERR invalid REDACT regex: {}", e))
+                    })?);
+            }
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_POINTER) && pointer.is_none() => {
+                pointer = Some(path_string(&args.next_arg()?)?)
+            }
+            // The number of paths to exclude comes first, so other arguments may follow them
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_EXCLUDE) => {
+                let count = match args.next_i64()? {
+                    n if n > 0 => n,
+                    _ => return Err(RedisError::Str("ERR EXCLUDE count must be positive")),
+                };
+                for _ in 0..count {
+                    excludes.push(backwards_compat_path(path_string(&args.next_arg()?)?));
+                }
+            }
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_COMPRESS) => {
+                let algorithm = args.next_string()?;
+                if !algorithm.eq_ignore_ascii_case(COMPRESS_GZIP) {
+                    return Err(RedisError::String(format!(
+                        "ERR unknown compression '{}', expected GZIP",
+                        algorithm
+                    )));
+                }
+                gzip = true;
+            }
             _ => paths.push(Path::new(arg)),
         };
     }
 
-    // path is optional -> no path found we use root "$"
-    if paths.is_empty() {
-        paths.push(Path::new(".".to_string()));
+    if pretty {
+        if indent.is_some() || newline.is_some() || space.is_some() {
+            return Err(RedisError::Str(
+                "ERR PRETTY cannot be combined with INDENT, NEWLINE or SPACE",
+            ));
+        }
+        indent = Some("  ".to_string());
+        newline = Some("\n".to_string());
+        space = Some(" ".to_string());
     }
+    let config = config::get();
+    let indent = indent.or_else(|| config.default_indent.clone());
+    let newline = newline.or_else(|| config.default_newline.clone());
+    let space = space.or_else(|| config.default_space.clone());
+    // ETAG hashes the compact serialization, whatever the formatting
+    let (indent, newline, space) = if options.etag {
+        (None, None, None)
+    } else {
+        (indent, newline, space)
+    };
 
-    let key = manager.open_key_read(ctx, &key)?;
-    let value = match key.get_value()? {
-        Some(doc) => KeyValue::new(doc).to_json(&mut paths, indent, newline, space, format)?,
-        None => RedisValue::Null,
+    let rebased = match (base, then) {
+        (Some(base), Some(then)) if paths.is_empty() && !with_count && !with_paths => {
+            Some((base, then))
+        }
+        (None, None) => None,
+        _ => return Err(RedisError::Str(
+            "ERR BASE requires THEN and can't be combined with other paths, WITHCOUNT or WITHPATHS",
+        )),
     };
 
-    Ok(value)
-}
+    // The value addressed by a JSON Pointer is replied as the one selected by a legacy path
+    if pointer.is_some()
+        && (!paths.is_empty()
+            || rebased.is_some()
+            || with_count
+            || with_paths
+            || options.ndjson
+            || case_insensitive)
+    {
+        return Err(RedisError::Str(
+            "ERR POINTER can't be combined with other paths, BASE, WITHCOUNT, WITHPATHS, NDJSON or CASEINSENSITIVE",
+        ));
+    }
 
-pub fn command_json_set<M: Manager>(
-    manager: M,
-    ctx: &Context,
-    args: Vec<RedisString>,
-) -> RedisResult {
-    let mut args = args.into_iter().skip(1);
+    // path is optional -> no path found we use root "$"
+    if paths.is_empty() {
+        paths.push(Path::new(".".to_string()));
+    }
 
-    let key = args.next_arg()?;
-    let path = backwards_compat_path(args.next_string()?);
+    if options.wrap && paths.len() > 1 {
+        return Err(RedisError::Str("ERR WRAP requires a single path"));
+    }
+    // The members of a reply to several paths are in no particular order
+    if options.etag && paths.len() > 1 {
+        return Err(RedisError::Str("ERR ETAG requires a single path"));
+    }
+
+    // Legacy paths always select a single value
+    if with_count && (paths.len() > 1 || paths[0].is_legacy()) {
+        return Err(RedisError::Str("ERR WITHCOUNT requires a single JSONPath"));
+    }
+    if with_paths && (paths.len() > 1 || paths[0].is_legacy() || with_count) {
+        return Err(RedisError::Str(
+            "ERR WITHPATHS requires a single JSONPath and can't be combined with WITHCOUNT",
+        ));
+    }
+    if options.ndjson
+        && (paths.len() > 1
+            || paths[0].is_legacy()
+            || with_count
+            || with_paths
+            || rebased.is_some())
+    {
+        return Err(RedisError::Str(
+            "ERR NDJSON requires a single JSONPath and can't be combined with WITHCOUNT, WITHPATHS or BASE",
+        ));
+    }
+    if options.schema.is_some() && (options.only_keys || options.flat_map) {
+        return Err(RedisError::Str(
+            "ERR COERCESCHEMA can't be combined with ONLYKEYS or FLATMAP",
+        ));
+    }
+    if options.flat_map && options.only_keys {
+        return Err(RedisError::Str(
+            "ERR FLATMAP can't be combined with ONLYKEYS",
+        ));
+    }
+    if options.with_indices && (options.only_keys || options.flat_map) {
+        return Err(RedisError::Str(
+            "ERR WITHINDICES can't be combined with ONLYKEYS or FLATMAP",
+        ));
+    }
+    if options.stats
+        && (options.only_keys
+            || options.flat_map
+            || options.with_indices
+            || options.schema.is_some())
+    {
+        return Err(RedisError::Str(
+            "ERR STATS can't be combined with ONLYKEYS, FLATMAP, WITHINDICES or COERCESCHEMA",
+        ));
+    }
+    if options.csv
+        && (paths.len() > 1
+            || with_count
+            || with_paths
+            || rebased.is_some()
+            || options.ndjson
+            || options.only_keys
+            || options.flat_map
+            || options.with_indices
+            || options.stats)
+    {
+        return Err(RedisError::Str(
+            "ERR CSV requires a single path and can't be combined with WITHCOUNT, WITHPATHS, BASE, NDJSON, ONLYKEYS, FLATMAP, WITHINDICES or STATS",
+        ));
+    }
+    if options.size_only && gzip {
+        return Err(RedisError::Str("ERR SIZE can't be combined with COMPRESS"));
+    }
+    if options.etag && (options.size_only || gzip) {
+        return Err(RedisError::Str(
+            "ERR ETAG can't be combined with SIZE or COMPRESS",
+        ));
+    }
+    if case_insensitive && rebased.is_some() {
+        return Err(RedisError::Str(
+            "ERR CASEINSENSITIVE can't be combined with BASE",
+        ));
+    }
+
+    // Only the queries of paths and formatting are cached, identified by how they are evaluated
+    // rather than how they are spelled
+    let query = if config.query_cache
+        && options.is_plain()
+        && pointer.is_none()
+        && rebased.is_none()
+        && excludes.is_empty()
+        && !case_insensitive
+        && !with_count
+        && !with_paths
+    {
+        let mut query = vec![format!("{:?}", (&format, &indent, &newline, &space))];
+        query.extend(paths.iter().map(|path| {
+            let kind = if path.is_legacy() {
+                "legacy"
+            } else {
+                "jsonpath"
+            };
+            // The reply to several paths is keyed on how they are spelled
+            if paths.len() > 1 {
+                format!("{} {}", kind, path.get_original())
+            } else {
+                format!("{} {}", kind, path.get_path())
+            }
+        }));
+        Some(query)
+    } else {
+        None
+    };
+
+    let key = manager.open_key_read(ctx, &key)?;
+    let cached = match &query {
+        Some(query) => key.get_cached(query)?,
+        None => None,
+    };
+    let hit = cached.is_some();
+    let value = match (key.get_value()?, cached) {
+        (Some(_), Some(reply)) => RedisValue::BulkString(reply),
+        (Some(doc), None) => {
+            let doc = KeyValue::new(doc);
+            if case_insensitive {
+                for path in paths.iter_mut() {
+                    let resolved = doc.resolve_ignore_case(path.get_path())?;
+                    path.resolve(resolved);
+                }
+            }
+            if let Some(pointer) = &pointer {
+                let tokens = patch::parse_pointer(pointer)
+                    .map_err(|e| RedisError::String(format!("ERR {}", e.msg)))?;
+                let (resolved, _) = patch::resolve_pointer(doc.val, &tokens)
+                    .and_then(|_| doc.walk_path(&tokens))
+                    .ok_or_else(|| {
+                        RedisError::String(format!("ERR pointer '{}' does not exist", pointer))
+                    })?;
+                paths[0].resolve(resolved);
+            }
+            let formatting = (indent, newline, space, format);
+            if excludes.is_empty() {
+                json_get_reply(
+                    &doc, &mut paths, &rebased, with_paths, with_count, formatting, &options,
+                )?
+            } else {
+                let pruned = exclude_paths(doc.val, &excludes)?;
+                json_get_reply(
+                    &KeyValue::new(&pruned),
+                    &mut paths,
+                    &rebased,
+                    with_paths,
+                    with_count,
+                    formatting,
+                    &options,
+                )?
+            }
+        }
+        (None, _) => RedisValue::Null,
+    };
+
+    if let (Some(query), RedisValue::BulkString(reply), false) = (query, &value, hit) {
+        key.set_cached(query, reply.clone())?;
+    }
+
+    let value = if gzip { gzip_reply(value)? } else { value };
+    // The version of an existing document comes first, so clients can tell whether it changed
+    match value {
+        RedisValue::Null => Ok(value),
+        value if with_version => Ok(RedisValue::Array(vec![
+            RedisValue::Integer(key.get_version()? as i64),
+            value,
+        ])),
+        value => Ok(value),
+    }
+}
+
+///
+/// Whether Redis only asks for the positions of a command's keys, e.g. for `COMMAND GETKEYS` or cluster routing
+///
+fn is_keys_position_request(ctx: &Context) -> bool {
+    unsafe { raw::RedisModule_IsKeysPositionRequest.unwrap()(ctx.ctx) != 0 }
+}
+
+///
+/// Reports the argument at `pos` as a key, while answering a keys position request
+///
+fn key_at_pos(ctx: &Context, pos: usize) {
+    unsafe { raw::RedisModule_KeyAtPos.unwrap()(ctx.ctx, pos as c_int) }
+}
+
+///
+/// The positions of the keys of JSON.SET: the key it sets, and the source key of FROMKEY
+///
+fn json_set_key_positions(args: &[RedisString]) -> Vec<usize> {
+    let mut positions = vec![1];
+    let value = match args.get(2).map(RedisString::try_as_str) {
+        Some(Ok(arg)) if arg.eq_ignore_ascii_case(CMD_ARG_PATHS) => args
+            .iter()
+            .skip(3)
+            .position(|a| {
+                a.try_as_str()
+                    .map_or(false, |a| a.eq_ignore_ascii_case("VALUE"))
+            })
+            .map(|i| i + 4),
+        _ => Some(3),
+    };
+    if let Some(value) = value {
+        let is_fromkey = args.get(value).map_or(false, |a| {
+            a.try_as_str()
+                .map_or(false, |a| a.eq_ignore_ascii_case(CMD_ARG_FROMKEY))
+        });
+        if is_fromkey && value + 1 < args.len() {
+            positions.push(value + 1);
+        }
+    }
+    positions
+}
+
+pub fn command_json_set<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    if is_keys_position_request(ctx) {
+        json_set_key_positions(&args)
+            .into_iter()
+            .for_each(|pos| key_at_pos(ctx, pos));
+        return REDIS_OK;
+    }
+    // Replicated along with the time a TTL expires at
+    let argv: Vec<_> = args.iter().map(|arg| arg.inner).collect();
+    let mut args = args.into_iter().skip(1);
+
+    let key = args.next_arg()?;
+    let first = args.next_string()?;
+    // PATHS <path> [path ...] VALUE sets the same value at several independent paths
+    let mut paths = if first.eq_ignore_ascii_case(CMD_ARG_PATHS) {
+        let mut paths = Vec::new();
+        loop {
+            match args.next_string()? {
+                arg if arg.eq_ignore_ascii_case("VALUE") => break,
+                arg => paths.push(backwards_compat_path(arg)),
+            }
+        }
+        if paths.is_empty() {
+            return Err(RedisError::WrongArity);
+        }
+        paths
+    } else {
+        vec![backwards_compat_path(first.clone())]
+    };
+    // A JSON Pointer (RFC 6901) is told apart by its leading '/', which no path has
+    let pointer = if first.starts_with('/') {
+        Some(
+            patch::parse_pointer(&first)
+                .map_err(|e| RedisError::String(format!("ERR {}", e.msg)))?,
+        )
+    } else {
+        None
+    };
     let value = args.next_string()?;
+    // FROMKEY <key> <path> takes the value from another key instead
+    let source = if value.eq_ignore_ascii_case(CMD_ARG_FROMKEY) {
+        Some((args.next_arg()?, backwards_compat_path(args.next_string()?)))
+    } else {
+        None
+    };
 
     let mut format = Format::JSON;
     let mut set_option = SetOptions::None;
+    let mut merge_array = false;
+    let mut extend = false;
+    let mut expire = None;
+    let mut keep_history = None;
+    let mut mkpath = false;
+    let mut make_append_only = false;
 
     while let Some(s) = args.next() {
         match s.try_as_str()? {
@@ -515,47 +1625,159 @@ pub fn command_json_set<M: Manager>(
             arg if arg.eq_ignore_ascii_case("FORMAT") => {
                 format = Format::from_str(args.next_string()?.as_str())?;
             }
+            arg if arg.eq_ignore_ascii_case("MERGEARRAY") => merge_array = true,
+            arg if arg.eq_ignore_ascii_case("EXTEND") => extend = true,
+            arg if arg.eq_ignore_ascii_case("MKPATH") => mkpath = true,
+            arg if arg.eq_ignore_ascii_case("APPENDONLY") => make_append_only = true,
+            arg if arg.eq_ignore_ascii_case("EX") && expire.is_none() => {
+                expire = Some(Duration::from_secs(parse_expire(args.next_i64()?)?))
+            }
+            arg if arg.eq_ignore_ascii_case("PX") && expire.is_none() => {
+                expire = Some(Duration::from_millis(parse_expire(args.next_i64()?)?))
+            }
+            arg if arg.eq_ignore_ascii_case("KEEPHISTORY") && keep_history.is_none() => {
+                keep_history = match args.next_i64()? {
+                    n if n > 0 => Some(n as usize),
+                    _ => return Err(RedisError::Str("ERR KEEPHISTORY must be positive")),
+                }
+            }
             _ => return Err(RedisError::Str("ERR syntax error")),
         };
     }
 
-    let mut redis_key = manager.open_key_write(ctx, key)?;
-    let current = redis_key.get_value()?;
+    if merge_array && source.is_some() {
+        return Err(RedisError::Str(
+            "ERR FROMKEY can't be combined with MERGEARRAY",
+        ));
+    }
+    // Merging only makes sense for existing arrays, and always adds to them
+    if merge_array && set_option != SetOptions::None {
+        return Err(RedisError::Str(
+            "ERR NX and XX can't be combined with MERGEARRAY",
+        ));
+    }
+    // The source is read before the key is opened for writing, as they may be the same key
+    let val = match &source {
+        Some((src, src_path)) => copy_from_key(&manager, ctx, src, src_path)?,
+        None => manager.from_str(&value, format)?,
+    };
+
+    // Like the TTL, the mode belongs to the key
+    if make_append_only
+        && (pointer.is_some() || paths.len() > 1 || paths[0] != JSON_ROOT_PATH || merge_array)
+    {
+        return Err(RedisError::Str(
+            "ERR APPENDONLY can only be used when setting the root",
+        ));
+    }
+
+    if let Some(tokens) = pointer {
+        if merge_array || extend || expire.is_some() || keep_history.is_some() {
+            return Err(RedisError::Str(
+                "ERR a JSON Pointer can't be combined with MERGEARRAY, EXTEND, EX, PX or KEEPHISTORY",
+            ));
+        }
+        return json_set_pointer(&manager, ctx, key, &tokens, val, &set_option, mkpath);
+    }
+    if mkpath {
+        return Err(RedisError::Str("ERR MKPATH requires a JSON Pointer"));
+    }
+
+    if paths.len() > 1 {
+        if merge_array || expire.is_some() || keep_history.is_some() {
+            return Err(RedisError::Str(
+                "ERR PATHS can't be combined with MERGEARRAY, EX, PX or KEEPHISTORY",
+            ));
+        }
+        return json_set_paths(&manager, ctx, key, &paths, val, &set_option, extend);
+    }
+    let path = paths.pop().unwrap();
 
-    let val = manager.from_str(&value, format)?;
+    // The TTL belongs to the key, so it only goes with a whole new value
+    if expire.is_some() && (path != JSON_ROOT_PATH || merge_array) {
+        return Err(RedisError::Str(
+            "ERR EX and PX can only be used when setting the root",
+        ));
+    }
+
+    let mut redis_key = open_key_write_mutable(&manager, ctx, key)?;
+    let append_only = redis_key.is_append_only()?;
+    // The document as it was is only kept once the change succeeded
+    let history = match keep_history {
+        Some(max) => redis_key.snapshot()?.map(|version| (version, max)),
+        None => None,
+    };
+
+    if merge_array {
+        let items = manager
+            .array_items(val)
+            .ok_or(RedisError::Str("ERR wrong type of value - expected array"))?;
+        let root = redis_key
+            .get_value()?
+            .ok_or_else(RedisError::nonexistent_key)?;
+        let mut non_array = false;
+        let paths = find_paths(&path, root, |v| {
+            non_array |= v.get_type() != SelectValueType::Array;
+            true
+        })?;
+        if paths.is_empty() {
+            return Err(RedisError::String(format!(
+                "Path '{}' does not exist",
+                path
+            )));
+        }
+        if non_array {
+            return Err(RedisError::Str(
+                "ERR wrong type of path value - expected array",
+            ));
+        }
+        for p in paths {
+            redis_key.arr_append(p, items.clone())?;
+        }
+        if let Some((version, max)) = history {
+            redis_key.push_history(version, max)?;
+        }
+        redis_key.apply_changes(ctx, "json.set")?;
+        return REDIS_OK;
+    }
+    let current = redis_key.get_value()?;
 
     match (current, set_option) {
         (Some(ref mut doc), ref op) => {
             if path == JSON_ROOT_PATH {
                 if *op != SetOptions::NotExists {
+                    check_append_only(append_only, &**doc, &[], true)?;
                     redis_key.set_value(Vec::new(), val)?;
+                    if let Some((version, max)) = history {
+                        redis_key.push_history(version, max)?;
+                    }
+                    if let Some(expire) = expire {
+                        redis_key.set_expire(expire, &argv)?;
+                    }
+                    if make_append_only {
+                        redis_key.set_append_only()?;
+                    }
                     redis_key.apply_changes(ctx, "json.set")?;
                     REDIS_OK
                 } else {
                     Ok(RedisValue::Null)
                 }
             } else {
-                let mut update_info = KeyValue::new(*doc).find_paths(&path, op)?;
+                let mut update_info = KeyValue::new(*doc).find_paths(&path, op, extend)?;
+                check_append_only_updates(append_only, &**doc, &update_info)?;
                 if !update_info.is_empty() {
                     let mut res = false;
                     if update_info.len() == 1 {
-                        res = match update_info.pop().unwrap() {
-                            UpdateInfo::SUI(sui) => redis_key.set_value(sui.path, val)?,
-                            UpdateInfo::AUI(aui) => redis_key.dict_add(aui.path, &aui.key, val)?,
-                        }
+                        res = apply_update(&mut redis_key, update_info.pop().unwrap(), val)?;
                     } else {
                         for ui in update_info {
-                            res = match ui {
-                                UpdateInfo::SUI(sui) => {
-                                    redis_key.set_value(sui.path, val.clone())?
-                                }
-                                UpdateInfo::AUI(aui) => {
-                                    redis_key.dict_add(aui.path, &aui.key, val.clone())?
-                                }
-                            }
+                            res = apply_update(&mut redis_key, ui, val.clone())?;
                         }
                     }
                     if res {
+                        if let Some((version, max)) = history {
+                            redis_key.push_history(version, max)?;
+                        }
                         redis_key.apply_changes(ctx, "json.set")?;
                         REDIS_OK
                     } else {
@@ -570,6 +1792,12 @@ pub fn command_json_set<M: Manager>(
         (None, _) => {
             if path == JSON_ROOT_PATH {
                 redis_key.set_value(Vec::new(), val)?;
+                if let Some(expire) = expire {
+                    redis_key.set_expire(expire, &argv)?;
+                }
+                if make_append_only {
+                    redis_key.set_append_only()?;
+                }
                 redis_key.apply_changes(ctx, "json.set")?;
                 REDIS_OK
             } else {
@@ -581,6 +1809,251 @@ pub fn command_json_set<M: Manager>(
     }
 }
 
+///
+/// Fails when one of the updates of JSON.SET would rewrite array elements of an append-only document
+///
+fn check_append_only_updates<V: SelectValue>(
+    append_only: bool,
+    root: &V,
+    updates: &[UpdateInfo],
+) -> Result<(), RedisError> {
+    for update in updates {
+        match update {
+            UpdateInfo::SUI(sui) => check_append_only(append_only, root, &sui.path, true)?,
+            UpdateInfo::AUI(aui) => check_append_only(append_only, root, &aui.path, false)?,
+            // Extending an array adds elements after the existing ones
+            UpdateInfo::EUI(eui) => check_append_only(append_only, root, &eui.path, false)?,
+        }
+    }
+    Ok(())
+}
+
+///
+/// Fails when writing any of `paths` would rewrite array elements of an append-only document,
+/// so that a command writing several paths changes none of them rather than some
+///
+fn check_append_only_paths<V: SelectValue>(
+    append_only: bool,
+    root: &V,
+    paths: &[Vec<String>],
+) -> Result<(), RedisError> {
+    paths
+        .iter()
+        .try_for_each(|p| check_append_only(append_only, root, p, true))
+}
+
+fn apply_update<O: Clone, V: SelectValue, W: WriteHolder<O, V>>(
+    redis_key: &mut W,
+    update_info: UpdateInfo,
+    val: O,
+) -> Result<bool, RedisError> {
+    match update_info {
+        UpdateInfo::SUI(sui) => redis_key.set_value(sui.path, val),
+        UpdateInfo::AUI(aui) => redis_key.dict_add(aui.path, &aui.key, val),
+        UpdateInfo::EUI(eui) => redis_key.arr_set_extend(eui.path, eui.index, val),
+    }
+}
+
+///
+/// Deep copies the value at `path` in `key`, which must both exist
+///
+fn copy_from_key<M: Manager>(
+    manager: &M,
+    ctx: &Context,
+    key: &RedisString,
+    path: &str,
+) -> Result<M::O, RedisError> {
+    let key = manager.open_key_read(ctx, key)?;
+    let doc = key.get_value()?.ok_or_else(RedisError::nonexistent_key)?;
+    Ok(manager.copy_value(KeyValue::new(doc).get_first(path)?))
+}
+
+///
+/// Sets a value at several independent paths, changing nothing unless all of them are valid
+///
+fn json_set_paths<M: Manager>(
+    manager: &M,
+    ctx: &Context,
+    key: RedisString,
+    paths: &[String],
+    val: M::O,
+    set_option: &SetOptions,
+    extend: bool,
+) -> RedisResult {
+    if paths.iter().any(|p| p == JSON_ROOT_PATH) {
+        return Err(RedisError::Str("ERR PATHS can't include the root"));
+    }
+    let mut redis_key = open_key_write_mutable(manager, ctx, key)?;
+    let append_only = redis_key.is_append_only()?;
+    let root = match redis_key.get_value()? {
+        Some(root) => root,
+        None if *set_option == SetOptions::AlreadyExists => return Ok(RedisValue::Null),
+        None => {
+            return Err(RedisError::Str(
+                "ERR new objects must be created at the root",
+            ))
+        }
+    };
+
+    // Every path is resolved before anything changes
+    let mut updates = Vec::new();
+    let mut doc = KeyValue::new(&*root);
+    for path in paths {
+        let update_info = doc.find_paths(path, set_option, extend)?;
+        // Without NX or XX a path that resolves to nothing can't be set
+        if update_info.is_empty() && *set_option == SetOptions::None {
+            return Err(RedisError::String(format!(
+                "ERR path '{}' does not exist",
+                path
+            )));
+        }
+        updates.extend(update_info);
+    }
+    check_append_only_updates(append_only, &*root, &updates)?;
+
+    let mut changed = false;
+    for ui in updates {
+        changed |= apply_update(&mut redis_key, ui, val.clone())?;
+    }
+    if changed {
+        redis_key.apply_changes(ctx, "json.set")?;
+        REDIS_OK
+    } else {
+        Ok(RedisValue::Null)
+    }
+}
+
+///
+/// Sets a value at a JSON Pointer: an existing object member or array element is replaced, a
+/// missing member is added and the `-` token appends to an array. With `mkpath` the missing
+/// ancestors of the value are added as empty objects, otherwise they are an error.
+///
+fn json_set_pointer<M: Manager>(
+    manager: &M,
+    ctx: &Context,
+    key: RedisString,
+    tokens: &[String],
+    val: M::O,
+    set_option: &SetOptions,
+    mkpath: bool,
+) -> RedisResult {
+    let mut redis_key = open_key_write_mutable(manager, ctx, key)?;
+    let append_only = redis_key.is_append_only()?;
+    let root = match redis_key.get_value()? {
+        Some(root) => &*root,
+        None if *set_option == SetOptions::AlreadyExists => return Ok(RedisValue::Null),
+        None => {
+            return Err(RedisError::Str(
+                "ERR new objects must be created at the root",
+            ))
+        }
+    };
+    // A pointer with a leading '/' has at least one token
+    let (last, parent) = tokens.split_last().unwrap();
+    match patch::resolve_pointer(root, tokens) {
+        Some(_) if *set_option != SetOptions::NotExists => {
+            check_append_only(append_only, root, tokens, true)?
+        }
+        Some(_) => {}
+        None => check_append_only(append_only, root, parent, false)?,
+    }
+    let mut depth = 0;
+    while depth < parent.len() && patch::resolve_pointer(root, &parent[..=depth]).is_some() {
+        depth += 1;
+    }
+
+    let changed = if depth < parent.len() {
+        if !mkpath {
+            return Err(RedisError::Str("ERR pointer parent does not exist"));
+        }
+        if *set_option == SetOptions::AlreadyExists {
+            return Ok(RedisValue::Null);
+        }
+        let ancestor = patch::resolve_pointer(root, &parent[..depth]).unwrap();
+        if ancestor.get_type() != SelectValueType::Object {
+            return Err(RedisError::Str(
+                "ERR MKPATH can only add members to objects",
+            ));
+        }
+        for i in depth..parent.len() {
+            let empty = manager.from_str("{}", Format::JSON)?;
+            redis_key.dict_add(parent[..i].to_vec(), &parent[i], empty)?;
+        }
+        redis_key.dict_add(parent.to_vec(), last, val)?
+    } else {
+        let parent_value = patch::resolve_pointer(root, parent).unwrap();
+        match parent_value.get_type() {
+            SelectValueType::Object => match (parent_value.get_key(last), set_option) {
+                (Some(_), SetOptions::NotExists) | (None, SetOptions::AlreadyExists) => false,
+                (Some(_), _) => redis_key.set_value(tokens.to_vec(), val)?,
+                (None, _) => redis_key.dict_add(parent.to_vec(), last, val)?,
+            },
+            SelectValueType::Array if last == "-" => {
+                if *set_option == SetOptions::AlreadyExists {
+                    false
+                } else if exceeds_max_array_len(parent_value, 1) {
+                    return Err(RedisError::Str("ERR array length exceeds max"));
+                } else {
+                    redis_key.arr_append(parent.to_vec(), vec![val])?;
+                    true
+                }
+            }
+            SelectValueType::Array => {
+                match patch::array_index(last).filter(|i| *i < parent_value.len().unwrap()) {
+                    Some(_) if *set_option == SetOptions::NotExists => false,
+                    Some(_) => redis_key.set_value(tokens.to_vec(), val)?,
+                    None => return Err(RedisError::Str("ERR array index out of range")),
+                }
+            }
+            _ => {
+                return Err(RedisError::Str(
+                    "ERR pointer parent is not an object or array",
+                ))
+            }
+        }
+    };
+    if changed {
+        redis_key.apply_changes(ctx, "json.set")?;
+        REDIS_OK
+    } else {
+        Ok(RedisValue::Null)
+    }
+}
+
+///
+/// The reply of a command modifying an existing value when its key does not exist
+///
+fn missing_key() -> RedisResult {
+    match config::get().missing_key_behavior {
+        MissingKeyBehavior::Error => Err(RedisError::nonexistent_key()),
+        MissingKeyBehavior::Null => Ok(RedisValue::Null),
+    }
+}
+
+///
+/// Opens a key for writing, failing if its document is frozen
+///
+fn open_key_write_mutable<M: Manager>(
+    manager: &M,
+    ctx: &Context,
+    key: RedisString,
+) -> Result<M::WriteHolder, RedisError> {
+    let mut redis_key = manager.open_key_write(ctx, key)?;
+    if redis_key.is_frozen()? {
+        Err(RedisError::Str("ERR key is immutable"))
+    } else {
+        Ok(redis_key)
+    }
+}
+
+fn parse_expire(expire: i64) -> Result<u64, RedisError> {
+    if expire > 0 {
+        Ok(expire as u64)
+    } else {
+        Err(RedisError::Str("ERR invalid expire time"))
+    }
+}
+
 fn find_paths<T: SelectValue, F: FnMut(&T) -> bool>(
     path: &str,
     doc: &T,
@@ -592,6 +2065,50 @@ fn find_paths<T: SelectValue, F: FnMut(&T) -> bool>(
         .select_with_paths(f)?)
 }
 
+///
+/// Order the matches of a deletion so every deletion leaves the paths still to delete valid:
+/// later array elements go before earlier ones, and the matches nested in another match are dropped
+///
+fn prepare_paths_for_deletion(paths: &mut Vec<Vec<String>>) {
+    if paths.len() < 2 {
+        return;
+    }
+    paths.sort_by(|p1, p2| {
+        p1.iter()
+            .zip(p2.iter())
+            .map(
+                |(t1, t2)| match (t1.parse::<usize>(), t2.parse::<usize>()) {
+                    (Ok(i1), Ok(i2)) => i1.cmp(&i2),
+                    _ => t1.cmp(t2),
+                },
+            )
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or_else(|| p1.len().cmp(&p2.len()))
+    });
+    // A nested match directly follows its ancestor (or another match nested in it)
+    paths.dedup_by(|p, prev| p.starts_with(prev.as_slice()));
+    paths.reverse();
+}
+
+///
+/// A copy of `doc` without the values matched by any of `paths`
+///
+fn exclude_paths<T: SelectValue>(doc: &T, paths: &[String]) -> Result<Value, RedisError> {
+    let mut matches = Vec::new();
+    for path in paths {
+        matches.extend(find_paths(path, doc, |_| true)?);
+    }
+    prepare_paths_for_deletion(&mut matches);
+    if matches.iter().any(Vec::is_empty) {
+        return Err(RedisError::Str("ERR EXCLUDE can't remove the root"));
+    }
+    let mut pruned = serde_json::to_value(doc).map_err(Error::from)?;
+    for p in matches {
+        patch::remove(&mut pruned, &p)?;
+    }
+    Ok(pruned)
+}
+
 pub fn command_json_del<M: Manager>(
     manager: M,
     ctx: &Context,
@@ -604,14 +2121,19 @@ pub fn command_json_del<M: Manager>(
         .next_string()
         .map_or_else(|_| JSON_ROOT_PATH.to_string(), backwards_compat_path);
 
-    let mut redis_key = manager.open_key_write(ctx, key)?;
+    let mut redis_key = open_key_write_mutable(&manager, ctx, key)?;
+    let append_only = redis_key.is_append_only()?;
     let deleted = match redis_key.get_value()? {
         Some(doc) => {
             let res = if path == JSON_ROOT_PATH {
                 redis_key.delete()?;
                 1
             } else {
-                let paths = find_paths(&path, doc, |_| true)?;
+                let mut paths = find_paths(&path, doc, |_| true)?;
+                for p in &paths {
+                    check_append_only(append_only, &*doc, p, true)?;
+                }
+                prepare_paths_for_deletion(&mut paths);
                 let mut changed = 0;
                 for p in paths {
                     if redis_key.delete_path(p)? {
@@ -639,19 +2161,89 @@ pub fn command_json_mget<M: Manager>(
         return Err(RedisError::WrongArity);
     }
 
+    // A trailing STRICT fails the whole command on the first key that errors instead of replying null for it
+    let strict = args.len() > 3
+        && args.last().map_or(false, |arg| {
+            arg.to_string().eq_ignore_ascii_case(CMD_ARG_STRICT)
+        });
+    let args = if strict {
+        &args[..args.len() - 1]
+    } else {
+        &args[..]
+    };
+
+    // Keys followed by PATHS and multiple paths reply with a path->value object per key.
+    // PATHS is only told apart from a key or a path named "paths" by the count of paths that
+    // follows it, which must cover exactly the rest of the arguments
+    if let Some(pos) = (2..args.len()).find(|&pos| {
+        args[pos]
+            .try_as_str()
+            .map_or(false, |arg| arg.eq_ignore_ascii_case(CMD_ARG_PATHS))
+            && args.get(pos + 1).map_or(false, |count| {
+                count
+                    .parse_integer()
+                    .map_or(false, |n| n > 0 && pos as i64 + 2 + n == args.len() as i64)
+            })
+    }) {
+        let keys = &args[1..pos];
+        let paths = args[pos + 2..]
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>();
+        let options = TransformOptions::default();
+        let results: Result<Vec<RedisValue>, RedisError> = keys
+            .iter()
+            .map(|key| {
+                let mut paths = paths.iter().map(|p| Path::new(p.clone())).collect();
+                let holder = manager.open_key_read(ctx, key)?;
+                let doc = match holder.get_value() {
+                    Err(e) if strict => return Err(mget_key_error(key, e)),
+                    doc => doc?,
+                };
+                match doc
+                    .map(|doc| {
+                        KeyValue::new(doc).to_json(
+                            &mut paths,
+                            None,
+                            None,
+                            None,
+                            Format::JSON,
+                            &options,
+                        )
+                    })
+                    .transpose()
+                {
+                    Ok(v) => Ok(v.into()),
+                    Err(e) if strict => Err(mget_key_error(key, e.into())),
+                    Err(_) => Ok(RedisValue::Null),
+                }
+            })
+            .collect();
+        return Ok(results?.into());
+    }
+
     args.last().ok_or(RedisError::WrongArity).and_then(|path| {
         let path = backwards_compat_path(path.to_string());
         let keys = &args[1..args.len() - 1];
 
+        // Shared by all the keys to save reallocating while every value is serialized
+        let mut buf = Vec::new();
         let results: Result<Vec<RedisValue>, RedisError> = keys
             .iter()
             .map(|key| {
-                manager
-                    .open_key_read(ctx, key)?
-                    .get_value()?
-                    .map(|doc| KeyValue::new(doc).to_string(&path, Format::JSON))
+                let holder = manager.open_key_read(ctx, key)?;
+                let doc = match holder.get_value() {
+                    Err(e) if strict => return Err(mget_key_error(key, e)),
+                    doc => doc?,
+                };
+                match doc
+                    .map(|doc| KeyValue::new(doc).to_string_with_buffer(&path, &mut buf))
                     .transpose()
-                    .map_or_else(|_| Ok(RedisValue::Null), |v| Ok(v.into()))
+                {
+                    Ok(v) => Ok(v.into()),
+                    Err(e) if strict => Err(mget_key_error(key, e.into())),
+                    Err(_) => Ok(RedisValue::Null),
+                }
             })
             .collect();
 
@@ -659,26 +2251,104 @@ pub fn command_json_mget<M: Manager>(
     })
 }
 
+///
+/// Names the key a JSON.MGET STRICT failed on, keeping the error code of the original error
+///
+fn mget_key_error(key: &RedisString, e: RedisError) -> RedisError {
+    let msg = e.to_string();
+    match msg.find(' ') {
+        Some(pos) if msg[..pos].chars().all(|c| c.is_ascii_uppercase()) => {
+            RedisError::String(format!(
+                "{} key '{}': {}",
+                &msg[..pos],
+                key.to_string(),
+                &msg[pos + 1..]
+            ))
+        }
+        _ => RedisError::String(format!("ERR key '{}': {}", key.to_string(), msg)),
+    }
+}
+
 pub fn command_json_type<M: Manager>(
     manager: M,
     ctx: &Context,
     args: Vec<RedisString>,
+) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = args.next_arg()?;
+    let args: Vec<RedisString> = args.collect();
+    let mut paths = Vec::new();
+    // SIMPLETYPES reports integers as "number", like other numbers. It is only read along a
+    // JSONPath, otherwise it is a legacy path as it always was
+    let mut simple = false;
+    let jsonpath = args
+        .iter()
+        .any(|arg| arg.try_as_str().map_or(false, |arg| arg.starts_with('$')));
+    for arg in &args {
+        match arg.try_as_str()? {
+            arg if jsonpath && arg.eq_ignore_ascii_case(CMD_ARG_SIMPLETYPES) => simple = true,
+            arg => paths.push(Path::new(arg.to_string())),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(Path::new(".".to_string()));
+    }
+
+    let key = manager.open_key_read(ctx, &key)?;
+    let doc = match key.get_value()? {
+        Some(doc) => KeyValue::new(doc),
+        None => return Ok(RedisValue::Null),
+    };
+
+    // A single path keeps the original reply, the type of its first match
+    if paths.len() == 1 {
+        return Ok(doc
+            .get_type(paths[0].get_path(), simple)
+            .map_or(RedisValue::Null, RedisValue::SimpleStringStatic));
+    }
+    Ok(RedisValue::Array(
+        paths
+            .iter()
+            .map(|path| doc.type_reply(path, simple))
+            .collect(),
+    ))
+}
+
+pub fn command_json_exists<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    args: Vec<RedisString>,
 ) -> RedisResult {
     let mut args = args.into_iter().skip(1);
     let key = args.next_arg()?;
     let path = backwards_compat_path(args.next_string()?);
+    args.done()?;
 
     let key = manager.open_key_read(ctx, &key)?;
+    // Nothing is serialized, the matches are only counted
+    let exists = match key.get_value()? {
+        Some(doc) => !find_paths(&path, doc, |_| true)?.is_empty(),
+        None => false,
+    };
+    Ok(RedisValue::Integer(exists as i64))
+}
 
-    let value = key.get_value()?.map_or_else(
-        || RedisValue::Null,
-        |doc| match KeyValue::new(doc).get_type(&path) {
-            Ok(s) => s.into(),
-            Err(_) => RedisValue::Null,
-        },
-    );
+pub fn command_json_count<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = args.next_arg()?;
+    let path = backwards_compat_path(args.next_string()?);
+    args.done()?;
 
-    Ok(value)
+    let key = manager.open_key_read(ctx, &key)?;
+    let count = match key.get_value()? {
+        Some(doc) => KeyValue::new(doc).get_values(&path)?.len(),
+        None => 0,
+    };
+    Ok(count.into())
 }
 
 enum NumOp {
@@ -700,33 +2370,99 @@ where
     let mut args = args.into_iter().skip(1);
 
     let key = args.next_arg()?;
-    let path = backwards_compat_path(args.next_string()?);
-    let number = args.next_string()?;
+    let mut pairs = vec![(
+        backwards_compat_path(args.next_string()?),
+        args.next_string()?,
+    )];
+    let mut get_old = false;
+    let mut decimal = false;
+    while let Some(arg) = args.next() {
+        match arg.try_as_str()? {
+            arg if arg.eq_ignore_ascii_case("GETOLD") && !get_old => get_old = true,
+            arg if arg.eq_ignore_ascii_case("DECIMAL") && !decimal => decimal = true,
+            // JSON.NUMINCRBY takes more <path> <number> pairs
+            arg if matches!(op, NumOp::Incr) => {
+                pairs.push((backwards_compat_path(arg.to_string()), args.next_string()?))
+            }
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    }
+    if decimal && matches!(op, NumOp::Pow) {
+        return Err(RedisError::Str(
+            "ERR DECIMAL is not supported by JSON.NUMPOWBY",
+        ));
+    }
 
-    let mut redis_key = manager.open_key_write(ctx, key)?;
+    let mut redis_key = open_key_write_mutable(&manager, ctx, key)?;
+    let append_only = redis_key.is_append_only()?;
 
-    let root = redis_key
-        .get_value()?
-        .ok_or_else(RedisError::nonexistent_key)?;
-    let paths = find_paths(&path, root, |v| {
-        v.get_type() == SelectValueType::Double || v.get_type() == SelectValueType::Long
-    })?;
-    if !paths.is_empty() {
+    let root = match redis_key.get_value()? {
+        Some(root) => root,
+        None => return missing_key(),
+    };
+    // Every path is checked before any number is changed
+    let mut updates = Vec::with_capacity(pairs.len());
+    for (path, number) in &pairs {
+        let mut old_values = Vec::new();
+        let paths = find_paths(path, root, |v| {
+            let is_number =
+                v.get_type() == SelectValueType::Double || v.get_type() == SelectValueType::Long;
+            if is_number && get_old {
+                old_values.push(serde_json::to_string(v));
+            }
+            is_number
+        })?;
+        if paths.is_empty() {
+            return Err(RedisError::String(format!(
+                "Path '{}' does not exist or does not contains a number",
+                path
+            )));
+        }
+        check_append_only_paths(append_only, root, &paths)?;
+        updates.push((paths, number, old_values));
+    }
+
+    let mut replies = Vec::with_capacity(updates.len());
+    let mut changed = false;
+    for (paths, number, old_values) in updates {
+        let single = paths.len() == 1;
         let mut res = None;
         for p in paths {
-            res = Some(match op {
-                NumOp::Incr => redis_key.incr_by(p, &number)?,
-                NumOp::Mult => redis_key.mult_by(p, &number)?,
-                NumOp::Pow => redis_key.pow_by(p, &number)?,
-            });
+            let n = match op {
+                NumOp::Incr if decimal => redis_key.decimal_incr_by(p, number),
+                NumOp::Mult if decimal => redis_key.decimal_mult_by(p, number),
+                NumOp::Incr => redis_key.incr_by(p, number),
+                NumOp::Mult => redis_key.mult_by(p, number),
+                NumOp::Pow => redis_key.pow_by(p, number),
+            };
+            // The numbers changed before a failing one stay changed, so they are still notified
+            // and replicated (the replica fails at the same number)
+            if n.is_err() && changed {
+                redis_key.apply_changes(ctx, cmd)?;
+            }
+            res = Some(n?);
+            changed = true;
         }
-        redis_key.apply_changes(ctx, cmd)?;
-        Ok(res.unwrap().to_string().into())
+        replies.push(if get_old {
+            let mut old_values = old_values
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(Error::from)?;
+            if single {
+                old_values.pop().into()
+            } else {
+                old_values.into()
+            }
+        } else {
+            res.unwrap().to_string().into()
+        });
+    }
+    redis_key.apply_changes(ctx, cmd)?;
+    // A single pair keeps the original reply
+    if replies.len() == 1 {
+        Ok(replies.pop().unwrap())
     } else {
-        Err(RedisError::String(format!(
-            "Path '{}' does not exist or does not contains a number",
-            path
-        )))
+        Ok(RedisValue::Array(replies))
     }
 }
 
@@ -754,6 +2490,91 @@ pub fn command_json_num_powby<M: Manager>(
     command_json_num_op(manager, ctx, args, "json.numpowby", NumOp::Pow)
 }
 
+pub fn command_json_counter<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+
+    let key = args.next_arg()?;
+    let path = backwards_compat_path(args.next_string()?);
+    let mut by = None;
+    let mut default = None;
+    while let Some(arg) = args.next() {
+        match arg.try_as_str()? {
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_BY) && by.is_none() => {
+                by = Some(args.next_string()?)
+            }
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_DEFAULT) && default.is_none() => {
+                default = Some(args.next_string()?)
+            }
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+    }
+    // A new counter replies with its value as it is stored
+    let parse_number = |n: String| match RedisJSON::parse_str(&n, Format::JSON) {
+        Ok(Value::Number(n)) => Ok(n.to_string()),
+        _ => Err(RedisError::Str("ERR BY and DEFAULT must be numbers")),
+    };
+    let by = parse_number(by.unwrap_or_else(|| "1".to_string()))?;
+    let default = parse_number(default.unwrap_or_else(|| "0".to_string()))?;
+    let initial = manager.from_str(&default, Format::JSON)?;
+
+    let mut redis_key = open_key_write_mutable(&manager, ctx, key)?;
+    let append_only = redis_key.is_append_only()?;
+    let root = match redis_key.get_value()? {
+        Some(root) => root,
+        None if path == JSON_ROOT_PATH => {
+            redis_key.set_value(Vec::new(), initial)?;
+            redis_key.apply_changes(ctx, "json.counter")?;
+            return Ok(default.into());
+        }
+        None => {
+            return Err(RedisError::Str(
+                "ERR new objects must be created at the root",
+            ))
+        }
+    };
+    let mut non_number = false;
+    let paths = find_paths(&path, root, |v| {
+        let is_number =
+            v.get_type() == SelectValueType::Double || v.get_type() == SelectValueType::Long;
+        non_number |= !is_number;
+        is_number
+    })?;
+    if non_number {
+        return Err(RedisError::Str(
+            "ERR wrong type of path value - expected a number",
+        ));
+    }
+    if paths.is_empty() {
+        // The counter is added as JSON.SET NX adds a value
+        let mut update_info =
+            KeyValue::new(root).find_paths(&path, &SetOptions::NotExists, false)?;
+        if update_info.is_empty() {
+            return Err(RedisError::String(format!(
+                "Path '{}' does not exist",
+                path
+            )));
+        }
+        let last = update_info.pop().unwrap();
+        for ui in update_info {
+            apply_update(&mut redis_key, ui, initial.clone())?;
+        }
+        apply_update(&mut redis_key, last, initial)?;
+        redis_key.apply_changes(ctx, "json.counter")?;
+        return Ok(default.into());
+    }
+    check_append_only_paths(append_only, root, &paths)?;
+    let mut res = None;
+    for p in paths {
+        res = Some(redis_key.incr_by(p, &by)?);
+    }
+    redis_key.apply_changes(ctx, "json.counter")?;
+    Ok(res.unwrap().to_string().into())
+}
+
 pub fn command_json_bool_toggle<M: Manager>(
     manager: M,
     ctx: &Context,
@@ -762,13 +2583,16 @@ pub fn command_json_bool_toggle<M: Manager>(
     let mut args = args.into_iter().skip(1);
     let key = args.next_arg()?;
     let path = backwards_compat_path(args.next_string()?);
-    let mut redis_key = manager.open_key_write(ctx, key)?;
+    let mut redis_key = open_key_write_mutable(&manager, ctx, key)?;
+    let append_only = redis_key.is_append_only()?;
 
-    let root = redis_key
-        .get_value()?
-        .ok_or_else(RedisError::nonexistent_key)?;
+    let root = match redis_key.get_value()? {
+        Some(root) => root,
+        None => return missing_key(),
+    };
     let paths = find_paths(&path, root, |v| v.get_type() == SelectValueType::Bool)?;
     if !paths.is_empty() {
+        check_append_only_paths(append_only, root, &paths)?;
         let mut res = None;
         for p in paths {
             res = Some(redis_key.bool_toggle(p)?);
@@ -787,6 +2611,24 @@ pub fn command_json_str_append<M: Manager>(
     manager: M,
     ctx: &Context,
     args: Vec<RedisString>,
+) -> RedisResult {
+    command_json_str_op(manager, ctx, args, "json.strappend", false)
+}
+
+pub fn command_json_str_prepend<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    command_json_str_op(manager, ctx, args, "json.strprepend", true)
+}
+
+fn command_json_str_op<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    args: Vec<RedisString>,
+    cmd: &str,
+    prepend: bool,
 ) -> RedisResult {
     let mut args = args.into_iter().skip(1);
 
@@ -804,20 +2646,29 @@ pub fn command_json_str_append<M: Manager>(
         path = JSON_ROOT_PATH.to_string();
         json = path_or_json;
     }
+    // A single string is appended, unlike the items of JSON.ARRAPPEND
+    args.done()?;
 
-    let mut redis_key = manager.open_key_write(ctx, key)?;
+    let mut redis_key = open_key_write_mutable(&manager, ctx, key)?;
+    let append_only = redis_key.is_append_only()?;
 
-    let root = redis_key
-        .get_value()?
-        .ok_or_else(RedisError::nonexistent_key)?;
+    let root = match redis_key.get_value()? {
+        Some(root) => root,
+        None => return missing_key(),
+    };
 
     let paths = find_paths(&path, root, |v| v.get_type() == SelectValueType::String)?;
     if !paths.is_empty() {
+        check_append_only_paths(append_only, root, &paths)?;
         let mut res = None;
         for p in paths {
-            res = Some(redis_key.str_append(p, json.clone())?);
+            res = Some(if prepend {
+                redis_key.str_prepend(p, json.clone())?
+            } else {
+                redis_key.str_append(p, json.clone())?
+            });
         }
-        redis_key.apply_changes(ctx, "json.strappend")?;
+        redis_key.apply_changes(ctx, cmd)?;
         Ok(res.unwrap().into())
     } else {
         Err(RedisError::String(format!(
@@ -845,6 +2696,11 @@ pub fn command_json_str_len<M: Manager>(
     }
 }
 
+fn exceeds_max_array_len<V: SelectValue>(array: &V, added: usize) -> bool {
+    let max_len = config::get().max_array_len;
+    max_len > 0 && array.len().unwrap() + added > max_len
+}
+
 pub fn command_json_arr_append<M: Manager>(
     manager: M,
     ctx: &Context,
@@ -855,34 +2711,198 @@ pub fn command_json_arr_append<M: Manager>(
     let key = args.next_arg()?;
     let path = backwards_compat_path(args.next_string()?);
 
+    // A JSON value can't be spelled like an option, so options are unambiguous
+    let mut token = None;
+    let mut typed = false;
+    let mut filter = None;
+    let mut upsert = false;
+    let mut with_value = false;
+    let mut max_len = None;
+    while let Some(arg) = args.peek() {
+        match arg.try_as_str()? {
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_IDEMPOTENT) && token.is_none() => {
+                args.next();
+                token = Some(args.next_string()?);
+            }
+            // The arrays keep only their last MAXLEN elements, dropping the oldest ones
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_MAXLEN) && max_len.is_none() => {
+                args.next();
+                max_len = match args.next_i64()? {
+                    n if n > 0 => Some(n as usize),
+                    _ => return Err(RedisError::Str("ERR MAXLEN must be positive")),
+                };
+            }
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_TYPED) && !typed => {
+                args.next();
+                typed = true;
+            }
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_WHERE) && filter.is_none() => {
+                args.next();
+                filter = Some(backwards_compat_path(args.next_string()?));
+            }
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_UPSERT) && !upsert => {
+                args.next();
+                upsert = true;
+            }
+            arg if arg.eq_ignore_ascii_case(CMD_ARG_WITHVALUE) && !with_value => {
+                args.next();
+                with_value = true;
+            }
+            _ => break,
+        }
+    }
+    // A repeated call replies with the recorded length
+    if with_value && token.is_some() {
+        return Err(RedisError::Str(
+            "ERR WITHVALUE can't be combined with IDEMPOTENT",
+        ));
+    }
+    if let Some(token) = &token {
+        if let Some(reply) = manager.open_key_read(ctx, &key)?.get_token_reply(token)? {
+            return Ok(reply.into());
+        }
+    }
+
     // We require at least one JSON item to append
     args.peek().ok_or(RedisError::WrongArity)?;
-    let args = args
-        .map(|json| manager.from_str(&json.into_string_lossy(), Format::JSON))
-        .collect::<Result<_, _>>()?;
+    let mut args: Vec<M::O> = args
+        .enumerate()
+        .map(|(i, json)| {
+            manager
+                .from_str(&json.into_string_lossy(), Format::JSON)
+                .map_err(|e| invalid_item(i, e))
+        })
+        .collect::<Result<_, Error>>()?;
+    let types: Vec<_> = if typed {
+        args.iter().map(|value| manager.value_name(value)).collect()
+    } else {
+        Vec::new()
+    };
 
-    let mut redis_key = manager.open_key_write(ctx, key)?;
-    let root = redis_key
-        .get_value()?
-        .ok_or_else(RedisError::nonexistent_key)?;
+    let mut redis_key = open_key_write_mutable(&manager, ctx, key)?;
+    let append_only = redis_key.is_append_only()?;
+    let root = match redis_key.get_value()? {
+        Some(root) => root,
+        None => return missing_key(),
+    };
 
-    let mut paths = find_paths(&path, root, |v| v.get_type() == SelectValueType::Array)?;
-    if paths.is_empty() {
+    let mut too_long = false;
+    // Whether MAXLEN drops elements of an existing array
+    let mut trims = false;
+    let mut mismatch = false;
+    let mut non_array = false;
+    let mut len = 0;
+    let mut paths = find_paths(&path, root, |v| {
+        if v.get_type() != SelectValueType::Array {
+            non_array = true;
+            return false;
+        }
+        len = v.len().unwrap_or(0);
+        // A capped array only grows up to its cap
+        let added = max_len.map_or(args.len(), |max| {
+            (len + args.len()).min(max).saturating_sub(len)
+        });
+        too_long |= exceeds_max_array_len(v, added);
+        trims |= added < args.len();
+        if typed {
+            // The first element sets the type of an empty array
+            let expected = match v.get_index(0) {
+                Some(first) => KeyValue::value_name(first),
+                None => types[0],
+            };
+            mismatch |= types.iter().any(|t| *t != expected);
+        }
+        true
+    })?;
+    let holds = filter_holds(&filter, root)?;
+    if paths.is_empty() && upsert && !non_array {
+        if !holds {
+            return if with_value {
+                arrays_reply(root, &paths)
+            } else {
+                Ok(len.into())
+            };
+        }
+        // A capped array keeps only the last MAXLEN items
+        let first = max_len.map_or(0, |max| args.len().saturating_sub(max));
+        let items = args.split_off(first);
+        let max_array_len = config::get().max_array_len;
+        if max_array_len > 0 && items.len() > max_array_len {
+            return Err(RedisError::Str("ERR array length exceeds max"));
+        }
+        if types.iter().any(|t| *t != types[0]) {
+            return Err(RedisError::Str("ERR element type mismatch"));
+        }
+        let res = items.len();
+        // The new array is added as JSON.SET NX adds a value
+        let array = manager.new_array(items);
+        let mut update_info =
+            KeyValue::new(root).find_paths(&path, &SetOptions::NotExists, false)?;
+        if update_info.is_empty() {
+            return Err(RedisError::String(format!(
+                "Path '{}' does not exist",
+                path
+            )));
+        }
+        let last = update_info.pop().unwrap();
+        for ui in update_info {
+            apply_update(&mut redis_key, ui, array.clone())?;
+        }
+        apply_update(&mut redis_key, last, array)?;
+        redis_key.apply_changes(ctx, "json.arrappend")?;
+        if with_value {
+            let root = redis_key.get_value()?.unwrap();
+            let paths = find_paths(&path, root, |v| v.get_type() == SelectValueType::Array)?;
+            return arrays_reply(root, &paths);
+        }
+        if let Some(token) = token {
+            redis_key.record_token(token, res)?;
+        }
+        Ok(res.into())
+    } else if paths.is_empty() {
         Err(RedisError::String(format!(
             "Path '{}' does not exist",
             path
         )))
-    } else if paths.len() == 1 {
-        let res = redis_key.arr_append(paths.pop().unwrap(), args)?;
-        redis_key.apply_changes(ctx, "json.arrappend")?;
-        Ok(res.into())
+    } else if !holds {
+        // The arrays are left as they are
+        if with_value {
+            arrays_reply(root, &paths)
+        } else {
+            Ok(len.into())
+        }
+    } else if too_long {
+        Err(RedisError::Str("ERR array length exceeds max"))
+    } else if mismatch {
+        Err(RedisError::Str("ERR element type mismatch"))
+    } else if append_only && trims {
+        Err(RedisError::Str("ERR append-only array"))
     } else {
-        let mut res = None;
+        let changed = if with_value {
+            paths.clone()
+        } else {
+            Vec::new()
+        };
+        // The last matched array takes ownership of the values, the others get clones
+        let last = paths.pop().unwrap();
         for p in paths {
-            res = Some(redis_key.arr_append(p, args.clone())?);
+            redis_key.arr_append(p.clone(), args.clone())?;
+            if let Some(max) = max_len {
+                redis_key.arr_trim_head(p, max)?;
+            }
+        }
+        let mut res = redis_key.arr_append(last.clone(), args)?;
+        if let Some(max) = max_len {
+            res = redis_key.arr_trim_head(last, max)?;
         }
         redis_key.apply_changes(ctx, "json.arrappend")?;
-        Ok(res.unwrap().into())
+        if with_value {
+            return arrays_reply(redis_key.get_value()?.unwrap(), &changed);
+        }
+        if let Some(token) = token {
+            redis_key.record_token(token, res)?;
+        }
+        Ok(res.into())
     }
 }
 
@@ -895,7 +2915,12 @@ pub fn command_json_arr_index<M: Manager>(
 
     let key = args.next_arg()?;
     let path = backwards_compat_path(args.next_string()?);
-    let json_scalar = args.next_string()?;
+    let mut needle = args.next_string()?;
+    // A JSON value can't be spelled PREDICATE, so it's unambiguous
+    let predicate = needle.eq_ignore_ascii_case("PREDICATE");
+    if predicate {
+        needle = args.next_string()?;
+    }
     let start: i64 = args.next().map(|v| v.parse_integer()).unwrap_or(Ok(0))?;
     let end: i64 = args.next().map(|v| v.parse_integer()).unwrap_or(Ok(0))?;
 
@@ -904,12 +2929,49 @@ pub fn command_json_arr_index<M: Manager>(
     let key = manager.open_key_read(ctx, &key)?;
 
     let index = key.get_value()?.map_or(Ok(-1), |doc| {
-        KeyValue::new(doc).arr_index(&path, &json_scalar, start, end)
+        KeyValue::new(doc).arr_index(&path, &needle, predicate, start, end)
     })?;
 
     Ok(index.into())
 }
 
+///
+/// Whether the document satisfies the WHERE filter of a conditional write, i.e. the filter
+/// selects at least one value in it (no filter always holds)
+///
+fn filter_holds<T: SelectValue>(filter: &Option<String>, doc: &T) -> Result<bool, RedisError> {
+    match filter {
+        Some(filter) => Ok(!find_paths(filter, doc, |_| true)?.is_empty()),
+        None => Ok(true),
+    }
+}
+
+///
+/// The WITHVALUE reply of the array commands: the serialization of the matched array as it is
+/// after the change, or an array of them when there are several matches
+///
+fn arrays_reply<T: SelectValue>(root: &T, paths: &[Vec<String>]) -> RedisResult {
+    let mut arrays = paths
+        .iter()
+        .map(|p| {
+            let arr = patch::resolve_pointer(root, p).ok_or("ERR path does not exist")?;
+            Ok(serde_json::to_string(arr)?)
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    if arrays.len() == 1 {
+        Ok(arrays.pop().unwrap().into())
+    } else {
+        Ok(arrays.into())
+    }
+}
+
+///
+/// Reports which of the items of a bulk JSON.ARRAPPEND or JSON.ARRINSERT failed to parse
+///
+fn invalid_item(index: usize, e: Error) -> Error {
+    Error::from(format!("ERR invalid JSON at argument {}: {}", index, e.msg))
+}
+
 pub fn command_json_arr_insert<M: Manager>(
     manager: M,
     ctx: &Context,
@@ -919,28 +2981,105 @@ pub fn command_json_arr_insert<M: Manager>(
 
     let key = args.next_arg()?;
     let path = backwards_compat_path(args.next_string()?);
-    let index = args.next_i64()?;
+    // INDEXOF <json> inserts before the first element equal to the needle instead of at an index
+    let (index, needle) = match args.peek() {
+        Some(arg) if arg.try_as_str()?.eq_ignore_ascii_case(CMD_ARG_INDEXOF) => {
+            args.next();
+            let needle = RedisJSON::parse_str(&args.next_string()?, Format::JSON)?;
+            (0, Some(needle))
+        }
+        _ => (args.next_i64()?, None),
+    };
+    let filter = match args.peek() {
+        Some(arg) if arg.try_as_str()?.eq_ignore_ascii_case(CMD_ARG_WHERE) => {
+            args.next();
+            Some(backwards_compat_path(args.next_string()?))
+        }
+        _ => None,
+    };
+    let with_value = match args.peek() {
+        Some(arg) if arg.try_as_str()?.eq_ignore_ascii_case(CMD_ARG_WITHVALUE) => {
+            args.next();
+            true
+        }
+        _ => false,
+    };
 
-    // We require at least one JSON item to append
+    // We require at least one JSON item to insert
     args.peek().ok_or(RedisError::WrongArity)?;
-    let args = args
-        .map(|json| manager.from_str(&json.into_string_lossy(), Format::JSON))
+    let args: Vec<M::O> = args
+        .enumerate()
+        .map(|(i, json)| {
+            manager
+                .from_str(&json.into_string_lossy(), Format::JSON)
+                .map_err(|e| invalid_item(i, e))
+        })
         .collect::<Result<_, _>>()?;
 
-    let mut redis_key = manager.open_key_write(ctx, key)?;
+    let mut redis_key = open_key_write_mutable(&manager, ctx, key)?;
+    let append_only = redis_key.is_append_only()?;
 
-    let root = redis_key
-        .get_value()?
-        .ok_or_else(RedisError::nonexistent_key)?;
+    let root = match redis_key.get_value()? {
+        Some(root) => root,
+        None => return missing_key(),
+    };
 
-    let paths = find_paths(&path, root, |v| v.get_type() == SelectValueType::Array)?;
-    if !paths.is_empty() {
+    let mut too_long = false;
+    let mut len = 0;
+    // The index of every matched array, in the order of the matches
+    let mut indexes = Vec::new();
+    // Whether an insert moves existing elements, i.e. isn't at the end of its array
+    let mut shifts = false;
+    let paths = find_paths(&path, root, |v| {
+        if v.get_type() != SelectValueType::Array {
+            return false;
+        }
+        len = v.len().unwrap_or(0);
+        too_long |= exceeds_max_array_len(v, args.len());
+        let at = match &needle {
+            None => Some(index),
+            Some(needle) => v
+                .values()
+                .unwrap()
+                .position(|e| KeyValue::new(v).is_eqaul(e, needle))
+                .map(|i| i as i64),
+        };
+        if let Some(i) = at {
+            shifts |= if i < 0 { len as i64 + i } else { i } != len as i64;
+        }
+        indexes.push(at);
+        true
+    })?;
+    let holds = filter_holds(&filter, root)?;
+    if !paths.is_empty() && !holds {
+        // The arrays are left as they are
+        if with_value {
+            arrays_reply(root, &paths)
+        } else {
+            Ok(len.into())
+        }
+    } else if too_long {
+        Err(RedisError::Str("ERR array length exceeds max"))
+    } else if indexes.contains(&None) {
+        Err(RedisError::Str("ERR INDEXOF value not found"))
+    } else if append_only && shifts {
+        Err(RedisError::Str("ERR append-only array"))
+    } else if !paths.is_empty() {
+        let changed = if with_value {
+            paths.clone()
+        } else {
+            Vec::new()
+        };
         let mut res = None;
-        for p in paths {
-            res = Some(redis_key.arr_insert(p, &args, index)?);
+        for (p, index) in paths.into_iter().zip(indexes) {
+            res = Some(redis_key.arr_insert(p, &args, index.unwrap())?);
         }
         redis_key.apply_changes(ctx, "json.arrinsert")?;
-        Ok(res.unwrap().into())
+        if with_value {
+            arrays_reply(redis_key.get_value()?.unwrap(), &changed)
+        } else {
+            Ok(res.unwrap().into())
+        }
     } else {
         Err(RedisError::String(format!(
             "Path '{}' does not exist or not an array",
@@ -984,24 +3123,50 @@ pub fn command_json_arr_pop<M: Manager>(
             (path, index)
         })
         .unwrap_or((JSON_ROOT_PATH.to_string(), i64::MAX));
+    let count = match args.next() {
+        None => None,
+        Some(c) => match c.parse_integer()? {
+            n if n >= 0 => Some(n as usize),
+            _ => return Err(RedisError::Str("ERR count must be a non negative integer")),
+        },
+    };
+    args.done()?;
 
-    let mut redis_key = manager.open_key_write(ctx, key)?;
+    let mut redis_key = open_key_write_mutable(&manager, ctx, key)?;
+    let append_only = redis_key.is_append_only()?;
 
-    let root = redis_key
-        .get_value()?
-        .ok_or_else(RedisError::nonexistent_key)?;
+    let root = match redis_key.get_value()? {
+        Some(root) => root,
+        None => return missing_key(),
+    };
 
     let paths = find_paths(&path, root, |v| v.get_type() == SelectValueType::Array)?;
-    if !paths.is_empty() {
+    if append_only && !paths.is_empty() {
+        return Err(RedisError::Str("ERR append-only array"));
+    }
+    // The reply is of the last array, but the document changed if any of the arrays did
+    let mut popped = false;
+    if let (Some(count), false) = (count, paths.is_empty()) {
+        let mut res = Vec::new();
+        for p in paths {
+            res = redis_key.arr_pop_many(p, index, count)?;
+            popped |= !res.is_empty();
+        }
+        if popped {
+            redis_key.apply_changes(ctx, "json.arrpop")?;
+        }
+        Ok(res.into())
+    } else if !paths.is_empty() {
         let mut res = None;
         for p in paths {
-            res = Some(redis_key.arr_pop(p, index)?);
+            res = redis_key.arr_pop(p, index)?;
+            popped |= res.is_some();
         }
-        match res.unwrap() {
-            Some(r) => {
-                redis_key.apply_changes(ctx, "json.arrpop")?;
-                Ok(r.into())
-            }
+        if popped {
+            redis_key.apply_changes(ctx, "json.arrpop")?;
+        }
+        match res {
+            Some(r) => Ok(r.into()),
             None => Ok(().into()),
         }
     } else {
@@ -1021,23 +3186,64 @@ pub fn command_json_arr_trim<M: Manager>(
 
     let key = args.next_arg()?;
     let path = backwards_compat_path(args.next_string()?);
-    let start = args.next_i64()?;
-    let stop = args.next_i64()?;
+    // KEEP <filter> keeps the elements satisfying the filter instead of a range
+    let first = args.next_arg()?;
+    let (filter, start, stop) = if first.try_as_str()?.eq_ignore_ascii_case(CMD_ARG_KEEP) {
+        (Some(args.next_string()?), 0, 0)
+    } else {
+        (None, first.parse_integer()?, args.next_i64()?)
+    };
+    let with_value = match args.next() {
+        None => false,
+        Some(s) if s.try_as_str()?.eq_ignore_ascii_case(CMD_ARG_WITHVALUE) => true,
+        Some(_) => return Err(RedisError::Str("ERR syntax error")),
+    };
 
-    let mut redis_key = manager.open_key_write(ctx, key)?;
+    let mut redis_key = open_key_write_mutable(&manager, ctx, key)?;
+    let append_only = redis_key.is_append_only()?;
 
-    let root = redis_key
-        .get_value()?
-        .ok_or_else(RedisError::nonexistent_key)?;
+    let root = match redis_key.get_value()? {
+        Some(root) => root,
+        None => return missing_key(),
+    };
 
     let paths = find_paths(&path, root, |v| v.get_type() == SelectValueType::Array)?;
+    if append_only && !paths.is_empty() {
+        return Err(RedisError::Str("ERR append-only array"));
+    }
     if !paths.is_empty() {
+        // The filter is evaluated on every array before any of them changes
+        let kept = match &filter {
+            Some(filter) => paths
+                .iter()
+                .map(|p| {
+                    let arr = patch::resolve_pointer(root, p).ok_or("ERR path does not exist")?;
+                    KeyValue::filter_indexes(arr, filter)
+                })
+                .collect::<Result<Vec<_>, Error>>()?,
+            None => Vec::new(),
+        };
+        let changed = if with_value {
+            paths.clone()
+        } else {
+            Vec::new()
+        };
         let mut res = None;
-        for p in paths {
-            res = Some(redis_key.arr_trim(p, start, stop)?);
+        if filter.is_some() {
+            for (p, indexes) in paths.into_iter().zip(kept) {
+                res = Some(redis_key.arr_keep(p, &indexes)?);
+            }
+        } else {
+            for p in paths {
+                res = Some(redis_key.arr_trim(p, start, stop)?);
+            }
         }
         redis_key.apply_changes(ctx, "json.arrtrim")?;
-        Ok(res.unwrap().into())
+        if with_value {
+            arrays_reply(redis_key.get_value()?.unwrap(), &changed)
+        } else {
+            Ok(res.unwrap().into())
+        }
     } else {
         Err(RedisError::String(format!(
             "Path '{}' does not exist or not an array",
@@ -1076,23 +3282,154 @@ pub fn command_json_obj_len<M: Manager>(
     let mut args = args.into_iter().skip(1);
     let key = args.next_arg()?;
     let path = backwards_compat_path(args.next_string()?);
+    let deep = match args.next() {
+        None => false,
+        Some(s) if s.try_as_str()?.eq_ignore_ascii_case("DEEP") => true,
+        Some(_) => return Err(RedisError::Str("ERR syntax error")),
+    };
 
     let key = manager.open_key_read(ctx, &key)?;
     match key.get_value()? {
         Some(doc) => Ok(RedisValue::Integer(
-            KeyValue::new(doc).obj_len(&path)? as i64
+            KeyValue::new(doc).obj_len(&path, deep)? as i64,
         )),
         None => Ok(RedisValue::Null),
     }
 }
 
-pub fn command_json_clear<M: Manager>(
+pub fn command_json_obj_rename<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = args.next_arg()?;
+    let path = backwards_compat_path(args.next_string()?);
+    let old_key = args.next_string()?;
+    let new_key = args.next_string()?;
+    let overwrite = match args.next() {
+        None => false,
+        Some(s) if s.try_as_str()?.eq_ignore_ascii_case("OVERWRITE") => true,
+        Some(_) => return Err(RedisError::Str("ERR syntax error")),
+    };
+    args.done()?;
+
+    let mut redis_key = open_key_write_mutable(&manager, ctx, key)?;
+    let append_only = redis_key.is_append_only()?;
+
+    let root = match redis_key.get_value()? {
+        Some(root) => root,
+        None => return missing_key(),
+    };
+
+    // Validate all the matched objects before renaming anything
+    let mut missing = false;
+    let mut conflict = false;
+    let paths = find_paths(&path, root, |v| {
+        if v.get_type() != SelectValueType::Object {
+            return false;
+        }
+        missing |= v.get_key(&old_key).is_none();
+        conflict |= old_key != new_key && v.get_key(&new_key).is_some();
+        true
+    })?;
+    if paths.is_empty() {
+        Err(RedisError::String(format!(
+            "Path '{}' does not exist or not an object",
+            path
+        )))
+    } else if missing {
+        Err(RedisError::String(format!(
+            "ERR key '{}' does not exist",
+            old_key
+        )))
+    } else if conflict && !overwrite {
+        Err(RedisError::String(format!(
+            "ERR key '{}' already exists",
+            new_key
+        )))
+    } else {
+        // The renamed member is removed, and a member of the new name replaced
+        for p in &paths {
+            for member in &[&old_key, &new_key] {
+                let mut member_path = p.clone();
+                member_path.push(member.to_string());
+                check_append_only(append_only, root, &member_path, true)?;
+            }
+        }
+        for p in paths {
+            redis_key.obj_rename(p, &old_key, &new_key)?;
+        }
+        redis_key.apply_changes(ctx, "json.objrename")?;
+        REDIS_OK
+    }
+}
+
+pub fn command_json_patch<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = args.next_arg()?;
+    let patch = args.next_string()?;
+    args.done()?;
+
+    let patch: Value = serde_json::from_str(&patch)
+        .map_err(|e| RedisError::String(format!("ERR invalid JSON Patch: {}", e)))?;
+
+    let mut redis_key = open_key_write_mutable(&manager, ctx, key)?;
+    let root = match redis_key.get_value()? {
+        Some(root) => root,
+        None => return missing_key(),
+    };
+    // The operations are applied to a copy, so a failing one leaves the document as it was
+    let mut doc = serde_json::to_value(root).map_err(Error::from)?;
+    patch::apply(&mut doc, &patch)?;
+    redis_key.set_value(
+        Vec::new(),
+        manager.from_str(&doc.to_string(), Format::JSON)?,
+    )?;
+    redis_key.apply_changes(ctx, "json.patch")?;
+    REDIS_OK
+}
+
+pub fn command_json_diff<M: Manager>(
     manager: M,
     ctx: &Context,
     args: Vec<RedisString>,
 ) -> RedisResult {
     let mut args = args.into_iter().skip(1);
     let key = args.next_arg()?;
+    let path = backwards_compat_path(args.next_string()?);
+    let other = RedisJSON::parse_str(&args.next_string()?, Format::JSON)?;
+    args.done()?;
+
+    let key = manager.open_key_read(ctx, &key)?;
+    match key.get_value()? {
+        Some(doc) => {
+            let doc = KeyValue::new(doc);
+            let ops = patch::diff(doc.get_first(&path)?, &other);
+            Ok(serde_json::to_string(&ops).map_err(Error::from)?.into())
+        }
+        None => Ok(RedisValue::Null),
+    }
+}
+
+pub fn command_json_clear<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let mut args = args.into_iter().skip(1).peekable();
+    let key = args.next_arg()?;
+    let verbose = match args.peek() {
+        Some(arg) if arg.try_as_str()?.eq_ignore_ascii_case(CMD_ARG_VERBOSE) => {
+            args.next();
+            true
+        }
+        _ => false,
+    };
     let paths = args
         .map(|arg| Path::new(arg.to_string()))
         .collect::<Vec<_>>();
@@ -1103,36 +3440,141 @@ pub fn command_json_clear<M: Manager>(
         paths
     };
 
-    let path = paths.first().unwrap().get_path();
+    let mut redis_key = open_key_write_mutable(&manager, ctx, key)?;
+    let append_only = redis_key.is_append_only()?;
 
-    // FIXME: handle multi paths
-    let mut redis_key = manager.open_key_write(ctx, key)?;
+    let root = match redis_key.get_value()? {
+        Some(root) => root,
+        None => return missing_key(),
+    };
 
-    let root = redis_key
-        .get_value()?
-        .ok_or_else(RedisError::nonexistent_key)?;
+    // Every path must exist, and may be cleared, before anything is cleared
+    for path in &paths {
+        let matches = find_paths(path.get_path(), root, |_v| true)?;
+        if matches.is_empty() {
+            return Err(RedisError::String(format!(
+                "Path '{}' does not exist",
+                path.get_path()
+            )));
+        }
+        check_append_only_paths(append_only, root, &matches)?;
+    }
 
-    let paths = find_paths(path, root, |_v| true)?;
-    if !paths.is_empty() {
-        let mut res = None;
-        for p in paths {
-            res = Some(redis_key.clear(p)?);
+    // Each path is matched after the previous ones were cleared, as they may overlap
+    let mut counts = Vec::with_capacity(paths.len());
+    for path in &paths {
+        let root = redis_key.get_value()?.unwrap();
+        let mut cleared = 0;
+        for p in find_paths(path.get_path(), root, |_v| true)? {
+            cleared += redis_key.clear(p)?;
         }
-        redis_key.apply_changes(ctx, "json.clear")?;
-        Ok(res.unwrap().into())
+        counts.push(cleared);
+    }
+    redis_key.apply_changes(ctx, "json.clear")?;
+    if verbose {
+        Ok(RedisValue::Array(
+            counts.into_iter().map(|c| (c as i64).into()).collect(),
+        ))
     } else {
-        Err(RedisError::String(format!(
-            "Path '{}' does not exist",
-            path
-        )))
+        Ok(counts.into_iter().sum::<usize>().into())
+    }
+}
+
+pub fn command_json_history<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = args.next_arg()?;
+    args.done()?;
+
+    let key = manager.open_key_read(ctx, &key)?;
+    match key.get_value()? {
+        Some(_) => Ok(key
+            .get_history()?
+            .into_iter()
+            .map(|v| RedisValue::BulkString(v.to_string()))
+            .collect::<Vec<_>>()
+            .into()),
+        None => Ok(RedisValue::Null),
+    }
+}
+
+pub fn command_json_undo<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = args.next_arg()?;
+    args.done()?;
+
+    let mut redis_key = open_key_write_mutable(&manager, ctx, key)?;
+    if redis_key.undo()? {
+        redis_key.apply_changes(ctx, "json.undo")?;
+        REDIS_OK
+    } else {
+        Ok(RedisValue::Null)
     }
 }
 
+fn command_json_set_frozen<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    args: Vec<RedisString>,
+    cmd: &str,
+    frozen: bool,
+) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = args.next_arg()?;
+    args.done()?;
+
+    let mut redis_key = manager.open_key_write(ctx, key)?;
+    redis_key.set_frozen(frozen)?;
+    redis_key.apply_changes(ctx, cmd)?;
+    REDIS_OK
+}
+
+pub fn command_json_freeze<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    command_json_set_frozen(manager, ctx, args, "json.freeze", true)
+}
+
+pub fn command_json_unfreeze<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    args: Vec<RedisString>,
+) -> RedisResult {
+    command_json_set_frozen(manager, ctx, args, "json.unfreeze", false)
+}
+
+///
+/// The subcommands of JSON.DEBUG that take a key, which always comes right after the subcommand
+///
+const DEBUG_KEY_SUBCOMMANDS: &[&str] = &["MEMORY", "VALIDATE", "JSONPATH", "REPR", "SIZESUMMARY"];
+
 pub fn command_json_debug<M: Manager>(
     manager: M,
     ctx: &Context,
     args: Vec<RedisString>,
 ) -> RedisResult {
+    if is_keys_position_request(ctx) {
+        let has_key = args.get(1).map_or(false, |a| {
+            a.try_as_str().map_or(false, |a| {
+                DEBUG_KEY_SUBCOMMANDS
+                    .iter()
+                    .any(|s| a.eq_ignore_ascii_case(s))
+            })
+        });
+        if has_key && args.len() > 2 {
+            key_at_pos(ctx, 2);
+        }
+        return REDIS_OK;
+    }
     let mut args = args.into_iter().skip(1);
     match args.next_string()?.to_uppercase().as_str() {
         "MEMORY" => {
@@ -1141,14 +3583,119 @@ pub fn command_json_debug<M: Manager>(
 
             let key = manager.open_key_read(ctx, &key)?;
             let value = match key.get_value()? {
-                Some(doc) => manager.get_memory(KeyValue::new(doc).get_first(&path)?)?,
+                Some(doc) => {
+                    let mut size = manager.get_memory(KeyValue::new(doc).get_first(&path)?)?;
+                    // The history belongs to the whole document
+                    if path == JSON_ROOT_PATH {
+                        size += key.get_history()?.iter().map(|v| v.len()).sum::<usize>();
+                    }
+                    size
+                }
                 None => 0,
             };
             Ok(value.into())
         }
+        "PARSE" => {
+            let json = args.next_string()?;
+            args.done()?;
+            manager.from_str(&json, Format::JSON)?;
+            REDIS_OK
+        }
+        "KEYSPACE" => json_debug_keyspace(manager, ctx, args),
+        "VALIDATE" => {
+            let key = args.next_arg()?;
+            args.done()?;
+
+            let key = manager.open_key_read(ctx, &key)?;
+            match key.get_value()? {
+                Some(doc) => {
+                    let mut path = JSON_ROOT_PATH.to_string();
+                    match validate_value(doc, &mut path) {
+                        Ok(()) => REDIS_OK,
+                        Err(problem) => Ok(format!("{}: {}", path, problem).into()),
+                    }
+                }
+                None => Ok(RedisValue::Null),
+            }
+        }
+        "JSONPATH" => {
+            let key = args.next_arg()?;
+            let path = backwards_compat_path(args.next_string()?);
+            args.done()?;
+
+            let key = manager.open_key_read(ctx, &key)?;
+            match key.get_value()? {
+                Some(doc) => {
+                    let matches = find_paths(&path, doc, |_| true)?;
+                    let doc = KeyValue::new(doc);
+                    Ok(matches
+                        .iter()
+                        .filter_map(|m| doc.walk_path(m).map(|(path, _)| path))
+                        .collect::<Vec<_>>()
+                        .into())
+                }
+                None => Ok(RedisValue::Null),
+            }
+        }
+        "REPR" => {
+            let key = args.next_arg()?;
+            let path = args
+                .next_string()
+                .map_or_else(|_| JSON_ROOT_PATH.to_string(), backwards_compat_path);
+            args.done()?;
+
+            let key = manager.open_key_read(ctx, &key)?;
+            match key.get_value()? {
+                Some(doc) => {
+                    let repr = repr_value(KeyValue::new(doc).get_first(&path)?);
+                    Ok(repr.to_string().into())
+                }
+                None => Ok(RedisValue::Null),
+            }
+        }
+        "SIZESUMMARY" => {
+            let key = args.next_arg()?;
+            args.done()?;
+
+            let key = manager.open_key_read(ctx, &key)?;
+            let doc = match key.get_value()? {
+                Some(doc) => doc,
+                None => return Ok(RedisValue::Null),
+            };
+            // Every top-level member or element with the memory of its subtree, in order
+            let mut summary = Vec::new();
+            match doc.get_type() {
+                SelectValueType::Object => {
+                    for (k, v) in doc.items().unwrap() {
+                        summary.push(RedisValue::BulkString(k.to_string()));
+                        summary.push(RedisValue::Integer(manager.get_memory(v)? as i64));
+                    }
+                }
+                SelectValueType::Array => {
+                    for (i, v) in doc.values().unwrap().enumerate() {
+                        summary.push(RedisValue::Integer(i as i64));
+                        summary.push(RedisValue::Integer(manager.get_memory(v)? as i64));
+                    }
+                }
+                _ => {
+                    return Err(RedisError::Str(
+                        "ERR SIZESUMMARY requires an object or array root",
+                    ))
+                }
+            }
+            Ok(RedisValue::Array(summary))
+        }
         "HELP" => {
             let results = vec![
                 "MEMORY <key> [path] - reports memory usage",
+                "PARSE <json>        - validates a JSON document without storing it",
+                "KEYSPACE [cursor] [MATCH <pattern>] [COUNT <count>]",
+                "                    - incrementally lists JSON keys with their memory usage",
+                "VALIDATE <key>      - checks the integrity of a stored document",
+                "JSONPATH <key> <path>",
+                "                    - lists the normalized paths of the values matched by path",
+                "REPR <key> [path]   - shows the stored type of every value",
+                "SIZESUMMARY <key>   - reports the memory usage of every top-level field",
                 "HELP                - this message",
             ];
             Ok(results.into())
@@ -1159,6 +3706,179 @@ pub fn command_json_debug<M: Manager>(
     }
 }
 
+///
+/// Walks a stored value checking the invariants every JSON value must hold.
+/// On failure `path` is left pointing at the offending value.
+///
+fn validate_value<V: SelectValue>(value: &V, path: &mut String) -> Result<(), &'static str> {
+    match value.get_type() {
+        SelectValueType::Double if !value.get_double().is_finite() => Err("number is not finite"),
+        SelectValueType::String if std::str::from_utf8(value.as_str().as_bytes()).is_err() => {
+            Err("string is not valid UTF-8")
+        }
+        SelectValueType::Array => {
+            for (i, v) in value.values().unwrap().enumerate() {
+                let len = path.len();
+                path.push_str(&format!("[{}]", i));
+                validate_value(v, path)?;
+                path.truncate(len);
+            }
+            if value.len() != Some(value.values().unwrap().count()) {
+                return Err("array length does not match its elements");
+            }
+            Ok(())
+        }
+        SelectValueType::Object => {
+            for (k, v) in value.items().unwrap() {
+                let len = path.len();
+                path.push_str(&key_segment(k));
+                validate_value(v, path)?;
+                path.truncate(len);
+            }
+            if value.len() != Some(value.items().unwrap().count()) {
+                return Err("object length does not match its members");
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+///
+/// The value with every scalar replaced by the name of the type it is stored as, telling e.g.
+/// integers (`long`) from floating point numbers (`double`)
+///
+fn repr_value<V: SelectValue>(value: &V) -> Value {
+    match value.get_type() {
+        SelectValueType::Null => "null".into(),
+        SelectValueType::Bool => "bool".into(),
+        SelectValueType::Long => "long".into(),
+        SelectValueType::Double => "double".into(),
+        SelectValueType::String => "string".into(),
+        SelectValueType::Array => Value::Array(value.values().unwrap().map(repr_value).collect()),
+        SelectValueType::Object => Value::Object(
+            value
+                .items()
+                .unwrap()
+                .map(|(k, v)| (k.to_string(), repr_value(v)))
+                .collect(),
+        ),
+    }
+}
+
+///
+/// Runs a single SCAN iteration and replies with the next cursor followed by
+/// the name and memory usage of every JSON key found in this iteration
+///
+fn json_debug_keyspace<M: Manager>(
+    manager: M,
+    ctx: &Context,
+    mut args: impl Iterator<Item = RedisString>,
+) -> RedisResult {
+    let mut cursor = "0".to_string();
+    let mut pattern = None;
+    let mut count = None;
+    let mut first = true;
+    while let Some(arg) = args.next() {
+        match arg.try_as_str()? {
+            arg if arg.eq_ignore_ascii_case("MATCH") => pattern = Some(args.next_arg()?),
+            arg if arg.eq_ignore_ascii_case("COUNT") => match args.next_i64()? {
+                n if n > 0 => count = Some(n.to_string()),
+                _ => return Err(RedisError::Str("ERR syntax error")),
+            },
+            arg if first && arg.parse::<u64>().is_ok() => cursor = arg.to_string(),
+            _ => return Err(RedisError::Str("ERR syntax error")),
+        }
+        first = false;
+    }
+
+    // The pattern and the key names are passed as they are, since they need not be UTF-8
+    let mut scan_args = vec![RedisString::create(ctx.ctx, &cursor)];
+    if let Some(pattern) = pattern {
+        scan_args.push(RedisString::create(ctx.ctx, "MATCH"));
+        scan_args.push(pattern);
+    }
+    if let Some(count) = &count {
+        scan_args.push(RedisString::create(ctx.ctx, "COUNT"));
+        scan_args.push(RedisString::create(ctx.ctx, count));
+    }
+    let mut argv: Vec<_> = scan_args.iter().map(|s| s.inner).collect();
+
+    let cmd = CString::new("SCAN").unwrap();
+    let fmt = CString::new("v").unwrap();
+    let reply = unsafe {
+        raw::RedisModule_Call.unwrap()(
+            ctx.ctx,
+            cmd.as_ptr(),
+            fmt.as_ptr(),
+            argv.as_mut_ptr(),
+            argv.len(),
+        )
+    };
+    let result = json_debug_keyspace_reply(&manager, ctx, reply);
+    if !reply.is_null() {
+        raw::free_call_reply(reply);
+    }
+    result
+}
+
+///
+/// Replies with the JSON keys among the key names of a SCAN reply, along with their memory usage
+///
+fn json_debug_keyspace_reply<M: Manager>(
+    manager: &M,
+    ctx: &Context,
+    reply: *mut raw::RedisModuleCallReply,
+) -> RedisResult {
+    let unexpected = || RedisError::Str("ERR unexpected SCAN reply");
+    if reply.is_null()
+        || raw::call_reply_type(reply) != raw::ReplyType::Array
+        || raw::call_reply_length(reply) != 2
+    {
+        return Err(unexpected());
+    }
+    let next_cursor = raw::call_reply_array_element(reply, 0);
+    let keys = raw::call_reply_array_element(reply, 1);
+    if raw::call_reply_type(next_cursor) != raw::ReplyType::String
+        || raw::call_reply_type(keys) != raw::ReplyType::Array
+    {
+        return Err(unexpected());
+    }
+
+    let mut results = Vec::new();
+    for i in 0..raw::call_reply_length(keys) {
+        let element = raw::call_reply_array_element(keys, i);
+        if raw::call_reply_type(element) != raw::ReplyType::String {
+            continue;
+        }
+        let key_name = unsafe {
+            let inner = raw::RedisModule_CreateStringFromCallReply.unwrap()(element);
+            let key_name = RedisString::new(ctx.ctx, inner);
+            // The new RedisString holds its own reference
+            raw::RedisModule_FreeString.unwrap()(ctx.ctx, inner);
+            key_name
+        };
+        let key = manager.open_key_read(ctx, &key_name)?;
+        // Keys of other types fail the type check and are skipped
+        if let Ok(Some(doc)) = key.get_value() {
+            let memory = manager.get_memory(doc)?;
+            results.push(RedisValue::StringBuffer(call_reply_bytes(element)));
+            results.push(memory.into());
+        }
+    }
+
+    Ok(RedisValue::Array(vec![
+        RedisValue::StringBuffer(call_reply_bytes(next_cursor)),
+        RedisValue::Array(results),
+    ]))
+}
+
+fn call_reply_bytes(reply: *mut raw::RedisModuleCallReply) -> Vec<u8> {
+    let mut len = 0;
+    let ptr = raw::call_reply_string_ptr(reply, &mut len);
+    unsafe { slice::from_raw_parts(ptr as *const u8, len) }.to_vec()
+}
+
 pub fn command_json_resp<M: Manager>(
     manager: M,
     ctx: &Context,
@@ -0,0 +1,108 @@
+// Module configuration.
+//
+// Configuration options are passed as name/value pairs in the module's load time arguments, e.g.:
+//   loadmodule rejson.so json-max-reply-bytes 1048576
+
+use redis_module::{Context, RedisString, Status};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MissingKeyBehavior {
+    // Reply with an error (the default)
+    Error,
+    // Reply with a null and change nothing
+    Null,
+}
+
+pub struct Config {
+    // Maximal size in bytes of a serialized JSON.GET reply (0 is unlimited)
+    pub max_reply_bytes: usize,
+    // Maximal length of an array grown by JSON.ARRAPPEND or JSON.ARRINSERT (0 is unlimited)
+    pub max_array_len: usize,
+    // Reply of the commands modifying an existing value when the key does not exist
+    pub missing_key_behavior: MissingKeyBehavior,
+    // Memoize the last JSON.GET reply of every key until it is modified
+    pub query_cache: bool,
+    // Formatting of the JSON.GET replies that don't set INDENT, NEWLINE or SPACE themselves
+    pub default_indent: Option<String>,
+    pub default_newline: Option<String>,
+    pub default_space: Option<String>,
+    // Serialize replies with an explicit stack rather than recursion, for arbitrarily deep documents
+    pub iterative_serializer: bool,
+}
+
+static mut CONFIG: Config = Config {
+    max_reply_bytes: 0,
+    max_array_len: 0,
+    missing_key_behavior: MissingKeyBehavior::Error,
+    query_cache: false,
+    default_indent: None,
+    default_newline: None,
+    default_space: None,
+    iterative_serializer: false,
+};
+
+pub fn get() -> &'static Config {
+    unsafe { &CONFIG }
+}
+
+pub fn init(ctx: &Context, args: &[RedisString]) -> Status {
+    match parse_args(ctx, args) {
+        Ok(()) => Status::Ok,
+        Err(e) => {
+            ctx.log_warning(&format!("Failed loading RedisJSON configuration: {}", e));
+            Status::Err
+        }
+    }
+}
+
+fn parse_args(ctx: &Context, args: &[RedisString]) -> Result<(), String> {
+    let config = unsafe { &mut CONFIG };
+    let mut args = args.iter();
+    while let Some(name) = args.next() {
+        let name = name.to_string();
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for '{}'", name))?
+            .to_string();
+        match name.to_lowercase().as_str() {
+            "json-max-reply-bytes" => config.max_reply_bytes = parse_usize(&name, &value)?,
+            "json-max-array-len" => config.max_array_len = parse_usize(&name, &value)?,
+            "json-missing-key-behavior" => {
+                config.missing_key_behavior = parse_missing_key_behavior(&name, &value)?
+            }
+            "json-query-cache" => config.query_cache = parse_bool(&name, &value)?,
+            "json-default-indent" => config.default_indent = Some(value),
+            "json-default-newline" => config.default_newline = Some(value),
+            "json-default-space" => config.default_space = Some(value),
+            "json-iterative-serializer" => config.iterative_serializer = parse_bool(&name, &value)?,
+            // Arguments might belong to a module embedding RedisJSON as a library
+            _ => ctx.log_notice(&format!("Ignoring unknown argument '{}'", name)),
+        }
+    }
+    Ok(())
+}
+
+fn parse_usize(name: &str, value: &str) -> Result<usize, String> {
+    value
+        .parse::<usize>()
+        .map_err(|_| format!("'{}' expects a non negative integer, got '{}'", name, value))
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("'{}' expects 'yes' or 'no', got '{}'", name, value)),
+    }
+}
+
+fn parse_missing_key_behavior(name: &str, value: &str) -> Result<MissingKeyBehavior, String> {
+    match value.to_lowercase().as_str() {
+        "error" => Ok(MissingKeyBehavior::Error),
+        "null" => Ok(MissingKeyBehavior::Null),
+        _ => Err(format!(
+            "'{}' expects 'error' or 'null', got '{}'",
+            name, value
+        )),
+    }
+}
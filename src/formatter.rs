@@ -69,6 +69,8 @@ impl RedisJsonFormatter {
     }
 }
 
+// Strings are escaped by the default `Formatter` methods, which escape every control character
+// as RFC 8259 requires
 impl Formatter for RedisJsonFormatter {
     fn begin_array<W: ?Sized>(&mut self, writer: &mut W) -> io::Result<()>
     where
@@ -0,0 +1,30 @@
+// Idempotency tokens of recently applied writes.
+//
+// Every document keeps the (token, reply) pairs of its most recent idempotent writes, so a retried
+// command is recognized and answered with the reply of its first execution instead of being
+// applied again. The tokens are part of the document: they are saved with it, replicated along
+// with the writes that record them and go away with the key. The oldest tokens are evicted first,
+// so a retry is only deduplicated while its token is still recorded.
+
+use std::collections::VecDeque;
+
+const MAX_TOKENS: usize = 1024;
+
+pub type Tokens = VecDeque<(String, usize)>;
+
+///
+/// Returns the reply recorded for `token`, if it was already applied
+///
+pub fn get(tokens: &Tokens, token: &str) -> Option<usize> {
+    tokens
+        .iter()
+        .find(|(t, _)| t == token)
+        .map(|(_, reply)| *reply)
+}
+
+pub fn record(tokens: &mut Tokens, token: String, reply: usize) {
+    tokens.push_back((token, reply));
+    while tokens.len() > MAX_TOKENS {
+        tokens.pop_front();
+    }
+}
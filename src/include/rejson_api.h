@@ -63,6 +63,12 @@ typedef struct RedisJSONAPI_V1 {
   // Return 1 if type of key is JSON
   int (*isJSON)(RedisModuleKey *redis_key);
 
+  // Rewind the iterator so `next` returns its results again from the first one
+  void (*resetIter)(JSONResultsIterator iter);
+
+  // Set `value` to the value of `key` in an Object, or to NULL if it has no such key
+  int (*getObjectValue)(RedisJSON json, const char *key, RedisJSON *value);
+
 } RedisJSONAPI_V1;
 
 #ifdef __cplusplus
@@ -6,7 +6,7 @@ use crate::c_api::{
     get_llapi_ctx, json_api_free_iter, json_api_get, json_api_get_at, json_api_get_boolean,
     json_api_get_double, json_api_get_int, json_api_get_json, json_api_get_len,
     json_api_get_string, json_api_get_type, json_api_is_json, json_api_len, json_api_next,
-    json_api_open_key_internal, LLAPI_CTX,
+    json_api_open_key_internal, json_api_reset_iter, LLAPI_CTX,
 };
 
 #[cfg(not(feature = "as-library"))]
@@ -19,11 +19,15 @@ mod array_index;
 mod backward;
 pub mod c_api;
 pub mod commands;
+pub mod config;
 pub mod error;
 mod formatter;
+mod idempotency;
 pub mod manager;
 mod nodevisitor;
+mod patch;
 pub mod redisjson;
+mod transform;
 
 use crate::redisjson::Format;
 pub const REDIS_JSON_TYPE_VERSION: i32 = 3;
@@ -36,11 +40,11 @@ pub static REDIS_JSON_TYPE: RedisType = RedisType::new(
 
         rdb_load: Some(redisjson::type_methods::rdb_load),
         rdb_save: Some(redisjson::type_methods::rdb_save),
-        aof_rewrite: None, // TODO add support
+        // TODO add support, until then AOF rewrites rely on the RDB preamble
+        aof_rewrite: None,
         free: Some(redisjson::type_methods::free),
 
-        // Currently unused by Redis
-        mem_usage: None,
+        mem_usage: Some(redisjson::type_methods::mem_usage),
         digest: None,
 
         // Auxiliary data (v2)
@@ -95,7 +99,30 @@ macro_rules! redis_json_module_create {(
         ///         [INDENT indentation-string]
         ///         [NEWLINE line-break-string]
         ///         [SPACE space-string]
-        ///         [path ...]
+        ///         [PRETTY]
+        ///         [NOESCAPE]
+        ///         [STRINGNUMS]
+        ///         [WITHCOUNT]
+        ///         [WITHPATHS]
+        ///         [WRAP]
+        ///         [SKIPNULL]
+        ///         [MATCHLIMIT n]
+        ///         [ONLYKEYS]
+        ///         [COERCENUM]
+        ///         [NDJSON]
+        ///         [CASEINSENSITIVE]
+        ///         [SIZE]
+        ///         [FLATMAP]
+        ///         [WITHINDICES]
+        ///         [STATS]
+        ///         [REDACT regex]
+        ///         [CSV]
+        ///         [ETAG]
+        ///         [COERCESCHEMA schema]
+        ///         [WITHVERSION]
+        ///         [COMPRESS GZIP]
+        ///         [path ... | BASE base-path THEN path | POINTER pointer]
+        ///         [EXCLUDE count exclude-path ...]
         ///
         /// TODO add support for multi path
         fn json_get(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
@@ -109,7 +136,7 @@ macro_rules! redis_json_module_create {(
         }
 
         ///
-        /// JSON.SET <key> <path> <json> [NX | XX | FORMAT <format>]
+        /// JSON.SET <key> <path> <json> | PATHS <path> [path ...] VALUE <json> | <path> FROMKEY <src> <src-path> [NX | XX | FORMAT <format> | MERGEARRAY | EXTEND | MKPATH | EX <seconds> | PX <milliseconds> | KEEPHISTORY <n> | APPENDONLY]
         ///
         fn json_set(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
             $pre_command_function_expr(ctx, &args);
@@ -122,7 +149,8 @@ macro_rules! redis_json_module_create {(
         }
 
         ///
-        /// JSON.MGET <key> [key ...] <path>
+        /// JSON.MGET <key> [key ...] <path> [STRICT]
+        /// JSON.MGET <key> [key ...] PATHS <count> <path> [path ...] [STRICT]
         ///
         fn json_mget(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
             $pre_command_function_expr(ctx, &args);
@@ -134,6 +162,19 @@ macro_rules! redis_json_module_create {(
             }
         }
 
+        ///
+        /// JSON.STRPREPEND <key> [path] <json-string>
+        ///
+        fn json_str_prepend(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+            $pre_command_function_expr(ctx, &args);
+            let m = $get_manager_expr;
+            match m {
+                Some(mngr) => commands::command_json_str_prepend(mngr, ctx, args),
+                None => commands::command_json_str_prepend(manager::RedisJsonKeyManager{phantom:PhantomData}, ctx, args),
+
+            }
+        }
+
         ///
         /// JSON.STRLEN <key> [path]
         ///
@@ -148,7 +189,7 @@ macro_rules! redis_json_module_create {(
         }
 
         ///
-        /// JSON.TYPE <key> [path]
+        /// JSON.TYPE <key> [path [path ...]] [SIMPLETYPES]
         ///
         fn json_type(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
             $pre_command_function_expr(ctx, &args);
@@ -161,7 +202,33 @@ macro_rules! redis_json_module_create {(
         }
 
         ///
-        /// JSON.NUMINCRBY <key> <path> <number>
+        /// JSON.EXISTS <key> <path>
+        ///
+        fn json_exists(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+            $pre_command_function_expr(ctx, &args);
+            let m = $get_manager_expr;
+            match m {
+                Some(mngr) => commands::command_json_exists(mngr, ctx, args),
+                None => commands::command_json_exists(manager::RedisJsonKeyManager{phantom:PhantomData}, ctx, args),
+
+            }
+        }
+
+        ///
+        /// JSON.COUNT <key> <path>
+        ///
+        fn json_count(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+            $pre_command_function_expr(ctx, &args);
+            let m = $get_manager_expr;
+            match m {
+                Some(mngr) => commands::command_json_count(mngr, ctx, args),
+                None => commands::command_json_count(manager::RedisJsonKeyManager{phantom:PhantomData}, ctx, args),
+
+            }
+        }
+
+        ///
+        /// JSON.NUMINCRBY <key> <path> <number> [<path> <number> ...] [GETOLD] [DECIMAL]
         ///
         fn json_num_incrby(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
             $pre_command_function_expr(ctx, &args);
@@ -174,7 +241,7 @@ macro_rules! redis_json_module_create {(
         }
 
         ///
-        /// JSON.NUMMULTBY <key> <path> <number>
+        /// JSON.NUMMULTBY <key> <path> <number> [GETOLD] [DECIMAL]
         ///
         fn json_num_multby(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
             $pre_command_function_expr(ctx, &args);
@@ -187,7 +254,7 @@ macro_rules! redis_json_module_create {(
         }
 
         ///
-        /// JSON.NUMPOWBY <key> <path> <number>
+        /// JSON.NUMPOWBY <key> <path> <number> [GETOLD]
         ///
         fn json_num_powby(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
             $pre_command_function_expr(ctx, &args);
@@ -199,6 +266,19 @@ macro_rules! redis_json_module_create {(
             }
         }
 
+        ///
+        /// JSON.COUNTER <key> <path> [BY <number>] [DEFAULT <number>]
+        ///
+        fn json_counter(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+            $pre_command_function_expr(ctx, &args);
+            let m = $get_manager_expr;
+            match m {
+                Some(mngr) => commands::command_json_counter(mngr, ctx, args),
+                None => commands::command_json_counter(manager::RedisJsonKeyManager{phantom:PhantomData}, ctx, args),
+
+            }
+        }
+
         //
         /// JSON.TOGGLE <key> <path>
         fn json_bool_toggle(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
@@ -225,7 +305,7 @@ macro_rules! redis_json_module_create {(
         }
 
         ///
-        /// JSON.ARRAPPEND <key> <path> <json> [json ...]
+        /// JSON.ARRAPPEND <key> <path> [IDEMPOTENT token] [TYPED] [WHERE filter] [UPSERT] [WITHVALUE] [MAXLEN n] <json> [json ...]
         ///
         fn json_arr_append(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
             $pre_command_function_expr(ctx, &args);
@@ -238,9 +318,10 @@ macro_rules! redis_json_module_create {(
         }
 
         ///
-        /// JSON.ARRINDEX <key> <path> <json-scalar> [start [stop]]
+        /// JSON.ARRINDEX <key> <path> <json-value> | PREDICATE <filter> [start [stop]]
         ///
-        /// scalar - number, string, Boolean (true or false), or null
+        /// json-value - any JSON value, arrays and objects matching deeply equal elements
+        /// filter - a JSONPath filter expression on the elements, e.g. `@.price > 100`
         ///
         fn json_arr_index(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
             $pre_command_function_expr(ctx, &args);
@@ -253,7 +334,7 @@ macro_rules! redis_json_module_create {(
         }
 
         ///
-        /// JSON.ARRINSERT <key> <path> <index> <json> [json ...]
+        /// JSON.ARRINSERT <key> <path> <index> | INDEXOF <needle> [WHERE filter] [WITHVALUE] <json> [json ...]
         ///
         fn json_arr_insert(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
             $pre_command_function_expr(ctx, &args);
@@ -278,7 +359,7 @@ macro_rules! redis_json_module_create {(
         }
 
         ///
-        /// JSON.ARRPOP <key> [path [index]]
+        /// JSON.ARRPOP <key> [path [index [count]]]
         ///
         fn json_arr_pop(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
             $pre_command_function_expr(ctx, &args);
@@ -291,7 +372,7 @@ macro_rules! redis_json_module_create {(
         }
 
         ///
-        /// JSON.ARRTRIM <key> <path> <start> <stop>
+        /// JSON.ARRTRIM <key> <path> <start> <stop> | KEEP <filter> [WITHVALUE]
         ///
         fn json_arr_trim(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
             $pre_command_function_expr(ctx, &args);
@@ -317,7 +398,7 @@ macro_rules! redis_json_module_create {(
         }
 
         ///
-        /// JSON.OBJLEN <key> [path]
+        /// JSON.OBJLEN <key> [path] [DEEP]
         ///
         fn json_obj_len(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
             $pre_command_function_expr(ctx, &args);
@@ -330,7 +411,20 @@ macro_rules! redis_json_module_create {(
         }
 
         ///
-        /// JSON.CLEAR <key> [path ...]
+        /// JSON.OBJRENAME <key> <path> <oldkey> <newkey> [OVERWRITE]
+        ///
+        fn json_obj_rename(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+            $pre_command_function_expr(ctx, &args);
+            let m = $get_manager_expr;
+            match m {
+                Some(mngr) => commands::command_json_obj_rename(mngr, ctx, args),
+                None => commands::command_json_obj_rename(manager::RedisJsonKeyManager{phantom:PhantomData}, ctx, args),
+
+            }
+        }
+
+        ///
+        /// JSON.CLEAR <key> [VERBOSE] [path ...]
         ///
         fn json_clear(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
             $pre_command_function_expr(ctx, &args);
@@ -342,11 +436,91 @@ macro_rules! redis_json_module_create {(
             }
         }
 
+        ///
+        /// JSON.PATCH <key> <json-patch>
+        ///
+        fn json_patch(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+            $pre_command_function_expr(ctx, &args);
+            let m = $get_manager_expr;
+            match m {
+                Some(mngr) => commands::command_json_patch(mngr, ctx, args),
+                None => commands::command_json_patch(manager::RedisJsonKeyManager{phantom:PhantomData}, ctx, args),
+
+            }
+        }
+
+        ///
+        /// JSON.DIFF <key> <path> <json>
+        ///
+        fn json_diff(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+            $pre_command_function_expr(ctx, &args);
+            let m = $get_manager_expr;
+            match m {
+                Some(mngr) => commands::command_json_diff(mngr, ctx, args),
+                None => commands::command_json_diff(manager::RedisJsonKeyManager{phantom:PhantomData}, ctx, args),
+
+            }
+        }
+
+        ///
+        /// JSON.HISTORY <key>
+        ///
+        fn json_history(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+            $pre_command_function_expr(ctx, &args);
+            let m = $get_manager_expr;
+            match m {
+                Some(mngr) => commands::command_json_history(mngr, ctx, args),
+                None => commands::command_json_history(manager::RedisJsonKeyManager{phantom:PhantomData}, ctx, args),
+
+            }
+        }
+
+        ///
+        /// JSON.UNDO <key>
+        ///
+        fn json_undo(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+            $pre_command_function_expr(ctx, &args);
+            let m = $get_manager_expr;
+            match m {
+                Some(mngr) => commands::command_json_undo(mngr, ctx, args),
+                None => commands::command_json_undo(manager::RedisJsonKeyManager{phantom:PhantomData}, ctx, args),
+
+            }
+        }
+
+        ///
+        /// JSON.FREEZE <key>
+        ///
+        fn json_freeze(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+            $pre_command_function_expr(ctx, &args);
+            let m = $get_manager_expr;
+            match m {
+                Some(mngr) => commands::command_json_freeze(mngr, ctx, args),
+                None => commands::command_json_freeze(manager::RedisJsonKeyManager{phantom:PhantomData}, ctx, args),
+
+            }
+        }
+
+        ///
+        /// JSON.UNFREEZE <key>
+        ///
+        fn json_unfreeze(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
+            $pre_command_function_expr(ctx, &args);
+            let m = $get_manager_expr;
+            match m {
+                Some(mngr) => commands::command_json_unfreeze(mngr, ctx, args),
+                None => commands::command_json_unfreeze(manager::RedisJsonKeyManager{phantom:PhantomData}, ctx, args),
+
+            }
+        }
+
         ///
         /// JSON.DEBUG <subcommand & arguments>
         ///
         /// subcommands:
         /// MEMORY <key> [path]
+        /// PARSE <json>
+        /// KEYSPACE [cursor] [MATCH <pattern>] [COUNT <count>]
         /// HELP
         ///
         fn json_debug(ctx: &Context, args: Vec<RedisString>) -> RedisResult {
@@ -398,6 +572,9 @@ macro_rules! redis_json_module_create {(
         }
 
         fn intialize(ctx: &Context, args: &Vec<RedisString>) -> Status {
+            if let Status::Err = $crate::config::init(ctx, args) {
+                return Status::Err;
+            }
             export_shared_api(ctx);
             $init_func(ctx, args)
         }
@@ -411,13 +588,17 @@ macro_rules! redis_json_module_create {(
                 ["json.del", json_del, "write", 1,1,1],
                 ["json.get", json_get, "readonly", 1,1,1],
                 ["json.mget", json_mget, "readonly", 1,1,1],
-                ["json.set", json_set, "write deny-oom", 1,1,1],
+                ["json.set", json_set, "write deny-oom getkeys-api", 1,1,1],
                 ["json.type", json_type, "readonly", 1,1,1],
+                ["json.exists", json_exists, "readonly", 1,1,1],
+                ["json.count", json_count, "readonly", 1,1,1],
                 ["json.numincrby", json_num_incrby, "write", 1,1,1],
                 ["json.toggle", json_bool_toggle, "write deny-oom", 1,1,1],
                 ["json.nummultby", json_num_multby, "write", 1,1,1],
                 ["json.numpowby", json_num_powby, "write", 1,1,1],
+                ["json.counter", json_counter, "write deny-oom", 1,1,1],
                 ["json.strappend", json_str_append, "write deny-oom", 1,1,1],
+                ["json.strprepend", json_str_prepend, "write deny-oom", 1,1,1],
                 ["json.strlen", json_str_len, "readonly", 1,1,1],
                 ["json.arrappend", json_arr_append, "write deny-oom", 1,1,1],
                 ["json.arrindex", json_arr_index, "readonly", 1,1,1],
@@ -427,8 +608,15 @@ macro_rules! redis_json_module_create {(
                 ["json.arrtrim", json_arr_trim, "write", 1,1,1],
                 ["json.objkeys", json_obj_keys, "readonly", 1,1,1],
                 ["json.objlen", json_obj_len, "readonly", 1,1,1],
+                ["json.objrename", json_obj_rename, "write", 1,1,1],
                 ["json.clear", json_clear, "write", 1,1,1],
-                ["json.debug", json_debug, "readonly", 1,1,1],
+                ["json.patch", json_patch, "write deny-oom", 1,1,1],
+                ["json.diff", json_diff, "readonly", 1,1,1],
+                ["json.freeze", json_freeze, "write", 1,1,1],
+                ["json.history", json_history, "readonly", 1,1,1],
+                ["json.undo", json_undo, "write", 1,1,1],
+                ["json.unfreeze", json_unfreeze, "write", 1,1,1],
+                ["json.debug", json_debug, "readonly getkeys-api", 0,0,0],
                 ["json.forget", json_del, "write", 1,1,1],
                 ["json.resp", json_resp, "readonly", 1,1,1],
                 ["json._cacheinfo", json_cache_info, "readonly", 1,1,1],
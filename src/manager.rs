@@ -1,25 +1,33 @@
-use jsonpath_lib::select::select_value::SelectValue;
+use jsonpath_lib::select::select_value::{SelectValue, SelectValueType};
 use serde_json::map::Entry;
 use serde_json::{Number, Value};
 
 use redis_module::key::{verify_type, RedisKey, RedisKeyWritable};
-use redis_module::raw::{RedisModuleKey, Status};
+use redis_module::raw::{self, RedisModuleKey, RedisModuleString, Status};
 use redis_module::rediserror::RedisError;
 use redis_module::{Context, NotifyEvent, RedisString};
 
 use std::marker::PhantomData;
 
-use crate::redisjson::RedisJSON;
+use crate::redisjson::{QueryCache, RedisJSON};
 use crate::Format;
 use crate::REDIS_JSON_TYPE;
 
 use crate::error::Error;
+use crate::idempotency::{self, Tokens};
 use bson::decode_document;
 use std::io::Cursor;
 
 use crate::array_index::ArrayIndex;
+use crate::commands::KeyValue;
 
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+use std::ffi::CString;
 use std::mem;
+use std::os::raw::{c_int, c_longlong};
+use std::str::FromStr;
+use std::time::Duration;
 
 pub struct SetUpdateInfo {
     pub path: Vec<String>,
@@ -30,27 +38,57 @@ pub struct AddUpdateInfo {
     pub key: String,
 }
 
+pub struct ExtendUpdateInfo {
+    pub path: Vec<String>,
+    pub index: usize,
+}
+
 pub enum UpdateInfo {
     SUI(SetUpdateInfo),
     AUI(AddUpdateInfo),
+    EUI(ExtendUpdateInfo),
 }
 
 pub trait ReadHolder<V: SelectValue> {
     fn get_value(&self) -> Result<Option<&V>, RedisError>;
+    fn get_cached(&self, query: &[String]) -> Result<Option<String>, RedisError>;
+    fn set_cached(&self, query: Vec<String>, reply: String) -> Result<(), RedisError>;
+    fn get_history(&self) -> Result<Vec<&str>, RedisError>;
+    fn get_version(&self) -> Result<u64, RedisError>;
+    fn get_token_reply(&self, token: &str) -> Result<Option<usize>, RedisError>;
 }
 
 pub trait WriteHolder<O: Clone, V: SelectValue> {
     fn delete(&mut self) -> Result<(), RedisError>;
+    fn set_expire(
+        &mut self,
+        expire: Duration,
+        args: &[*mut RedisModuleString],
+    ) -> Result<(), RedisError>;
+    fn is_frozen(&mut self) -> Result<bool, RedisError>;
+    fn set_frozen(&mut self, frozen: bool) -> Result<(), RedisError>;
+    fn is_append_only(&mut self) -> Result<bool, RedisError>;
+    fn set_append_only(&mut self) -> Result<(), RedisError>;
+    fn snapshot(&mut self) -> Result<Option<String>, RedisError>;
+    fn push_history(&mut self, version: String, max: usize) -> Result<(), RedisError>;
+    fn record_token(&mut self, token: String, reply: usize) -> Result<(), RedisError>;
+    fn undo(&mut self) -> Result<bool, RedisError>;
     fn get_value(&mut self) -> Result<Option<&mut V>, RedisError>;
     fn set_value(&mut self, path: Vec<String>, v: O) -> Result<bool, RedisError>;
     fn dict_add(&mut self, path: Vec<String>, key: &str, v: O) -> Result<bool, RedisError>;
+    fn arr_set_extend(&mut self, path: Vec<String>, index: usize, v: O)
+        -> Result<bool, RedisError>;
     fn delete_path(&mut self, path: Vec<String>) -> Result<bool, RedisError>;
     fn incr_by(&mut self, path: Vec<String>, num: &str) -> Result<Number, RedisError>;
     fn mult_by(&mut self, path: Vec<String>, num: &str) -> Result<Number, RedisError>;
     fn pow_by(&mut self, path: Vec<String>, num: &str) -> Result<Number, RedisError>;
+    fn decimal_incr_by(&mut self, path: Vec<String>, num: &str) -> Result<Number, RedisError>;
+    fn decimal_mult_by(&mut self, path: Vec<String>, num: &str) -> Result<Number, RedisError>;
     fn bool_toggle(&mut self, path: Vec<String>) -> Result<bool, RedisError>;
     fn str_append(&mut self, path: Vec<String>, val: String) -> Result<usize, RedisError>;
+    fn str_prepend(&mut self, path: Vec<String>, val: String) -> Result<usize, RedisError>;
     fn arr_append(&mut self, path: Vec<String>, args: Vec<O>) -> Result<usize, RedisError>;
+    fn arr_trim_head(&mut self, path: Vec<String>, max_len: usize) -> Result<usize, RedisError>;
     fn arr_insert(
         &mut self,
         path: Vec<String>,
@@ -58,8 +96,21 @@ pub trait WriteHolder<O: Clone, V: SelectValue> {
         index: i64,
     ) -> Result<usize, RedisError>;
     fn arr_pop(&mut self, path: Vec<String>, index: i64) -> Result<Option<String>, RedisError>;
+    fn arr_pop_many(
+        &mut self,
+        path: Vec<String>,
+        index: i64,
+        count: usize,
+    ) -> Result<Vec<String>, RedisError>;
     fn arr_trim(&mut self, path: Vec<String>, start: i64, stop: i64) -> Result<usize, RedisError>;
+    fn arr_keep(&mut self, path: Vec<String>, indexes: &[usize]) -> Result<usize, RedisError>;
     fn clear(&mut self, path: Vec<String>) -> Result<usize, RedisError>;
+    fn obj_rename(
+        &mut self,
+        path: Vec<String>,
+        old_key: &str,
+        new_key: &str,
+    ) -> Result<bool, RedisError>;
     fn apply_changes(&mut self, ctx: &Context, command: &str) -> Result<(), RedisError>;
 }
 
@@ -84,10 +135,61 @@ pub trait Manager {
         key: RedisString,
     ) -> Result<Self::WriteHolder, RedisError>;
     fn from_str(&self, val: &str, format: Format) -> Result<Self::O, Error>;
+    fn copy_value(&self, v: &Self::V) -> Self::O;
+    fn array_items(&self, v: Self::O) -> Option<Vec<Self::O>>;
+    fn new_array(&self, items: Vec<Self::O>) -> Self::O;
+    fn value_name(&self, v: &Self::O) -> &'static str;
     fn get_memory(&self, v: &Self::V) -> Result<usize, RedisError>;
     fn is_json(&self, key: *mut RedisModuleKey) -> Result<bool, RedisError>;
 }
 
+///
+/// Key type errors are reported the way Redis' own commands report them
+///
+fn wrong_type(_: RedisError) -> RedisError {
+    RedisError::Str("WRONGTYPE Operation against a key holding the wrong kind of value")
+}
+
+fn parse_decimal(num: &str) -> Result<Decimal, Error> {
+    Decimal::from_str(num)
+        .or_else(|_| Decimal::from_scientific(num))
+        .map_err(|_| Error::from(format!("ERR '{}' is not a valid decimal number", num)))
+}
+
+///
+/// Fails when a change at `path` of an append-only document would remove or replace array
+/// elements: the change is inside an array, or it is `replaced` by a value holding one
+///
+pub fn check_append_only<V: SelectValue>(
+    append_only: bool,
+    root: &V,
+    path: &[String],
+    replaced: bool,
+) -> Result<(), RedisError> {
+    if !append_only {
+        return Ok(());
+    }
+    let mut curr = root;
+    for segment in path {
+        if curr.get_type() == SelectValueType::Array {
+            return Err(RedisError::Str("ERR append-only array"));
+        }
+        curr = match curr.get_key(segment) {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+    }
+    let mut stack = if replaced { vec![curr] } else { Vec::new() };
+    while let Some(v) = stack.pop() {
+        match v.get_type() {
+            SelectValueType::Array => return Err(RedisError::Str("ERR append-only array")),
+            SelectValueType::Object => stack.extend(v.items().unwrap().map(|(_, v)| v)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
 fn err_json(value: &Value, expected_value: &'static str) -> Error {
     Error::from(format!(
         "ERR wrong type of path value - expected {} but found {}",
@@ -100,6 +202,9 @@ pub struct KeyHolderWrite<'a> {
     key: RedisKeyWritable,
     key_name: RedisString,
     val: Option<&'a mut RedisJSON>,
+    // The Unix time in milliseconds at which the key was set to expire, with the arguments of
+    // the command that set it
+    expire_at: Option<(i64, Vec<*mut RedisModuleString>)>,
 }
 
 fn update<F: FnMut(Value) -> Result<Option<Value>, Error>>(
@@ -182,19 +287,29 @@ impl<'a> KeyHolderWrite<'a> {
         mut op2_fun: F2,
     ) -> Result<Number, RedisError>
     where
-        F1: FnMut(i64, i64) -> i64,
+        F1: FnMut(i64, i64) -> Option<i64>,
         F2: FnMut(f64, f64) -> f64,
     {
+        self.check_write(&path, true)?;
         let in_value = &serde_json::from_str(num)?;
         if let Value::Number(in_value) = in_value {
             let mut res = None;
             self.do_op(path, |v| {
-                let num_res = match (v.as_i64(), in_value.as_i64()) {
-                    (Some(num1), Some(num2)) => ((op1_fun)(num1, num2)).into(),
-                    _ => {
-                        let num1 = v.as_f64().unwrap();
-                        let num2 = in_value.as_f64().unwrap();
-                        Number::from_f64((op2_fun)(num1, num2)).unwrap()
+                // Integer operands stay integers (and keep their full precision)
+                // as long as the result fits in an i64
+                let int_res = match (v.as_i64(), in_value.as_i64()) {
+                    (Some(num1), Some(num2)) => (op1_fun)(num1, num2),
+                    _ => None,
+                };
+                let num_res = match int_res {
+                    Some(n) => n.into(),
+                    None => {
+                        let num1 = v.as_f64().ok_or_else(|| err_json(&v, "number"))?;
+                        let num2 = in_value
+                            .as_f64()
+                            .ok_or_else(|| Error::from("ERR bad input number"))?;
+                        Number::from_f64((op2_fun)(num1, num2))
+                            .ok_or_else(|| Error::from("ERR result is not a number"))?
                     }
                 };
                 res = Some(Value::Number(num_res));
@@ -212,9 +327,107 @@ impl<'a> KeyHolderWrite<'a> {
         }
     }
 
+    ///
+    /// Like `do_num_op` but with exact decimal arithmetic, the result keeping its decimal digits
+    ///
+    fn do_decimal_op<F>(
+        &mut self,
+        path: Vec<String>,
+        num: &str,
+        mut op_fun: F,
+    ) -> Result<Number, RedisError>
+    where
+        F: FnMut(Decimal, Decimal) -> Option<Decimal>,
+    {
+        self.check_write(&path, true)?;
+        let num2 = parse_decimal(num)?;
+        let mut res = None;
+        self.do_op(path, |v| {
+            let num1 = match &v {
+                Value::Number(n) => parse_decimal(&n.to_string())?,
+                _ => return Err(err_json(&v, "number")),
+            };
+            let n = (op_fun)(num1, num2).ok_or_else(|| Error::from("ERR decimal overflow"))?;
+            let mut n = n.to_string();
+            // Integers beyond 64 bits are stored as doubles, which keep all their digits
+            if !n.contains('.') && n.parse::<i64>().is_err() && n.parse::<u64>().is_err() {
+                n.push_str(".0");
+            }
+            let n = RedisJSON::parse_str(&n, Format::JSON)?;
+            res = n.as_number().cloned();
+            Ok(Some(n))
+        })?;
+        res.ok_or(RedisError::Str("path does not exists"))
+    }
+
+    fn do_str_op<F>(&mut self, path: Vec<String>, val: &str, op_fun: F) -> Result<usize, RedisError>
+    where
+        F: Fn(&str, &str) -> String,
+    {
+        self.check_write(&path, true)?;
+        let json = serde_json::from_str(val)?;
+        if let Value::String(s) = json {
+            let mut res = None;
+            self.do_op(path, |v| {
+                let new_str = op_fun(v.as_str().unwrap(), s.as_str());
+                res = Some(new_str.len());
+                Ok(Some(Value::String(new_str)))
+            })?;
+            match res {
+                None => Err(RedisError::Str("path does not exists")),
+                Some(l) => Ok(l),
+            }
+        } else {
+            Err(RedisError::String(format!(
+                "ERR wrong type of value - expected string but found {}",
+                val
+            )))
+        }
+    }
+
+    ///
+    /// Fails when writing at `path` would remove or replace array elements of an append-only
+    /// document. Every mutator checks it, so no command can bypass the mode.
+    ///
+    fn check_write(&mut self, path: &[String], replaced: bool) -> Result<(), RedisError> {
+        self.get_json_holder()?;
+        match &self.val {
+            Some(v) => check_append_only(v.append_only, &v.data, path, replaced),
+            None => Ok(()),
+        }
+    }
+
+    ///
+    /// Like `check_write` for a change of the array at `path`, which `removes` tells, given the
+    /// array's length, whether it removes, moves or replaces existing elements
+    ///
+    fn check_array_write<F: FnOnce(usize) -> bool>(
+        &mut self,
+        path: &[String],
+        removes: F,
+    ) -> Result<(), RedisError> {
+        self.check_write(path, false)?;
+        if let Some(v) = &self.val {
+            if v.append_only {
+                let len = path
+                    .iter()
+                    .try_fold(&v.data, |curr, segment| curr.get_key(segment))
+                    .and_then(|array| array.len())
+                    .unwrap_or(0);
+                if removes(len) {
+                    return Err(RedisError::Str("ERR append-only array"));
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn get_json_holder(&mut self) -> Result<(), RedisError> {
         if self.val.is_none() {
-            self.val = self.key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)?;
+            self.val = self
+                .key
+                .get_value::<RedisJSON>(&REDIS_JSON_TYPE)
+                .map_err(wrong_type)?;
         }
         Ok(())
     }
@@ -225,9 +438,18 @@ impl<'a> KeyHolderWrite<'a> {
                 self.get_json_holder()?;
                 match &mut self.val {
                     Some(v) => v.data = inner,
-                    None => self
-                        .key
-                        .set_value(&REDIS_JSON_TYPE, RedisJSON { data: inner })?,
+                    None => self.key.set_value(
+                        &REDIS_JSON_TYPE,
+                        RedisJSON {
+                            data: inner,
+                            frozen: false,
+                            cache: QueryCache::default(),
+                            history: VecDeque::new(),
+                            version: 0,
+                            append_only: false,
+                            tokens: Tokens::new(),
+                        },
+                    )?,
                 }
             }
             None => {
@@ -239,21 +461,162 @@ impl<'a> KeyHolderWrite<'a> {
     }
 }
 
+///
+/// A TTL is relative to when the command runs, so replicas and the AOF get the command (`args`
+/// after its name) followed by the time the key expires at. Replicating both the same way keeps
+/// them in order, in a transaction
+///
+fn replicate_with_expire(
+    ctx: &Context,
+    command: &str,
+    args: &mut [*mut RedisModuleString],
+    key_name: &RedisString,
+    at: i64,
+) -> Result<(), RedisError> {
+    let cmd = CString::new(command).unwrap();
+    let fmt = CString::new("v").unwrap();
+    let args = &mut args[1..];
+    let status = unsafe {
+        raw::RedisModule_Replicate.unwrap()(
+            ctx.ctx,
+            cmd.as_ptr(),
+            fmt.as_ptr(),
+            args.as_mut_ptr(),
+            args.len(),
+        )
+    };
+    if status != Status::Ok as c_int {
+        return Err(RedisError::Str("failed to replicate the command"));
+    }
+    let cmd = CString::new("PEXPIREAT").unwrap();
+    let fmt = CString::new("sl").unwrap();
+    let status = unsafe {
+        raw::RedisModule_Replicate.unwrap()(
+            ctx.ctx,
+            cmd.as_ptr(),
+            fmt.as_ptr(),
+            key_name.inner,
+            at as c_longlong,
+        )
+    };
+    if status != Status::Ok as c_int {
+        return Err(RedisError::Str("failed to replicate the expire time"));
+    }
+    Ok(())
+}
+
 impl<'a> WriteHolder<Value, Value> for KeyHolderWrite<'a> {
     fn apply_changes(&mut self, ctx: &Context, command: &str) -> Result<(), RedisError> {
         if ctx.notify_keyspace_event(NotifyEvent::MODULE, command, &self.key_name) != Status::Ok {
             Err(RedisError::Str("failed notify key space event"))
         } else {
-            ctx.replicate_verbatim();
+            match self.expire_at.take() {
+                Some((at, mut args)) => {
+                    replicate_with_expire(ctx, command, &mut args, &self.key_name, at)?
+                }
+                None => ctx.replicate_verbatim(),
+            }
+            self.get_json_holder()?;
+            if let Some(v) = &mut self.val {
+                v.version += 1;
+                v.cache.clear();
+            }
             Ok(())
         }
     }
 
     fn delete(&mut self) -> Result<(), RedisError> {
+        // The value is freed along with the key
+        self.val = None;
         self.key.delete()?;
         Ok(())
     }
 
+    fn set_expire(
+        &mut self,
+        expire: Duration,
+        args: &[*mut RedisModuleString],
+    ) -> Result<(), RedisError> {
+        let now = unsafe { raw::RedisModule_Milliseconds.unwrap()() };
+        self.key.set_expire(expire)?;
+        self.expire_at = Some((now + expire.as_millis() as i64, args.to_vec()));
+        Ok(())
+    }
+
+    fn is_frozen(&mut self) -> Result<bool, RedisError> {
+        self.get_json_holder()?;
+        Ok(self.val.as_ref().map_or(false, |v| v.frozen))
+    }
+
+    fn set_frozen(&mut self, frozen: bool) -> Result<(), RedisError> {
+        self.get_json_holder()?;
+        match &mut self.val {
+            Some(v) => {
+                v.frozen = frozen;
+                Ok(())
+            }
+            None => Err(RedisError::nonexistent_key()),
+        }
+    }
+
+    fn is_append_only(&mut self) -> Result<bool, RedisError> {
+        self.get_json_holder()?;
+        Ok(self.val.as_ref().map_or(false, |v| v.append_only))
+    }
+
+    fn set_append_only(&mut self) -> Result<(), RedisError> {
+        self.get_json_holder()?;
+        match &mut self.val {
+            Some(v) => {
+                v.append_only = true;
+                Ok(())
+            }
+            None => Err(RedisError::nonexistent_key()),
+        }
+    }
+
+    fn snapshot(&mut self) -> Result<Option<String>, RedisError> {
+        self.get_json_holder()?;
+        Ok(self.val.as_ref().map(|v| v.data.to_string()))
+    }
+
+    fn push_history(&mut self, version: String, max: usize) -> Result<(), RedisError> {
+        self.get_json_holder()?;
+        if let Some(v) = &mut self.val {
+            v.history.push_back(version);
+            while v.history.len() > max {
+                v.history.pop_front();
+            }
+        }
+        Ok(())
+    }
+
+    fn record_token(&mut self, token: String, reply: usize) -> Result<(), RedisError> {
+        self.get_json_holder()?;
+        if let Some(v) = &mut self.val {
+            idempotency::record(&mut v.tokens, token, reply);
+        }
+        Ok(())
+    }
+
+    fn undo(&mut self) -> Result<bool, RedisError> {
+        self.get_json_holder()?;
+        if self.val.as_ref().map_or(false, |v| !v.history.is_empty()) {
+            // An earlier version is restored as a whole
+            self.check_write(&[], true)?;
+        }
+        match &mut self.val {
+            Some(v) => match v.history.pop_back() {
+                Some(version) => {
+                    v.data = RedisJSON::parse_str(&version, Format::JSON)?;
+                    Ok(true)
+                }
+                None => Ok(false),
+            },
+            None => Err(RedisError::nonexistent_key()),
+        }
+    }
+
     fn get_value(&mut self) -> Result<Option<&mut Value>, RedisError> {
         self.get_json_holder()?;
 
@@ -264,6 +627,7 @@ impl<'a> WriteHolder<Value, Value> for KeyHolderWrite<'a> {
     }
 
     fn set_value(&mut self, path: Vec<String>, mut v: Value) -> Result<bool, RedisError> {
+        self.check_write(&path, true)?;
         let mut updated = false;
         if path.is_empty() {
             // update the root
@@ -279,6 +643,7 @@ impl<'a> WriteHolder<Value, Value> for KeyHolderWrite<'a> {
     }
 
     fn dict_add(&mut self, path: Vec<String>, key: &str, mut v: Value) -> Result<bool, RedisError> {
+        self.check_write(&path, false)?;
         let mut updated = false;
         if path.is_empty() {
             // update the root
@@ -310,7 +675,40 @@ impl<'a> WriteHolder<Value, Value> for KeyHolderWrite<'a> {
         Ok(updated)
     }
 
+    fn arr_set_extend(
+        &mut self,
+        path: Vec<String>,
+        index: usize,
+        mut v: Value,
+    ) -> Result<bool, RedisError> {
+        self.check_array_write(&path, |len| index < len)?;
+        let mut updated = false;
+        let mut extend = |val: Value| {
+            if let Value::Array(mut arr) = val {
+                if arr.len() <= index {
+                    arr.resize(index + 1, Value::Null);
+                }
+                arr[index] = v.take();
+                updated = true;
+                Value::Array(arr)
+            } else {
+                val
+            }
+        };
+        if path.is_empty() {
+            // update the root
+            let root = self.get_value().unwrap().unwrap().take();
+            self.set_root(Some(extend(root)))?;
+        } else {
+            update(&path, self.get_value().unwrap().unwrap(), |val| {
+                Ok(Some(extend(val)))
+            })?;
+        }
+        Ok(updated)
+    }
+
     fn delete_path(&mut self, path: Vec<String>) -> Result<bool, RedisError> {
+        self.check_write(&path, true)?;
         let mut deleted = false;
         update(&path, self.get_value().unwrap().unwrap(), |v| {
             if !v.is_null() {
@@ -322,18 +720,39 @@ impl<'a> WriteHolder<Value, Value> for KeyHolderWrite<'a> {
     }
 
     fn incr_by(&mut self, path: Vec<String>, num: &str) -> Result<Number, RedisError> {
-        self.do_num_op(path, num, |i1, i2| i1 + i2, |f1, f2| f1 + f2)
+        self.do_num_op(path, num, |i1, i2| i1.checked_add(i2), |f1, f2| f1 + f2)
     }
 
     fn mult_by(&mut self, path: Vec<String>, num: &str) -> Result<Number, RedisError> {
-        self.do_num_op(path, num, |i1, i2| i1 * i2, |f1, f2| f1 * f2)
+        self.do_num_op(path, num, |i1, i2| i1.checked_mul(i2), |f1, f2| f1 * f2)
     }
 
     fn pow_by(&mut self, path: Vec<String>, num: &str) -> Result<Number, RedisError> {
-        self.do_num_op(path, num, |i1, i2| i1.pow(i2 as u32), |f1, f2| f1.powf(f2))
+        self.do_num_op(
+            path,
+            num,
+            |i1, i2| {
+                // Negative exponents yield fractions, leave those to the f64 variant
+                if (0..=u32::MAX as i64).contains(&i2) {
+                    i1.checked_pow(i2 as u32)
+                } else {
+                    None
+                }
+            },
+            |f1, f2| f1.powf(f2),
+        )
+    }
+
+    fn decimal_incr_by(&mut self, path: Vec<String>, num: &str) -> Result<Number, RedisError> {
+        self.do_decimal_op(path, num, |d1, d2| d1.checked_add(d2))
+    }
+
+    fn decimal_mult_by(&mut self, path: Vec<String>, num: &str) -> Result<Number, RedisError> {
+        self.do_decimal_op(path, num, |d1, d2| d1.checked_mul(d2))
     }
 
     fn bool_toggle(&mut self, path: Vec<String>) -> Result<bool, RedisError> {
+        self.check_write(&path, true)?;
         let mut res = None;
         self.do_op(path, |v| {
             let val = v.as_bool().unwrap() ^ true;
@@ -347,27 +766,15 @@ impl<'a> WriteHolder<Value, Value> for KeyHolderWrite<'a> {
     }
 
     fn str_append(&mut self, path: Vec<String>, val: String) -> Result<usize, RedisError> {
-        let json = serde_json::from_str(&val)?;
-        if let Value::String(s) = json {
-            let mut res = None;
-            self.do_op(path, |v| {
-                let new_str = [v.as_str().unwrap(), s.as_str()].concat();
-                res = Some(new_str.len());
-                Ok(Some(Value::String(new_str)))
-            })?;
-            match res {
-                None => Err(RedisError::Str("path does not exists")),
-                Some(l) => Ok(l),
-            }
-        } else {
-            Err(RedisError::String(format!(
-                "ERR wrong type of value - expected string but found {}",
-                val
-            )))
-        }
+        self.do_str_op(path, &val, |old, s| [old, s].concat())
+    }
+
+    fn str_prepend(&mut self, path: Vec<String>, val: String) -> Result<usize, RedisError> {
+        self.do_str_op(path, &val, |old, s| [s, old].concat())
     }
 
     fn arr_append(&mut self, path: Vec<String>, mut args: Vec<Value>) -> Result<usize, RedisError> {
+        self.check_write(&path, false)?;
         let mut res = None;
         self.do_op(path, |mut v| {
             let arr = v.as_array_mut().unwrap();
@@ -381,12 +788,33 @@ impl<'a> WriteHolder<Value, Value> for KeyHolderWrite<'a> {
         }
     }
 
+    fn arr_trim_head(&mut self, path: Vec<String>, max_len: usize) -> Result<usize, RedisError> {
+        self.check_array_write(&path, |len| len > max_len)?;
+        let mut res = None;
+        self.do_op(path, |mut v| {
+            let arr = v.as_array_mut().unwrap();
+            // The oldest elements are the first ones
+            let excess = arr.len().saturating_sub(max_len);
+            arr.drain(..excess);
+            res = Some(arr.len());
+            Ok(Some(v))
+        })?;
+        match res {
+            None => Err(RedisError::Str("path does not exists")),
+            Some(n) => Ok(n),
+        }
+    }
+
     fn arr_insert(
         &mut self,
         paths: Vec<String>,
         args: &Vec<Value>,
         index: i64,
     ) -> Result<usize, RedisError> {
+        // Only an insert at the end keeps the existing elements where they are
+        self.check_array_write(&paths, |len| {
+            (if index < 0 { len as i64 + index } else { index }) != len as i64
+        })?;
         let mut res = None;
         self.do_op(paths, |mut v| {
             // Verify legal index in bounds
@@ -409,6 +837,7 @@ impl<'a> WriteHolder<Value, Value> for KeyHolderWrite<'a> {
     }
 
     fn arr_pop(&mut self, path: Vec<String>, index: i64) -> Result<Option<String>, RedisError> {
+        self.check_array_write(&path, |_| true)?;
         let mut res = None;
         self.do_op(path, |mut v| {
             if let Some(array) = v.as_array() {
@@ -437,7 +866,44 @@ impl<'a> WriteHolder<Value, Value> for KeyHolderWrite<'a> {
         }
     }
 
+    fn arr_pop_many(
+        &mut self,
+        path: Vec<String>,
+        index: i64,
+        count: usize,
+    ) -> Result<Vec<String>, RedisError> {
+        self.check_array_write(&path, |_| true)?;
+        let mut res = Vec::new();
+        self.do_op(path, |mut v| {
+            if let Some(array) = v.as_array() {
+                if array.is_empty() {
+                    return Ok(Some(v));
+                }
+                // Verify legel index in bounds
+                let len = array.len() as i64;
+                let index = if index < 0 {
+                    0.max(len + index)
+                } else {
+                    index.min(len - 1)
+                } as usize;
+                let end = index.saturating_add(count).min(len as usize);
+
+                let mut new_value = v.take();
+                let curr = new_value.as_array_mut().unwrap();
+                res = curr.drain(index..end).collect();
+                Ok(Some(new_value))
+            } else {
+                Err(err_json(&v, "array"))
+            }
+        })?;
+        Ok(res
+            .iter()
+            .map(|n| RedisJSON::serialize(n, Format::JSON))
+            .collect::<Result<_, _>>()?)
+    }
+
     fn arr_trim(&mut self, path: Vec<String>, start: i64, stop: i64) -> Result<usize, RedisError> {
+        self.check_array_write(&path, |_| true)?;
         let mut res = None;
         self.do_op(path, |mut v| {
             if let Some(array) = v.as_array() {
@@ -466,37 +932,142 @@ impl<'a> WriteHolder<Value, Value> for KeyHolderWrite<'a> {
         }
     }
 
+    fn arr_keep(&mut self, path: Vec<String>, indexes: &[usize]) -> Result<usize, RedisError> {
+        self.check_array_write(&path, |len| indexes.len() < len)?;
+        let mut res = None;
+        self.do_op(path, |mut v| {
+            if let Some(array) = v.as_array_mut() {
+                // The indexes are sorted
+                let mut index = 0;
+                array.retain(|_| {
+                    let keep = indexes.binary_search(&index).is_ok();
+                    index += 1;
+                    keep
+                });
+                res = Some(array.len());
+                Ok(Some(v))
+            } else {
+                Err(err_json(&v, "array"))
+            }
+        })?;
+        match res {
+            None => Err(RedisError::Str("path does not exists")),
+            Some(l) => Ok(l),
+        }
+    }
+
     fn clear(&mut self, path: Vec<String>) -> Result<usize, RedisError> {
+        self.check_write(&path, true)?;
         let mut cleared = 0;
+        // Only values which actually change are counted
         self.do_op(path, |v| match v {
             Value::Object(mut obj) => {
-                obj.clear();
-                cleared += 1;
+                if !obj.is_empty() {
+                    obj.clear();
+                    cleared += 1;
+                }
                 Ok(Some(Value::from(obj)))
             }
             Value::Array(mut arr) => {
-                arr.clear();
-                cleared += 1;
+                if !arr.is_empty() {
+                    arr.clear();
+                    cleared += 1;
+                }
                 Ok(Some(Value::from(arr)))
             }
+            Value::Number(n) if n.as_f64() != Some(0.0) => {
+                cleared += 1;
+                Ok(Some(Value::from(0)))
+            }
             _ => Ok(Some(v)),
         })?;
         Ok(cleared)
     }
+
+    fn obj_rename(
+        &mut self,
+        path: Vec<String>,
+        old_key: &str,
+        new_key: &str,
+    ) -> Result<bool, RedisError> {
+        // The renamed member is removed, and a member of the new name replaced
+        for key in &[old_key, new_key] {
+            let mut member = path.clone();
+            member.push(key.to_string());
+            self.check_write(&member, true)?;
+        }
+        let mut renamed = false;
+        self.do_op(path, |v| match v {
+            Value::Object(obj) if obj.contains_key(old_key) => {
+                renamed = true;
+                // The members are rebuilt in order, so the renamed one keeps its position
+                let obj = obj
+                    .into_iter()
+                    .filter(|(key, _)| key != new_key || key == old_key)
+                    .map(|(key, val)| match key {
+                        key if key == old_key => (new_key.to_string(), val),
+                        key => (key, val),
+                    })
+                    .collect();
+                Ok(Some(Value::Object(obj)))
+            }
+            Value::Object(obj) => Ok(Some(Value::Object(obj))),
+            _ => Err(err_json(&v, "object")),
+        })?;
+        Ok(renamed)
+    }
 }
 
 pub struct KeyHolderRead {
     key: RedisKey,
 }
 
+impl KeyHolderRead {
+    fn get_json(&self) -> Result<Option<&RedisJSON>, RedisError> {
+        self.key
+            .get_value::<RedisJSON>(&REDIS_JSON_TYPE)
+            .map_err(wrong_type)
+    }
+}
+
 impl ReadHolder<Value> for KeyHolderRead {
     fn get_value(&self) -> Result<Option<&Value>, RedisError> {
-        let key_value = self.key.get_value::<RedisJSON>(&REDIS_JSON_TYPE)?;
+        let key_value = self.get_json()?;
         match key_value {
             Some(v) => Ok(Some(&v.data)),
             None => Ok(None),
         }
     }
+
+    fn get_cached(&self, query: &[String]) -> Result<Option<String>, RedisError> {
+        let key_value = self.get_json()?;
+        Ok(key_value.and_then(|v| v.cache.get(query)))
+    }
+
+    fn set_cached(&self, query: Vec<String>, reply: String) -> Result<(), RedisError> {
+        if let Some(v) = self.get_json()? {
+            v.cache.set(query, reply);
+        }
+        Ok(())
+    }
+
+    fn get_history(&self) -> Result<Vec<&str>, RedisError> {
+        let key_value = self.get_json()?;
+        Ok(key_value.map_or_else(Vec::new, |v| {
+            // Most recent first, which is the version JSON.UNDO restores
+            v.history.iter().rev().map(|s| s.as_str()).collect()
+        }))
+    }
+
+    fn get_version(&self) -> Result<u64, RedisError> {
+        let key_value = self.get_json()?;
+        Ok(key_value.map_or(0, |v| v.version))
+    }
+
+    fn get_token_reply(&self, token: &str) -> Result<Option<usize>, RedisError> {
+        let key_value = self.get_json()?;
+        Ok(key_value.and_then(|v| idempotency::get(&v.tokens, token)))
+    }
 }
 
 pub struct RedisJsonKeyManager<'a> {
@@ -524,12 +1095,13 @@ impl<'a> Manager for RedisJsonKeyManager<'a> {
             key: key_ptr,
             key_name: key,
             val: None,
+            expire_at: None,
         })
     }
 
     fn from_str(&self, val: &str, format: Format) -> Result<Value, Error> {
         match format {
-            Format::JSON => Ok(serde_json::from_str(val)?),
+            Format::JSON => RedisJSON::parse_str(val, Format::JSON),
             Format::BSON => decode_document(&mut Cursor::new(val.as_bytes()))
                 .map(|docs| {
                     let v = if !docs.is_empty() {
@@ -545,6 +1117,25 @@ impl<'a> Manager for RedisJsonKeyManager<'a> {
         }
     }
 
+    fn copy_value(&self, v: &Value) -> Value {
+        v.clone()
+    }
+
+    fn array_items(&self, v: Value) -> Option<Vec<Value>> {
+        match v {
+            Value::Array(arr) => Some(arr),
+            _ => None,
+        }
+    }
+
+    fn new_array(&self, items: Vec<Value>) -> Value {
+        Value::Array(items)
+    }
+
+    fn value_name(&self, v: &Value) -> &'static str {
+        KeyValue::value_name(v)
+    }
+
     fn get_memory(&self, v: &Value) -> Result<usize, RedisError> {
         let res = match v {
             Value::Null => 0,
@@ -0,0 +1,228 @@
+// JSON Patch (RFC 6902) operations applied to a document, or computed between two documents.
+//
+// The operations address values with JSON Pointers (RFC 6901), e.g. `/a/0/b`, and are applied one
+// by one. Callers apply them to a copy of the document, so a failing operation leaves it unchanged.
+
+use crate::commands::KeyValue;
+use crate::error::Error;
+use jsonpath_lib::select::select_value::{SelectValue, SelectValueType};
+use serde_json::{json, Value};
+
+///
+/// Splits a JSON Pointer into its unescaped reference tokens (the root has none)
+///
+pub fn parse_pointer(pointer: &str) -> Result<Vec<String>, Error> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    match pointer.strip_prefix('/') {
+        Some(tokens) => Ok(tokens
+            .split('/')
+            .map(|token| token.replace("~1", "/").replace("~0", "~"))
+            .collect()),
+        None => Err(format!("invalid JSON Pointer '{}'", pointer).into()),
+    }
+}
+
+///
+/// The value referenced by the tokens of a JSON Pointer, if any
+///
+pub fn resolve_pointer<'a, T: SelectValue>(doc: &'a T, tokens: &[String]) -> Option<&'a T> {
+    tokens.iter().try_fold(doc, |v, token| match v.get_type() {
+        SelectValueType::Array => v.get_index(array_index(token)?),
+        SelectValueType::Object => v.get_key(token),
+        _ => None,
+    })
+}
+
+fn resolve_pointer_mut<'a>(doc: &'a mut Value, tokens: &[String]) -> Option<&'a mut Value> {
+    tokens.iter().try_fold(doc, |v, token| match v {
+        Value::Array(arr) => arr.get_mut(array_index(token)?),
+        Value::Object(map) => map.get_mut(token),
+        _ => None,
+    })
+}
+
+///
+/// Parses an array index token, which has no sign and no leading zeros
+///
+pub fn array_index(token: &str) -> Option<usize> {
+    if token.is_empty()
+        || (token.len() > 1 && token.starts_with('0'))
+        || !token.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    token.parse().ok()
+}
+
+pub fn apply(doc: &mut Value, patch: &Value) -> Result<(), Error> {
+    let ops = patch
+        .as_array()
+        .ok_or("ERR JSON Patch must be an array of operations")?;
+    for (i, op) in ops.iter().enumerate() {
+        apply_op(doc, op).map_err(|e| {
+            Error::from(format!("ERR JSON Patch operation {} failed: {}", i, e.msg))
+        })?;
+    }
+    Ok(())
+}
+
+fn apply_op(doc: &mut Value, op: &Value) -> Result<(), Error> {
+    let member = |name: &str| {
+        op.get(name)
+            .ok_or_else(|| Error::from(format!("missing '{}'", name)))
+    };
+    let pointer = |name: &str| {
+        member(name)?
+            .as_str()
+            .ok_or_else(|| Error::from(format!("'{}' must be a string", name)))
+            .and_then(parse_pointer)
+    };
+
+    let path = pointer("path")?;
+    match member("op")?.as_str() {
+        Some("add") => add(doc, &path, member("value")?.clone()),
+        Some("remove") => remove(doc, &path).map(|_| ()),
+        Some("replace") => {
+            let value = member("value")?.clone();
+            let target = resolve_pointer_mut(doc, &path).ok_or("path does not exist")?;
+            *target = value;
+            Ok(())
+        }
+        Some("move") => {
+            let from = pointer("from")?;
+            if path.len() > from.len() && path.starts_with(&from) {
+                return Err("can't move a value into one of its children".into());
+            }
+            if path != from {
+                let value = remove(doc, &from)?;
+                add(doc, &path, value)?;
+            }
+            Ok(())
+        }
+        Some("copy") => {
+            let value = resolve_pointer(&*doc, &pointer("from")?)
+                .ok_or("from does not exist")?
+                .clone();
+            add(doc, &path, value)
+        }
+        Some("test") => {
+            let value = member("value")?;
+            match resolve_pointer(&*doc, &path) {
+                Some(v) if KeyValue::new(v).is_eqaul(v, value) => Ok(()),
+                _ => Err("test failed".into()),
+            }
+        }
+        Some(other) => Err(format!("unknown op '{}'", other).into()),
+        None => Err("'op' must be a string".into()),
+    }
+}
+
+///
+/// Adds a value to an object (replacing an existing member) or inserts it in an array, where `-`
+/// is past its last element
+///
+fn add(doc: &mut Value, path: &[String], value: Value) -> Result<(), Error> {
+    let (last, parent) = match path.split_last() {
+        Some(split) => split,
+        None => {
+            *doc = value;
+            return Ok(());
+        }
+    };
+    match resolve_pointer_mut(doc, parent) {
+        Some(Value::Object(map)) => {
+            map.insert(last.to_string(), value);
+            Ok(())
+        }
+        Some(Value::Array(arr)) => {
+            let index = match last.as_str() {
+                "-" => arr.len(),
+                token => array_index(token)
+                    .filter(|i| *i <= arr.len())
+                    .ok_or("array index out of range")?,
+            };
+            arr.insert(index, value);
+            Ok(())
+        }
+        Some(_) => Err("parent is not an object or an array".into()),
+        None => Err("path does not exist".into()),
+    }
+}
+
+pub fn remove(doc: &mut Value, path: &[String]) -> Result<Value, Error> {
+    let (last, parent) = path.split_last().ok_or("can't remove the root")?;
+    match resolve_pointer_mut(doc, parent) {
+        Some(Value::Object(map)) => map.remove(last),
+        Some(Value::Array(arr)) => array_index(last)
+            .filter(|i| *i < arr.len())
+            .map(|i| arr.remove(i)),
+        _ => None,
+    }
+    .ok_or_else(|| "path does not exist".into())
+}
+
+///
+/// The operations turning `from` into `to`, with pointers relative to them. Object members are
+/// removed, added or compared by key, and array elements by index, the longer array's extra
+/// elements being removed (last first) or added at the end
+///
+pub fn diff<T: SelectValue>(from: &T, to: &Value) -> Vec<Value> {
+    let mut ops = Vec::new();
+    diff_values(from, to, &mut String::new(), &mut ops);
+    ops
+}
+
+fn diff_values<T: SelectValue>(from: &T, to: &Value, pointer: &mut String, ops: &mut Vec<Value>) {
+    let len = pointer.len();
+    match (from.get_type(), to) {
+        (SelectValueType::Object, Value::Object(members)) => {
+            for (k, v) in from.items().unwrap() {
+                push_token(pointer, k);
+                match members.get(k) {
+                    Some(to) => diff_values(v, to, pointer, ops),
+                    None => ops.push(json!({"op": "remove", "path": pointer})),
+                }
+                pointer.truncate(len);
+            }
+            for (k, v) in members.iter().filter(|(k, _)| from.get_key(k).is_none()) {
+                push_token(pointer, k);
+                ops.push(json!({"op": "add", "path": pointer, "value": v}));
+                pointer.truncate(len);
+            }
+        }
+        (SelectValueType::Array, Value::Array(elements)) => {
+            let from_len = from.len().unwrap();
+            for (i, (v, to)) in from.values().unwrap().zip(elements).enumerate() {
+                push_token(pointer, &i.to_string());
+                diff_values(v, to, pointer, ops);
+                pointer.truncate(len);
+            }
+            for i in (elements.len()..from_len).rev() {
+                push_token(pointer, &i.to_string());
+                ops.push(json!({"op": "remove", "path": pointer}));
+                pointer.truncate(len);
+            }
+            for (i, v) in elements.iter().enumerate().skip(from_len) {
+                push_token(pointer, &i.to_string());
+                ops.push(json!({"op": "add", "path": pointer, "value": v}));
+                pointer.truncate(len);
+            }
+        }
+        // Unlike `test`, an integer turned into a double (or back) is a change
+        _ => {
+            if from.get_type() != to.get_type() || !KeyValue::new(from).is_eqaul(from, to) {
+                ops.push(json!({"op": "replace", "path": pointer, "value": to}));
+            }
+        }
+    }
+}
+
+///
+/// Appends an escaped reference token to a JSON Pointer
+///
+fn push_token(pointer: &mut String, token: &str) {
+    pointer.push('/');
+    pointer.push_str(&token.replace('~', "~0").replace('/', "~1"));
+}
@@ -7,6 +7,8 @@
 use crate::backward;
 use crate::c_api::JSONType;
 use crate::error::Error;
+use crate::idempotency::Tokens;
+use crate::manager::{Manager, RedisJsonKeyManager};
 use crate::nodevisitor::{StaticPathElement, StaticPathParser, VisitStatus};
 use crate::REDIS_JSON_TYPE_VERSION;
 use jsonpath_lib::select::json_node::JsonValueUpdater;
@@ -14,8 +16,11 @@ use jsonpath_lib::select::{Selector, SelectorMut};
 
 use bson::decode_document;
 use redis_module::raw::{self, Status};
-use serde_json::Value;
+use serde_json::{Number, Value};
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::io::Cursor;
+use std::marker::PhantomData;
 use std::mem;
 use std::os::raw::{c_int, c_void};
 
@@ -34,9 +39,9 @@ pub enum Format {
 impl Format {
     pub fn from_str(s: &str) -> Result<Format, Error> {
         match s {
-            "JSON" => Ok(Format::JSON),
-            "BSON" => Ok(Format::BSON),
-            _ => Err("ERR wrong format".into()),
+            s if s.eq_ignore_ascii_case("JSON") => Ok(Format::JSON),
+            s if s.eq_ignore_ascii_case("BSON") => Ok(Format::BSON),
+            _ => Err(format!("ERR unknown format '{}', expected JSON or BSON", s).into()),
         }
     }
 }
@@ -47,6 +52,8 @@ impl Format {
 pub struct Path {
     original_path: String,
     fixed_path: Option<String>,
+    // The path actually evaluated when it was resolved against a document (e.g. ignoring case)
+    resolved_path: Option<String>,
 }
 
 impl Path {
@@ -67,6 +74,7 @@ impl Path {
         Path {
             original_path: path,
             fixed_path,
+            resolved_path: None,
         }
     }
 
@@ -75,7 +83,18 @@ impl Path {
     }
 
     pub fn get_path(&self) -> &String {
-        self.fixed_path.as_ref().unwrap_or(&self.original_path)
+        self.resolved_path
+            .as_ref()
+            .or_else(|| self.fixed_path.as_ref())
+            .unwrap_or(&self.original_path)
+    }
+
+    pub fn get_original(&self) -> &String {
+        &self.original_path
+    }
+
+    pub fn resolve(&mut self, path: String) {
+        self.resolved_path = Some(path);
     }
 
     pub fn take_original(self) -> String {
@@ -83,16 +102,113 @@ impl Path {
     }
 }
 
+const MAX_CACHED_REPLY_LEN: usize = 64 * 1024;
+
+///
+/// The reply of the last JSON.GET query on a document, dropped whenever the document changes
+///
+#[derive(Debug, Default)]
+pub struct QueryCache {
+    last: RefCell<Option<(Vec<String>, String)>>,
+}
+
+impl QueryCache {
+    pub fn get(&self, query: &[String]) -> Option<String> {
+        match &*self.last.borrow() {
+            Some((q, reply)) if q.as_slice() == query => Some(reply.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn set(&self, query: Vec<String>, reply: String) {
+        // Large replies aren't kept, bounding the memory of the cache
+        let cached = if reply.len() <= MAX_CACHED_REPLY_LEN {
+            Some((query, reply))
+        } else {
+            None
+        };
+        *self.last.borrow_mut() = cached;
+    }
+
+    pub fn clear(&self) {
+        *self.last.borrow_mut() = None;
+    }
+
+    pub fn mem_usage(&self) -> usize {
+        self.last.borrow().as_ref().map_or(0, |(query, reply)| {
+            query.iter().map(String::len).sum::<usize>() + reply.len()
+        })
+    }
+}
+
+///
+/// Brings the numbers of a parsed value to the form they are stored in: integers and doubles of
+/// at most 15 significant digits take their `i64`, `u64` or `f64` form, while longer doubles keep
+/// their exact digits (e.g. NUMINCRBY DECIMAL results)
+///
+pub fn canonicalize_numbers(value: &mut Value) -> Result<(), Error> {
+    let mut stack = vec![value];
+    while let Some(value) = stack.pop() {
+        match value {
+            Value::Number(n) => *n = canonical_number(n)?,
+            Value::Array(arr) => stack.extend(arr.iter_mut()),
+            Value::Object(map) => stack.extend(map.values_mut()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn canonical_number(n: &Number) -> Result<Number, Error> {
+    if let Some(i) = n.as_i64() {
+        return Ok(i.into());
+    }
+    if let Some(u) = n.as_u64() {
+        return Ok(u.into());
+    }
+    let out_of_range = || Error::from(format!("ERR number out of range: {}", n));
+    let d = n.as_f64().ok_or_else(out_of_range)?;
+    if n.is_f64() {
+        // Up to 15 significant digits an `f64` round-trips exactly
+        let text = n.to_string();
+        let mantissa = text
+            .split(|c: char| c == 'e' || c == 'E')
+            .next()
+            .unwrap_or_default();
+        let digits: String = mantissa.chars().filter(char::is_ascii_digit).collect();
+        if digits.trim_start_matches('0').trim_end_matches('0').len() > 15 {
+            return Ok(n.clone());
+        }
+    }
+    // Integers beyond 64 bits are approximated
+    Number::from_f64(d).ok_or_else(out_of_range)
+}
+
 #[derive(Debug)]
 pub struct RedisJSON {
     //FIXME: make private and expose array/object Values without requiring a path
     pub data: Value,
+    // Frozen documents reject every write until they are unfrozen
+    pub frozen: bool,
+    pub cache: QueryCache,
+    // Serialized previous versions kept by JSON.SET KEEPHISTORY, oldest first
+    pub history: VecDeque<String>,
+    // Bumped by every write to the document, replied by JSON.GET WITHVERSION
+    pub version: u64,
+    // Append-only documents reject every write that removes or replaces array elements
+    pub append_only: bool,
+    // Tokens of the most recent JSON.ARRAPPEND IDEMPOTENT writes with their replies, oldest first
+    pub tokens: Tokens,
 }
 
 impl RedisJSON {
     pub fn parse_str(data: &str, format: Format) -> Result<Value, Error> {
         match format {
-            Format::JSON => Ok(serde_json::from_str(data)?),
+            Format::JSON => {
+                let mut value = serde_json::from_str(data)?;
+                canonicalize_numbers(&mut value)?;
+                Ok(value)
+            }
             Format::BSON => decode_document(&mut Cursor::new(data.as_bytes()))
                 .map(|docs| {
                     let v = if !docs.is_empty() {
@@ -110,7 +226,15 @@ impl RedisJSON {
 
     pub fn from_str(data: &str, format: Format) -> Result<Self, Error> {
         let value = RedisJSON::parse_str(data, format)?;
-        Ok(Self { data: value })
+        Ok(Self {
+            data: value,
+            frozen: false,
+            cache: QueryCache::default(),
+            history: VecDeque::new(),
+            version: 0,
+            append_only: false,
+            tokens: Tokens::new(),
+        })
     }
 
     fn add_value(&mut self, path: &str, value: Value) -> Result<bool, Error> {
@@ -292,7 +416,7 @@ impl RedisJSON {
             if arr.is_empty() || end < -1 {
                 return Ok(-1);
             }
-            let v: Value = serde_json::from_str(scalar)?;
+            let v = RedisJSON::parse_str(scalar, Format::JSON)?;
 
             let len = arr.len() as i64;
 
@@ -459,6 +583,12 @@ pub mod type_methods {
         let json = match encver {
             0 => RedisJSON {
                 data: backward::json_rdb_load(rdb),
+                frozen: false,
+                cache: QueryCache::default(),
+                history: VecDeque::new(),
+                version: 0,
+                append_only: false,
+                tokens: Tokens::new(),
             },
             2 => {
                 let data = raw::load_string(rdb);
@@ -472,13 +602,35 @@ pub mod type_methods {
             }
             3 => {
                 let data = raw::load_string(rdb);
-                RedisJSON::from_str(&data, Format::JSON).unwrap()
+                if data.is_empty() {
+                    load_with_state(rdb)
+                } else {
+                    RedisJSON::from_str(&data, Format::JSON).unwrap()
+                }
             }
             _ => panic!("Can't load old RedisJSON RDB"),
         };
         Box::into_raw(Box::new(json)) as *mut c_void
     }
 
+    // The document followed by the state kept along with it
+    fn load_with_state(rdb: *mut raw::RedisModuleIO) -> RedisJSON {
+        let data = raw::load_string(rdb);
+        let mut json = RedisJSON::from_str(&data, Format::JSON).unwrap();
+        json.frozen = raw::load_unsigned(rdb) > 0;
+        for _ in 0..raw::load_unsigned(rdb) {
+            json.history.push_back(raw::load_string(rdb));
+        }
+        json.version = raw::load_unsigned(rdb);
+        json.append_only = raw::load_unsigned(rdb) > 0;
+        for _ in 0..raw::load_unsigned(rdb) {
+            let token = raw::load_string(rdb);
+            let reply = raw::load_unsigned(rdb) as usize;
+            json.tokens.push_back((token, reply));
+        }
+        json
+    }
+
     #[allow(non_snake_case, unused)]
     pub unsafe extern "C" fn free(value: *mut c_void) {
         let json = value as *mut RedisJSON;
@@ -487,10 +639,47 @@ pub mod type_methods {
         Box::from_raw(json);
     }
 
+    #[allow(non_snake_case, unused)]
+    pub unsafe extern "C" fn mem_usage(value: *const c_void) -> usize {
+        let json = &*(value as *const RedisJSON);
+        let manager = RedisJsonKeyManager {
+            phantom: PhantomData,
+        };
+        // Everything kept with the document counts, wherever in it the change came from
+        manager.get_memory(&json.data).unwrap_or(0)
+            + json.history.iter().map(String::len).sum::<usize>()
+            + json
+                .tokens
+                .iter()
+                .map(|(token, _)| token.len())
+                .sum::<usize>()
+            + json.cache.mem_usage()
+    }
+
     #[allow(non_snake_case, unused)]
     pub unsafe extern "C" fn rdb_save(rdb: *mut raw::RedisModuleIO, value: *mut c_void) {
         let json = &*(value as *mut RedisJSON);
+        // A document without any other state is saved as earlier versions of the module save it,
+        // so they can still load it
+        if !json.frozen && json.history.is_empty() && !json.append_only && json.tokens.is_empty() {
+            raw::save_string(rdb, &json.data.to_string());
+            return;
+        }
+        // Otherwise an empty string, which is never a serialized document, comes first
+        raw::save_string(rdb, "");
         raw::save_string(rdb, &json.data.to_string());
+        raw::save_unsigned(rdb, json.frozen as u64);
+        raw::save_unsigned(rdb, json.history.len() as u64);
+        for version in &json.history {
+            raw::save_string(rdb, version);
+        }
+        raw::save_unsigned(rdb, json.version);
+        raw::save_unsigned(rdb, json.append_only as u64);
+        raw::save_unsigned(rdb, json.tokens.len() as u64);
+        for (token, reply) in &json.tokens {
+            raw::save_string(rdb, token);
+            raw::save_unsigned(rdb, *reply as u64);
+        }
     }
 
     #[allow(non_snake_case, unused)]
@@ -0,0 +1,769 @@
+// Output transformations applied to JSON values while they are serialized for a reply.
+//
+// `TransformValue` wraps a value and serializes it through the requested transformations
+// without materializing a transformed copy of the document.
+
+use crate::commands::KeyValue;
+use jsonpath_lib::select::select_value::{SelectValue, SelectValueType};
+use regex::Regex;
+use serde::ser::{Error, SerializeMap, SerializeSeq};
+use serde::{Serialize, Serializer};
+use serde_json::ser::Formatter;
+use serde_json::{Number, Value};
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+// What the strings matched by JSON.GET REDACT are replaced with
+const REDACTED: &str = "***";
+
+#[derive(Default)]
+pub struct TransformOptions {
+    // Serialize numbers as JSON strings
+    pub string_nums: bool,
+    // Reply with the array of matches even for legacy paths (the values themselves are unchanged)
+    pub wrap: bool,
+    // Omit the object members whose value is null
+    pub skip_null: bool,
+    // Maximal number of JSONPath matches in the reply (the first ones are kept)
+    pub match_limit: Option<usize>,
+    // Reply with the keys of every matched object instead of its value (null for non objects)
+    pub only_keys: bool,
+    // Serialize the strings holding a JSON number (e.g. "42") as that number
+    pub coerce_nums: bool,
+    // Reply with every JSONPath match as compact JSON on its own line instead of an array
+    pub ndjson: bool,
+    // Reply with the number of bytes of the serialization instead of it
+    pub size_only: bool,
+    // Reply with every matched value as an object of its leaves keyed by their JSON Pointers
+    pub flat_map: bool,
+    // Reply with every matched array as the pairs of its indexes and elements (matches must be arrays)
+    pub with_indices: bool,
+    // Reply with a hash of the compact serialization instead of it
+    pub etag: bool,
+    // The types to cast the members of the matched objects to, by name (see `is_valid_schema`)
+    pub schema: Option<Value>,
+    // Reply with the count, sum, min, max and average of every matched array of numbers
+    pub stats: bool,
+    // Mask the string values that this matches
+    pub redact: Option<Regex>,
+    // Reply with the matched array of objects as CSV text instead of JSON
+    pub csv: bool,
+}
+
+impl TransformOptions {
+    fn is_identity(&self) -> bool {
+        !self.string_nums
+            && !self.skip_null
+            && !self.coerce_nums
+            && self.schema.is_none()
+            && self.redact.is_none()
+    }
+
+    // Whether the reply is the plain serialization of the matches
+    pub fn is_plain(&self) -> bool {
+        self.is_identity()
+            && !self.wrap
+            && self.match_limit.is_none()
+            && !self.only_keys
+            && !self.ndjson
+            && !self.size_only
+            && !self.flat_map
+            && !self.with_indices
+            && !self.etag
+            && !self.stats
+            && !self.csv
+    }
+
+    fn redacts(&self, s: &str) -> bool {
+        self.redact.as_ref().map_or(false, |re| re.is_match(s))
+    }
+
+    pub fn max_matches(&self) -> usize {
+        self.match_limit.unwrap_or(usize::MAX)
+    }
+}
+
+pub struct TransformValue<'a, V: SelectValue> {
+    value: &'a V,
+    options: &'a TransformOptions,
+    // Whether the value is nested in a matched value rather than the match itself
+    nested: bool,
+    // The part of the schema that applies to the value
+    schema: Option<&'a Value>,
+}
+
+impl<'a, V: SelectValue> TransformValue<'a, V> {
+    pub fn new(value: &'a V, options: &'a TransformOptions) -> Self {
+        TransformValue {
+            value,
+            options,
+            nested: false,
+            schema: options.schema.as_ref(),
+        }
+    }
+
+    // Array elements share the schema of their array
+    fn wrap(&self, value: &'a V) -> Self {
+        self.wrap_with(value, self.schema)
+    }
+
+    fn wrap_with(&self, value: &'a V, schema: Option<&'a Value>) -> Self {
+        TransformValue {
+            value,
+            options: self.options,
+            nested: true,
+            schema,
+        }
+    }
+
+    // A string cast from another type is masked like a stored one
+    fn coerce(&self, value: &V, schema: Option<&Value>) -> Result<Option<Value>, String> {
+        Ok(coerce(value, schema)?.map(|coerced| match coerced {
+            Value::String(s) if self.options.redacts(&s) => Value::String(REDACTED.to_string()),
+            coerced => coerced,
+        }))
+    }
+
+    ///
+    /// The value, an array of objects, as CSV: a header row with the member names in the order
+    /// they first appear, then a row per object. Strings are written as they are, null and missing
+    /// members as empty cells, and other values as JSON. Rows end with CRLF as in RFC 4180.
+    ///
+    pub fn to_csv(&self) -> Result<String, String> {
+        let rows = self
+            .value
+            .values()
+            .filter(|_| self.value.get_type() == SelectValueType::Array)
+            .ok_or("ERR CSV requires an array of objects")?
+            .map(|row| match row.get_type() {
+                SelectValueType::Object => Ok(row),
+                _ => Err(format!(
+                    "ERR CSV requires an array of objects, found {}",
+                    KeyValue::value_name(row)
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut seen = HashSet::new();
+        let header = rows
+            .iter()
+            .flat_map(|row| row.items().unwrap().map(|(k, _)| k))
+            .filter(|k| seen.insert(*k))
+            .collect::<Vec<_>>();
+        let mut csv = String::new();
+        if header.is_empty() {
+            return Ok(csv);
+        }
+        write_csv_row(&mut csv, header.iter().map(|k| k.to_string()));
+        for row in rows {
+            let row = self.wrap(row);
+            let cells = header
+                .iter()
+                .map(|k| match row.value.get_key(k) {
+                    Some(v) => serde_json::to_value(row.wrap_with(v, member_schema(row.schema, k)))
+                        .map(|cell| match cell {
+                            Value::Null => String::new(),
+                            Value::String(s) => s,
+                            cell => cell.to_string(),
+                        }),
+                    None => Ok(String::new()),
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| e.to_string())?;
+            write_csv_row(&mut csv, cells.into_iter());
+        }
+        Ok(csv)
+    }
+}
+
+// Cells holding a separator, a quote or a line break are quoted, with their quotes doubled
+fn write_csv_row<I: Iterator<Item = String>>(csv: &mut String, cells: I) {
+    for (i, cell) in cells.enumerate() {
+        if i > 0 {
+            csv.push(',');
+        }
+        if cell.contains(|c| matches!(c, ',' | '"' | '\r' | '\n')) {
+            csv.push('"');
+            csv.push_str(&cell.replace('"', "\"\""));
+            csv.push('"');
+        } else {
+            csv.push_str(&cell);
+        }
+    }
+    csv.push_str("\r\n");
+}
+
+///
+/// Whether a schema is an object of member names to the types `string`, `number`, `integer` or
+/// `boolean`, or to the schema of a nested object
+///
+pub fn is_valid_schema(schema: &Value) -> bool {
+    match schema {
+        Value::Object(members) => members.values().all(|v| match v {
+            Value::String(t) => ["string", "number", "integer", "boolean"].contains(&t.as_str()),
+            v => is_valid_schema(v),
+        }),
+        _ => false,
+    }
+}
+
+fn member_schema<'a>(schema: Option<&'a Value>, key: &str) -> Option<&'a Value> {
+    schema.and_then(|s| s.get(key))
+}
+
+///
+/// The scalar a value is cast to by the type of its schema, or `None` when the schema is for the
+/// members of an object (or of the objects in an array). Null is left as is, and so is a value
+/// that has no schema.
+///
+fn coerce<V: SelectValue>(value: &V, schema: Option<&Value>) -> Result<Option<Value>, String> {
+    let target = match schema {
+        Some(Value::String(target)) => target.as_str(),
+        Some(_) => {
+            return match value.get_type() {
+                SelectValueType::Object | SelectValueType::Array | SelectValueType::Null => {
+                    Ok(None)
+                }
+                _ => Err(format!(
+                    "ERR COERCESCHEMA can't coerce {} to object",
+                    KeyValue::value_name(value)
+                )),
+            }
+        }
+        None => return Ok(None),
+    };
+    let coerced = match (target, value.get_type()) {
+        (_, SelectValueType::Null) => Some(Value::Null),
+        ("string", SelectValueType::String) => Some(Value::String(value.get_str())),
+        ("string", SelectValueType::Long)
+        | ("string", SelectValueType::Double)
+        | ("string", SelectValueType::Bool) => serde_json::to_string(value).ok().map(Value::String),
+        ("number", SelectValueType::Long)
+        | ("number", SelectValueType::Double)
+        | ("integer", SelectValueType::Long) => serde_json::to_value(value).ok(),
+        ("integer", SelectValueType::Double) => {
+            let d = value.get_double();
+            // Only integral doubles that an i64 holds exactly are cast
+            if d.fract() == 0.0 && d.abs() < 9007199254740992.0 {
+                Some(Value::from(d as i64))
+            } else {
+                None
+            }
+        }
+        ("number", SelectValueType::String) => value.as_str().parse().ok().map(Value::Number),
+        ("integer", SelectValueType::String) => value.as_str().parse::<i64>().ok().map(Value::from),
+        ("boolean", SelectValueType::Bool) => Some(Value::Bool(value.get_bool())),
+        ("boolean", SelectValueType::String) => value.as_str().parse().ok().map(Value::Bool),
+        _ => None,
+    };
+    match coerced {
+        Some(coerced) => Ok(Some(coerced)),
+        None => Err(format!(
+            "ERR COERCESCHEMA can't coerce {} to {}",
+            KeyValue::value_name(value),
+            target
+        )),
+    }
+}
+
+///
+/// The count, sum, min, max and average of the numbers of an array, in one pass over it. The sum
+/// of integers is an integer unless it overflows, and the min and max are the elements as they are.
+///
+fn array_stats<V: SelectValue>(value: &V) -> Result<Vec<(&'static str, Value)>, String> {
+    let values = value
+        .values()
+        .filter(|_| value.get_type() == SelectValueType::Array)
+        .ok_or("ERR STATS requires array matches")?;
+    let mut count = 0usize;
+    let mut sum = 0.0;
+    let mut long_sum = Some(0i64);
+    let mut min: Option<(f64, &V)> = None;
+    let mut max: Option<(f64, &V)> = None;
+    for v in values {
+        let d = match v.get_type() {
+            SelectValueType::Long => {
+                long_sum = long_sum.and_then(|s| s.checked_add(v.get_long()));
+                v.get_long() as f64
+            }
+            SelectValueType::Double => {
+                long_sum = None;
+                v.get_double()
+            }
+            _ => {
+                return Err(format!(
+                    "ERR STATS requires arrays of numbers, found {}",
+                    KeyValue::value_name(v)
+                ))
+            }
+        };
+        count += 1;
+        sum += d;
+        if min.map_or(true, |(m, _)| d < m) {
+            min = Some((d, v));
+        }
+        if max.map_or(true, |(m, _)| d > m) {
+            max = Some((d, v));
+        }
+    }
+    let float = |d: f64| Number::from_f64(d).map_or(Value::Null, Value::Number);
+    let element = |e: Option<(f64, &V)>| {
+        e.and_then(|(_, v)| serde_json::to_value(v).ok())
+            .unwrap_or(Value::Null)
+    };
+    Ok(vec![
+        ("count", Value::from(count)),
+        ("sum", long_sum.map_or_else(|| float(sum), Value::from)),
+        ("min", element(min)),
+        ("max", element(max)),
+        (
+            "avg",
+            if count > 0 {
+                float(sum / count as f64)
+            } else {
+                Value::Null
+            },
+        ),
+    ])
+}
+
+impl<'a, V: SelectValue> Serialize for TransformValue<'a, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.options.only_keys && !self.nested {
+            return match self.value.keys() {
+                Some(keys) => serializer.collect_seq(keys),
+                None => serializer.serialize_unit(),
+            };
+        }
+        if self.options.flat_map && !self.nested {
+            let leaves = flatten(self.value, self.options);
+            let mut map = serializer.serialize_map(Some(leaves.len()))?;
+            for (pointer, v) in leaves {
+                map.serialize_entry(&pointer, &self.wrap(v))?;
+            }
+            return map.end();
+        }
+        if self.options.with_indices && !self.nested {
+            let values = self
+                .value
+                .values()
+                .filter(|_| self.value.get_type() == SelectValueType::Array)
+                .ok_or_else(|| S::Error::custom("ERR WITHINDICES requires array matches"))?;
+            let mut seq = serializer.serialize_seq(self.value.len())?;
+            for (i, v) in values.enumerate() {
+                seq.serialize_element(&(i, self.wrap(v)))?;
+            }
+            return seq.end();
+        }
+        if self.options.stats && !self.nested {
+            let stats = array_stats(self.value).map_err(S::Error::custom)?;
+            let mut map = serializer.serialize_map(Some(stats.len()))?;
+            for (k, v) in &stats {
+                map.serialize_entry(k, v)?;
+            }
+            return map.end();
+        }
+        if let Some(coerced) = self
+            .coerce(self.value, self.schema)
+            .map_err(S::Error::custom)?
+        {
+            return coerced.serialize(serializer);
+        }
+        if self.options.is_identity() {
+            return self.value.serialize(serializer);
+        }
+        let value = self.value;
+        match value.get_type() {
+            SelectValueType::Null => serializer.serialize_unit(),
+            SelectValueType::Bool => serializer.serialize_bool(value.get_bool()),
+            SelectValueType::Long | SelectValueType::Double => {
+                // Numbers keep their stored digits, which an `f64` may not hold
+                if self.options.string_nums {
+                    let n = serde_json::to_string(value).map_err(S::Error::custom)?;
+                    serializer.collect_str(&n)
+                } else {
+                    value.serialize(serializer)
+                }
+            }
+            SelectValueType::String => {
+                let s = value.as_str();
+                if self.options.redacts(s) {
+                    return serializer.serialize_str(REDACTED);
+                }
+                // Coercing is pointless when numbers are serialized as strings anyway
+                if self.options.coerce_nums && !self.options.string_nums {
+                    if let Ok(n) = s.parse::<Number>() {
+                        return n.serialize(serializer);
+                    }
+                }
+                serializer.serialize_str(s)
+            }
+            SelectValueType::Array => {
+                let mut seq = serializer.serialize_seq(value.len())?;
+                for v in value.values().unwrap() {
+                    seq.serialize_element(&self.wrap(v))?;
+                }
+                seq.end()
+            }
+            SelectValueType::Object => {
+                let len = if self.options.skip_null {
+                    None
+                } else {
+                    value.len()
+                };
+                let mut map = serializer.serialize_map(len)?;
+                for (k, v) in value.items().unwrap() {
+                    if self.options.skip_null && v.get_type() == SelectValueType::Null {
+                        continue;
+                    }
+                    map.serialize_entry(k, &self.wrap_with(v, member_schema(self.schema, k)))?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+///
+/// Collects the leaves of a value with their JSON Pointers relative to it, e.g. `/a/0/b`, where
+/// scalars and empty arrays or objects are leaves
+///
+fn flatten<'a, V: SelectValue>(value: &'a V, options: &TransformOptions) -> Vec<(String, &'a V)> {
+    let mut leaves = Vec::new();
+    // Depth first with an explicit stack, children pushed last to first to keep their order
+    let mut stack = vec![(String::new(), value)];
+    while let Some((pointer, value)) = stack.pop() {
+        let children = match value.get_type() {
+            SelectValueType::Array if value.len() != Some(0) => value
+                .values()
+                .unwrap()
+                .enumerate()
+                .map(|(i, v)| (format!("{}/{}", pointer, i), v))
+                .collect::<Vec<_>>(),
+            SelectValueType::Object if value.len() != Some(0) => value
+                .items()
+                .unwrap()
+                .filter(|(_, v)| !(options.skip_null && v.get_type() == SelectValueType::Null))
+                .map(|(k, v)| {
+                    let key = k.replace('~', "~0").replace('/', "~1");
+                    (format!("{}/{}", pointer, key), v)
+                })
+                .collect(),
+            _ => {
+                leaves.push((pointer, value));
+                continue;
+            }
+        };
+        stack.extend(children.into_iter().rev());
+    }
+    leaves
+}
+
+pub struct PathValue<'a, V: SelectValue> {
+    pub path: String,
+    pub value: TransformValue<'a, V>,
+}
+
+impl<'a, V: SelectValue> Serialize for PathValue<'a, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("path", &self.path)?;
+        map.serialize_entry("value", &self.value)?;
+        map.end()
+    }
+}
+
+///
+/// Writes a reply exactly as its `Serialize` implementation does, but walks the matched values
+/// with an explicit stack instead of recursion, so arbitrarily deep documents don't overflow the
+/// call stack
+///
+pub trait IterativeSerialize {
+    fn write_iterative<W: io::Write, F: Formatter>(
+        &self,
+        writer: &mut W,
+        formatter: &mut F,
+    ) -> Result<(), serde_json::Error>;
+}
+
+// A container being written, with whether none of its elements was started yet and its schema
+enum Frame<'a, V: SelectValue> {
+    Array(
+        Box<dyn Iterator<Item = &'a V> + 'a>,
+        bool,
+        Option<&'a Value>,
+    ),
+    Object(
+        Box<dyn Iterator<Item = (&'a str, &'a V)> + 'a>,
+        bool,
+        Option<&'a Value>,
+    ),
+}
+
+impl<'a, V: SelectValue> TransformValue<'a, V> {
+    fn write_nested<W: io::Write, F: Formatter>(
+        &self,
+        value: &'a V,
+        writer: &mut W,
+        formatter: &mut F,
+    ) -> Result<(), serde_json::Error> {
+        let mut stack: Vec<Frame<'a, V>> = Vec::new();
+        let mut next = Some((value, self.schema));
+        while let Some((value, schema)) = next.take() {
+            match (value.get_type(), self.coerce(value, schema)) {
+                (_, Err(e)) => return Err(serde_json::Error::custom(e)),
+                (_, Ok(Some(coerced))) => write_scalar(writer, &coerced)?,
+                (SelectValueType::Array, _) => {
+                    formatter
+                        .begin_array(writer)
+                        .map_err(serde_json::Error::io)?;
+                    stack.push(Frame::Array(value.values().unwrap(), true, schema));
+                }
+                (SelectValueType::Object, _) => {
+                    formatter
+                        .begin_object(writer)
+                        .map_err(serde_json::Error::io)?;
+                    stack.push(Frame::Object(value.items().unwrap(), true, schema));
+                }
+                _ => write_scalar(writer, &self.wrap_with(value, schema))?,
+            }
+            // Ends the value just written in its container and starts the container's next value,
+            // ending the containers that have no more values
+            while let Some(frame) = stack.last_mut() {
+                let started = match frame {
+                    Frame::Array(values, first, schema) => {
+                        if !*first {
+                            formatter
+                                .end_array_value(writer)
+                                .map_err(serde_json::Error::io)?;
+                        }
+                        match values.next() {
+                            Some(v) => {
+                                formatter
+                                    .begin_array_value(writer, *first)
+                                    .map_err(serde_json::Error::io)?;
+                                *first = false;
+                                Some((v, *schema))
+                            }
+                            None => {
+                                formatter.end_array(writer).map_err(serde_json::Error::io)?;
+                                None
+                            }
+                        }
+                    }
+                    Frame::Object(items, first, schema) => {
+                        if !*first {
+                            formatter
+                                .end_object_value(writer)
+                                .map_err(serde_json::Error::io)?;
+                        }
+                        let skip_null = self.options.skip_null;
+                        match items
+                            .find(|(_, v)| !(skip_null && v.get_type() == SelectValueType::Null))
+                        {
+                            Some((k, v)) => {
+                                formatter
+                                    .begin_object_key(writer, *first)
+                                    .map_err(serde_json::Error::io)?;
+                                write_scalar(writer, k)?;
+                                formatter
+                                    .end_object_key(writer)
+                                    .and_then(|_| formatter.begin_object_value(writer))
+                                    .map_err(serde_json::Error::io)?;
+                                *first = false;
+                                Some((v, member_schema(*schema, k)))
+                            }
+                            None => {
+                                formatter
+                                    .end_object(writer)
+                                    .map_err(serde_json::Error::io)?;
+                                None
+                            }
+                        }
+                    }
+                };
+                match started {
+                    Some(v) => {
+                        next = Some(v);
+                        break;
+                    }
+                    None => {
+                        stack.pop();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, V: SelectValue> IterativeSerialize for TransformValue<'a, V> {
+    fn write_iterative<W: io::Write, F: Formatter>(
+        &self,
+        writer: &mut W,
+        formatter: &mut F,
+    ) -> Result<(), serde_json::Error> {
+        if self.options.only_keys && !self.nested {
+            return match self.value.keys() {
+                Some(keys) => write_array(keys, writer, formatter, |k, w, _| write_scalar(w, k)),
+                None => write_scalar(writer, &()),
+            };
+        }
+        if self.options.flat_map && !self.nested {
+            let leaves = flatten(self.value, self.options);
+            return write_object(
+                leaves.iter().map(|(pointer, v)| (pointer.as_str(), *v)),
+                writer,
+                formatter,
+                |v, w, f| self.write_nested(v, w, f),
+            );
+        }
+        if self.options.with_indices && !self.nested {
+            let values = self
+                .value
+                .values()
+                .filter(|_| self.value.get_type() == SelectValueType::Array)
+                .ok_or_else(|| {
+                    serde_json::Error::custom("ERR WITHINDICES requires array matches")
+                })?;
+            return write_array(values.enumerate(), writer, formatter, |(i, v), w, f| {
+                write_array(0..2, w, f, |element, w, f| match element {
+                    0 => write_scalar(w, &i),
+                    _ => self.write_nested(v, w, f),
+                })
+            });
+        }
+        if self.options.stats && !self.nested {
+            let stats = array_stats(self.value).map_err(serde_json::Error::custom)?;
+            return write_object(
+                stats.iter().map(|(k, v)| (*k, v)),
+                writer,
+                formatter,
+                |v, w, _| write_scalar(w, v),
+            );
+        }
+        self.write_nested(self.value, writer, formatter)
+    }
+}
+
+impl<'a, V: SelectValue> IterativeSerialize for PathValue<'a, V> {
+    fn write_iterative<W: io::Write, F: Formatter>(
+        &self,
+        writer: &mut W,
+        formatter: &mut F,
+    ) -> Result<(), serde_json::Error> {
+        write_object(
+            vec![("path", None), ("value", Some(&self.value))],
+            writer,
+            formatter,
+            |v, w, f| match v {
+                Some(value) => value.write_iterative(w, f),
+                None => write_scalar(w, &self.path),
+            },
+        )
+    }
+}
+
+impl<T: IterativeSerialize> IterativeSerialize for Option<T> {
+    fn write_iterative<W: io::Write, F: Formatter>(
+        &self,
+        writer: &mut W,
+        formatter: &mut F,
+    ) -> Result<(), serde_json::Error> {
+        match self {
+            Some(v) => v.write_iterative(writer, formatter),
+            None => write_scalar(writer, &()),
+        }
+    }
+}
+
+impl<T: IterativeSerialize> IterativeSerialize for Vec<T> {
+    fn write_iterative<W: io::Write, F: Formatter>(
+        &self,
+        writer: &mut W,
+        formatter: &mut F,
+    ) -> Result<(), serde_json::Error> {
+        write_array(self, writer, formatter, |v, w, f| v.write_iterative(w, f))
+    }
+}
+
+impl<T: IterativeSerialize> IterativeSerialize for HashMap<String, T> {
+    fn write_iterative<W: io::Write, F: Formatter>(
+        &self,
+        writer: &mut W,
+        formatter: &mut F,
+    ) -> Result<(), serde_json::Error> {
+        write_object(
+            self.iter().map(|(k, v)| (k.as_str(), v)),
+            writer,
+            formatter,
+            |v, w, f| v.write_iterative(w, f),
+        )
+    }
+}
+
+fn write_array<I, W, F>(
+    values: I,
+    writer: &mut W,
+    formatter: &mut F,
+    mut write_value: impl FnMut(I::Item, &mut W, &mut F) -> Result<(), serde_json::Error>,
+) -> Result<(), serde_json::Error>
+where
+    I: IntoIterator,
+    W: io::Write,
+    F: Formatter,
+{
+    formatter
+        .begin_array(writer)
+        .map_err(serde_json::Error::io)?;
+    for (i, v) in values.into_iter().enumerate() {
+        formatter
+            .begin_array_value(writer, i == 0)
+            .map_err(serde_json::Error::io)?;
+        write_value(v, writer, formatter)?;
+        formatter
+            .end_array_value(writer)
+            .map_err(serde_json::Error::io)?;
+    }
+    formatter.end_array(writer).map_err(serde_json::Error::io)
+}
+
+fn write_object<'k, T, W, F>(
+    entries: impl IntoIterator<Item = (&'k str, T)>,
+    writer: &mut W,
+    formatter: &mut F,
+    mut write_value: impl FnMut(T, &mut W, &mut F) -> Result<(), serde_json::Error>,
+) -> Result<(), serde_json::Error>
+where
+    W: io::Write,
+    F: Formatter,
+{
+    formatter
+        .begin_object(writer)
+        .map_err(serde_json::Error::io)?;
+    for (i, (k, v)) in entries.into_iter().enumerate() {
+        formatter
+            .begin_object_key(writer, i == 0)
+            .map_err(serde_json::Error::io)?;
+        write_scalar(writer, k)?;
+        formatter
+            .end_object_key(writer)
+            .and_then(|_| formatter.begin_object_value(writer))
+            .map_err(serde_json::Error::io)?;
+        write_value(v, writer, formatter)?;
+        formatter
+            .end_object_value(writer)
+            .map_err(serde_json::Error::io)?;
+    }
+    formatter.end_object(writer).map_err(serde_json::Error::io)
+}
+
+///
+/// Writes a scalar (or a string) the way the serializer of any formatter does, as the formatters
+/// only differ by the layout of the containers
+///
+fn write_scalar<W: io::Write, T: Serialize + ?Sized>(
+    writer: &mut W,
+    value: &T,
+) -> Result<(), serde_json::Error> {
+    value.serialize(&mut serde_json::Serializer::new(writer))
+}
@@ -2,6 +2,7 @@
 
 import sys
 import os
+import time
 import redis
 import json
 from RLTest import Env
@@ -178,6 +179,254 @@ def testSetBehaviorModifyingSubcommands(env):
     r.expect('JSON.SET', 'test', '.foo', '[]', 'XX', 'XN').raiseError()
     r.expect('JSON.SET', 'test', '.foo', '[]', 'XX', '').raiseError()
 
+def testFormatOption(env):
+    """Test FORMAT values are case insensitive"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"a":1}', 'FORMAT', 'json'))
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.a', '2', 'FORMAT', 'Json'))
+    r.assertEqual(r.execute_command('JSON.GET', 'test', 'FORMAT', 'jSoN', '.a'), '2')
+    r.expect('JSON.SET', 'test', '.', '{}', 'FORMAT', 'xml').error().contains("unknown format 'xml', expected JSON or BSON")
+    r.expect('JSON.GET', 'test', 'FORMAT', 'yaml').error().contains("unknown format 'yaml', expected JSON or BSON")
+
+def testSetMergeArray(env):
+    """Test JSON.SET's MERGEARRAY subcommand"""
+    r = env
+
+    r.expect('JSON.SET', 'test', '.', '{"arr":[1,2],"str":"foo"}').ok()
+    r.expect('JSON.SET', 'test', '.arr', '[3,4]', 'MERGEARRAY').ok()
+    r.expect('JSON.GET', 'test', '.arr').equal('[1,2,3,4]')
+
+    # Both sides must be arrays
+    r.expect('JSON.SET', 'test', '.arr', '5', 'MERGEARRAY').raiseError()
+    r.expect('JSON.SET', 'test', '.str', '[5]', 'MERGEARRAY').raiseError()
+    r.expect('JSON.SET', 'test', '.missing', '[5]', 'MERGEARRAY').raiseError()
+    r.expect('JSON.SET', 'test', '.arr', '[5]', 'MERGEARRAY', 'NX').error().contains("can't be combined")
+    r.expect('JSON.SET', 'test', '.arr', '[5]', 'XX', 'MERGEARRAY').error().contains("can't be combined")
+    r.expect('JSON.GET', 'test', '.').equal('{"arr":[1,2,3,4],"str":"foo"}')
+
+def testSetFromKey(env):
+    """Test JSON.SET FROMKEY"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'src', '.', '{"sub":{"i":1,"f":1.0,"s":"1","a":[true,null]}}'))
+    r.assertOk(r.execute_command('JSON.SET', 'dst', '.', '{}'))
+    r.assertOk(r.execute_command('JSON.SET', 'dst', '.copy', 'FROMKEY', 'src', '$.sub'))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'dst', '.copy')),
+                  {"i": 1, "f": 1.0, "s": "1", "a": [True, None]})
+    r.assertEqual(r.execute_command('JSON.TYPE', 'dst', '.copy.i', '.copy.f', '.copy.s', '.copy.a'),
+                  ['integer', 'number', 'string', 'array'])
+    # A whole new key, and a copy within the same key
+    r.assertOk(r.execute_command('JSON.SET', 'new', '.', 'fromkey', 'src', '.sub.a'))
+    r.assertEqual(r.execute_command('JSON.GET', 'new'), '[true,null]')
+    r.assertOk(r.execute_command('JSON.SET', 'src', '.again', 'FROMKEY', 'src', '.sub.i'))
+    r.assertEqual(r.execute_command('JSON.GET', 'src', '.again'), '1')
+    # Changing the copy leaves the source as it was
+    r.assertEqual(r.execute_command('JSON.NUMINCRBY', 'dst', '.copy.i', 1), '2')
+    r.assertEqual(r.execute_command('JSON.GET', 'src', '.sub.i'), '1')
+
+    r.expect('JSON.SET', 'dst', '.x', 'FROMKEY', 'src', '.missing').error().contains('does not exist')
+    r.expect('JSON.SET', 'dst', '.x', 'FROMKEY', 'nokey', '.').raiseError()
+    r.expect('JSON.SET', 'dst', '.copy.a', 'FROMKEY', 'src', '.sub.a', 'MERGEARRAY').raiseError()
+    r.assertEqual(r.execute_command('JSON.GET', 'dst', '$.x'), '[]')
+
+    # The source key is declared as a key of the command
+    r.assertEqual(r.execute_command('COMMAND', 'GETKEYS', 'JSON.SET', 'dst', '.x', 'FROMKEY', 'src', '.'), ['dst', 'src'])
+    r.assertEqual(r.execute_command('COMMAND', 'GETKEYS', 'JSON.SET', 'dst', 'PATHS', '.x', '.y', 'VALUE', 'fromkey', 'src', '.'), ['dst', 'src'])
+    r.assertEqual(r.execute_command('COMMAND', 'GETKEYS', 'JSON.SET', 'dst', '.x', '1'), ['dst'])
+
+def testSetMultiplePaths(env):
+    """Test JSON.SET PATHS ... VALUE"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"a":{"x":0},"b":[1,2],"c":{}}'))
+    # Replacing an existing value and adding a new one at once
+    r.assertOk(r.execute_command('JSON.SET', 'test', 'PATHS', '$.a.x', '.c.new', 'VALUE', '"v"'))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test')),
+                  {'a': {'x': 'v'}, 'b': [1, 2], 'c': {'new': 'v'}})
+    r.assertOk(r.execute_command('JSON.SET', 'test', 'paths', '$.b[0]', '$.a.y', 'value', '7'))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test')),
+                  {'a': {'x': 'v', 'y': 7}, 'b': [7, 2], 'c': {'new': 'v'}})
+
+    # One impossible path fails the whole command
+    r.expect('JSON.SET', 'test', 'PATHS', '$.a.x', '$.missing.y', 'VALUE', '1').raiseError()
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.a.x'), '"v"')
+    # NX and XX skip the paths that don't qualify
+    r.assertOk(r.execute_command('JSON.SET', 'test', 'PATHS', '$.a.x', '$.a.z', 'VALUE', '0', 'NX'))
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.a'), '{"x":"v","y":7,"z":0}')
+    r.assertIsNone(r.execute_command('JSON.SET', 'test', 'PATHS', '$.a.x', '$.a.y', 'VALUE', '0', 'NX'))
+
+    # A target of the wrong type fails the whole command too
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.s', '"str"'))
+    r.expect('JSON.SET', 'test', 'PATHS', '$.a.x', '$.s.y', 'VALUE', '1').error().contains('expected object')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.a.x'), '"v"')
+    r.expect('JSON.SET', 'test', '.s.y', '1').error().contains('path value is string, expected object')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.s'), '"str"')
+
+    r.expect('JSON.SET', 'test', 'PATHS', '$', '$.a', 'VALUE', '1').raiseError()
+    r.expect('JSON.SET', 'test', 'PATHS', 'VALUE', '1').raiseError()
+    r.expect('JSON.SET', 'test', 'PATHS', '$.a', '$.b').raiseError()
+    r.expect('JSON.SET', 'missing', 'PATHS', '$.a', '$.b', 'VALUE', '1').raiseError()
+
+def testSetArrayIndex(env):
+    """Test JSON.SET of array elements"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"arr":[1,2,3],"o":{"arr":[4]}}'))
+    # Elements in range are replaced in place
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.arr[2]', '{"a":1}'))
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.arr[0]', '"x"'))
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.arr[-2]', 'null'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.arr'), '[["x",null,{"a":1}]]')
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$..arr[0]', '0', 'XX'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$..arr'), '[[0,null,{"a":1}],[0]]')
+    # NX leaves existing elements as they are
+    r.assertIsNone(r.execute_command('JSON.SET', 'doc', '$.arr[1]', '5', 'NX'))
+    r.assertIsNone(r.execute_command('JSON.SET', 'doc', '$.arr[-1]', '5', 'NX', 'EXTEND'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.arr'), '[[0,null,{"a":1}]]')
+    # Elements out of range are an error unless EXTEND is given
+    r.expect('JSON.SET', 'doc', '$.arr[3]', '5').raiseError().contains('array index out of range')
+    r.expect('JSON.SET', 'doc', '$.arr[5]', '5', 'NX').raiseError().contains('array index out of range')
+    r.assertIsNone(r.execute_command('JSON.SET', 'doc', '$.arr[3]', '5', 'XX'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.arr'), '[[0,null,{"a":1}]]')
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.arr[3]', '5', 'NX', 'EXTEND'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.arr'), '[[0,null,{"a":1},5]]')
+
+def testSetNegativeArrayIndex(env):
+    """Test JSON.SET of array elements counted from the end"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"a":[1,2,{"b":3}]}'))
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.a[-1].c', '4'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a[2]'), '[{"b":3,"c":4}]')
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.a[-1]', '"last"'))
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.a[-3]', '"first"', 'XX'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a'), '[["first",2,"last"]]')
+    r.assertIsNone(r.execute_command('JSON.SET', 'doc', '$.a[-2]', '5', 'NX'))
+    # Still out of range from the end, even with EXTEND
+    r.expect('JSON.SET', 'doc', '$.a[-4]', '5').raiseError().contains('array index out of range')
+    r.expect('JSON.SET', 'doc', '$.a[-4]', '5', 'EXTEND').raiseError().contains('array index out of range')
+    r.assertIsNone(r.execute_command('JSON.SET', 'doc', '$.a[-4]', '5', 'XX'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a'), '[["first",2,"last"]]')
+
+def testSetFilter(env):
+    """Test JSON.SET of the array elements matched by a filter"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"users":[{"id":4,"name":"Ann"},{"id":5,"name":"Al"},{"id":6,"name":"Cy"}]}'))
+    # The whole matched element is replaced, the others are untouched
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.users[?(@.id==5)]', '{"id":5,"name":"Bob","admin":true}'))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', '$.users')),
+                  [[{'id': 4, 'name': 'Ann'}, {'id': 5, 'name': 'Bob', 'admin': True}, {'id': 6, 'name': 'Cy'}]])
+    # Or a member of it
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.users[?(@.id==6)].name', '"Dee"'))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', '$.users[*].name')), ['Ann', 'Bob', 'Dee'])
+    # Every match is replaced
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.users[?(@.id>=5)]', '{}'))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', '$.users')), [[{'id': 4, 'name': 'Ann'}, {}, {}]])
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.users[?(@.id==4)].id', '7', 'XX'))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', '$.users[0]')), [{'id': 7, 'name': 'Ann'}])
+
+def testSetPointer(env):
+    """Test JSON.SET with a JSON Pointer"""
+    r = env
+
+    r.expect('JSON.SET', 'test', '$', '{"user":{"name":"foo"},"items":[1,2]}').ok()
+    # Object members are replaced or added
+    r.expect('JSON.SET', 'test', '/user/name', '"bar"').ok()
+    r.expect('JSON.SET', 'test', '/user/a~1b', '1').ok()
+    r.expect('JSON.GET', 'test', '.user').equal('{"a/b":1,"name":"bar"}')
+    # Array elements are replaced, and - appends
+    r.expect('JSON.SET', 'test', '/items/0', '10').ok()
+    r.expect('JSON.SET', 'test', '/items/-', '3').ok()
+    r.expect('JSON.GET', 'test', '.items').equal('[10,2,3]')
+    r.expect('JSON.SET', 'test', '/items/3', '4').error().contains('out of range')
+    r.expect('JSON.SET', 'test', '/items/01', '4').error().contains('out of range')
+
+    # NX and XX apply to the addressed value
+    r.expect('JSON.SET', 'test', '/user/name', '"baz"', 'NX').equal(None)
+    r.expect('JSON.SET', 'test', '/user/age', '42', 'XX').equal(None)
+    r.expect('JSON.SET', 'test', '/items/-', '5', 'XX').equal(None)
+    r.expect('JSON.SET', 'test', '/items/1', '20', 'XX').ok()
+    r.expect('JSON.GET', 'test', '.items').equal('[10,20,3]')
+
+    # Missing ancestors
+    r.expect('JSON.SET', 'test', '/a/b/c', '1').error().contains('parent does not exist')
+    r.expect('JSON.SET', 'test', '/a/b/c', '1', 'MKPATH').ok()
+    r.expect('JSON.GET', 'test', '.a').equal('{"b":{"c":1}}')
+    r.expect('JSON.SET', 'test', '/items/5/x', '1', 'MKPATH').raiseError()
+    r.expect('JSON.SET', 'test', '/user/name/x', '1').error().contains('not an object or array')
+    r.expect('JSON.SET', 'test', '$.user', '1', 'MKPATH').error().contains('requires a JSON Pointer')
+    r.expect('JSON.SET', 'missing', '/a', '1').raiseError()
+    r.expect('JSON.GET', 'test', '.items').equal('[10,20,3]')
+
+def testSetExtendArray(env):
+    """Test JSON.SET EXTEND"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"arr":[1,2,3]}'))
+    # Without EXTEND an out of range index is an error
+    r.expect('JSON.SET', 'doc', '$.arr[5]', '6').raiseError()
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.arr[5]', '6', 'EXTEND'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.arr'), '[[1,2,3,null,null,6]]')
+    r.assertEqual(r.execute_command('JSON.ARRLEN', 'doc', '$.arr'), 6)
+    # An index in range is set as usual
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.arr[3]', '4', 'EXTEND'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.arr'), '[[1,2,3,4,null,6]]')
+    # Root arrays can be extended too
+    r.assertOk(r.execute_command('JSON.SET', 'root', '$', '[]'))
+    r.assertOk(r.execute_command('JSON.SET', 'root', '$[1]', '"a"', 'EXTEND'))
+    r.assertEqual(r.execute_command('JSON.GET', 'root', '$'), '[[null,"a"]]')
+    # The padding is bounded even without json-max-array-len
+    r.expect('JSON.SET', 'doc', '$.arr[4000000000]', '1', 'EXTEND').error().contains('array length exceeds max')
+    r.expect('JSON.SET', 'doc', '$.arr[1048576]', '1', 'EXTEND').error().contains('array length exceeds max')
+    r.assertEqual(r.execute_command('JSON.ARRLEN', 'doc', '$.arr'), 6)
+
+def testSetExpire(env):
+    """Test JSON.SET EX and PX"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"a":1}', 'EX', 100))
+    ttl = r.execute_command('TTL', 'test')
+    r.assertTrue(0 < ttl <= 100)
+    r.assertOk(r.execute_command('JSON.SET', 'test', '$', '{"a":2}', 'PX', 200000))
+    pttl = r.execute_command('PTTL', 'test')
+    r.assertTrue(100000 < pttl <= 200000)
+    # Setting without a TTL keeps the key as is
+    r.assertOk(r.execute_command('JSON.SET', 'test', '$.a', '3'))
+    r.assertTrue(r.execute_command('PTTL', 'test') > 0)
+
+    # A value that is not set doesn't get a TTL either
+    r.assertOk(r.execute_command('JSON.SET', 'other', '.', '1'))
+    r.assertIsNone(r.execute_command('JSON.SET', 'other', '.', '2', 'NX', 'EX', 100))
+    r.assertEqual(r.execute_command('TTL', 'other'), -1)
+
+    # Only the first of several clients creates the entry, and its TTL is kept
+    r.assertOk(r.execute_command('JSON.SET', 'cache', '$', '{"v":1}', 'NX', 'PX', 200000))
+    r.assertIsNone(r.execute_command('JSON.SET', 'cache', '$', '{"v":2}', 'NX', 'PX', 50000))
+    r.assertTrue(100000 < r.execute_command('PTTL', 'cache') <= 200000)
+    r.assertEqual(r.execute_command('JSON.GET', 'cache', '$.v'), '[1]')
+
+    r.expect('JSON.SET', 'test', '$.a', '4', 'EX', 100).error().contains('root')
+    r.expect('JSON.SET', 'test', '.', '4', 'EX', 0).raiseError()
+    r.expect('JSON.SET', 'test', '.', '4', 'EX', 10, 'PX', 10).raiseError()
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.a'), '3')
+
+def testSetExpireAOF():
+    """Test that the TTL of JSON.SET EX and PX is written to the AOF as the time the key expires at"""
+    env = Env(useAOF=True)
+    env.skipOnCluster()
+    r = env
+
+    # Keep the expired key around until the AOF is loaded
+    r.expect('DEBUG', 'SET-ACTIVE-EXPIRE', '0').ok()
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"a":1}', 'PX', 500))
+    time.sleep(1)
+    r.expect('DEBUG', 'LOADAOF').ok()
+    r.assertIsNone(r.execute_command('JSON.GET', 'doc'))
+    r.assertEqual(r.execute_command('EXISTS', 'doc'), 0)
+    r.expect('DEBUG', 'SET-ACTIVE-EXPIRE', '1').ok()
+
 def testSetWithBracketNotation(env):
     r = env
 
@@ -265,6 +514,463 @@ def testGetFormatting(env):
                     res = r.execute_command('JSON.GET', list(o.keys()).pop(), 'INDENT', indent, 'NEWLINE', newline, 'SPACE', space)
                     r.assertEqual(res, f.format(newline=newline, space=space, indent=indent))
 
+def testGetPretty(env):
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"obj":{"f":"v"},"arr":[0,1]}'))
+    manual = r.execute_command('JSON.GET', 'doc', 'INDENT', '  ', 'NEWLINE', '\n', 'SPACE', ' ')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'PRETTY', 'POINTER', ''), manual)
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'pretty', '$.obj'),
+                  r.execute_command('JSON.GET', 'doc', 'INDENT', '  ', 'NEWLINE', '\n', 'SPACE', ' ', '$.obj'))
+    r.expect('JSON.GET', 'doc', 'PRETTY', 'INDENT', '\t', '$').error().contains('PRETTY cannot be combined')
+    r.expect('JSON.GET', 'doc', 'SPACE', ' ', 'PRETTY', '$').error().contains('PRETTY cannot be combined')
+
+def testGetStringNums(env):
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"i":9007199254740993,"d":1.5,"n":[-1,2.0],"s":"3","b":true,"z":null}'))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'STRINGNUMS', 'POINTER', '')),
+                  {"i": "9007199254740993", "d": "1.5", "n": ["-1", "2.0"], "s": "3", "b": True, "z": None})
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'STRINGNUMS', '$..i'), '["9007199254740993"]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'STRINGNUMS', 'POINTER', '/d'), '"1.5"')
+    # Numbers are kept by default
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '.i'), '9007199254740993')
+
+def testGetNullOrMissing(env):
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"n":null,"a":{"n":null}}'))
+    # A matched null is serialized, a missing legacy path is an error
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '.n'), 'null')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'a.n'), 'null')
+    r.expect('JSON.GET', 'doc', '.missing').error().contains('does not exist')
+    r.expect('JSON.GET', 'doc', '.a.missing').error().contains('does not exist')
+    # JSONPaths tell the two apart by the number of matches
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.n'), '[null]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.missing'), '[]')
+    # Only a missing key is a null reply
+    r.assertIsNone(r.execute_command('JSON.GET', 'nosuch', '.n'))
+
+def testGetSkipNull(env):
+    """Test JSON.GET SKIPNULL"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"a":1,"b":null,"c":{"d":null,"e":"x"},"f":[null,{"g":null}],"h":null}'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'SKIPNULL', 'POINTER', ''), '{"a":1,"c":{"e":"x"},"f":[null,{}]}')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'skipnull', '$.c'), '[{"e":"x"}]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'SKIPNULL', 'POINTER', '/b'), 'null')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'SKIPNULL', 'STRINGNUMS', 'POINTER', '/c'), '{"e":"x"}')
+    # Nulls are kept by default
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '.c'), '{"d":null,"e":"x"}')
+
+def testGetMatchLimit(env):
+    """Test JSON.GET MATCHLIMIT"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"a":[1,2,3],"b":{"c":4,"d":5}}'))
+    r.assertEqual(len(json.loads(r.execute_command('JSON.GET', 'doc', '$..*'))), 7)
+    r.assertEqual(len(json.loads(r.execute_command('JSON.GET', 'doc', 'MATCHLIMIT', 3, '$..*'))), 3)
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'matchlimit', 2, '$.a[*]'), '[1,2]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'MATCHLIMIT', 10, '$.a[*]'), '[1,2,3]')
+    # The count tells about the truncation
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'MATCHLIMIT', 1, 'WITHCOUNT', '$.a[*]'), [3, '[1]'])
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'MATCHLIMIT', 1, 'WITHPATHS', '$.b.*')),
+                  [{'path': "$['b']['c']", 'value': 4}])
+    r.expect('JSON.GET', 'doc', 'MATCHLIMIT', 0, '$..*').error().contains('MATCHLIMIT must be positive')
+    r.expect('JSON.GET', 'doc', 'MATCHLIMIT', '$').raiseError()
+
+def testGetCoerceNum(env):
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"a":"42","b":"abc","c":{"d":"-1.5","e":" 7"},"f":["0",1]}'))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'COERCENUM', 'POINTER', '')),
+                  {"a": 42, "b": "abc", "c": {"d": -1.5, "e": " 7"}, "f": [0, 1]})
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'COERCENUM', '$.a', '$.b')), {"$.a": 42, "$.b": "abc"})
+    # The stored value is unchanged
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '.a'), '"42"')
+    r.assertEqual(r.execute_command('JSON.TYPE', 'doc', '.a'), 'string')
+
+def testGetNdjson(env):
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"x":1,"a":{"x":{"b":[1, 2]}},"c":[{"x":"s"},{"x":null}]}'))
+    res = r.execute_command('JSON.GET', 'doc', 'NDJSON', 'PRETTY', '$..x')
+    r.assertEqual(res, '1\n{"b":[1,2]}\n"s"\nnull\n')
+    r.assertEqual([json.loads(line) for line in res.splitlines()], [1, {"b": [1, 2]}, "s", None])
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'NDJSON', 'MATCHLIMIT', '2', '$..x'), '1\n{"b":[1,2]}\n')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'NDJSON', '$.nosuch'), '')
+    r.expect('JSON.GET', 'doc', 'NDJSON', 'POINTER', '/x').error().contains('NDJSON')
+    r.expect('JSON.GET', 'doc', 'NDJSON', '$.x', '$.a').error().contains('NDJSON requires a single JSONPath')
+    r.expect('JSON.GET', 'doc', 'NDJSON', 'WITHCOUNT', '$..x').error()
+
+def testGetCaseInsensitive(env):
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"user":{"name":"a","Tags":["x",{"ID":1}]},"Key":1,"key":2}'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'CASEINSENSITIVE', '$.User.Name'), '["a"]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'CASEINSENSITIVE', '$.USER.tags[1].id'), '[1]')
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'CASEINSENSITIVE', '$.USER.NAME', '.user.TAGS[0]')),
+                  {"$.USER.NAME": "a", ".user.TAGS[0]": "x"})
+    # The exact case is preferred
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'CASEINSENSITIVE', '$.key'), '[2]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'CASEINSENSITIVE', '$.Key'), '[1]')
+    # Without the option the case matters
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.User.Name'), '[]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'CASEINSENSITIVE', '$.User.nosuch'), '[]')
+    r.expect('JSON.GET', 'doc', 'CASEINSENSITIVE', '$..name').error().contains('CASEINSENSITIVE requires')
+
+def testGetSize(env):
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"a":{"x":1,"s":"caf\u00e9"},"b":[{"x":2.5},{"x":null}]}'))
+    for args in [['POINTER', ''], ['POINTER', '/a'], ['$..x'], ['$.a', '$.b'], ['PRETTY', '$'], ['SKIPNULL', 'STRINGNUMS', 'POINTER', '/b'], ['NDJSON', '$..x']]:
+        res = r.execute_command('JSON.GET', 'doc', *args)
+        r.assertEqual(r.execute_command('JSON.GET', 'doc', 'SIZE', *args), len(res.encode('utf-8')))
+    res = r.execute_command('JSON.GET', 'doc', '$..x')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'SIZE', 'WITHCOUNT', '$..x'), [3, len(res)])
+    r.assertIsNone(r.execute_command('JSON.GET', 'nosuch', 'SIZE', '$'))
+    r.expect('JSON.GET', 'doc', 'SIZE', 'COMPRESS', 'GZIP', '$').error().contains('SIZE')
+
+def testGetFlatMap(env):
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"a":{"b":1,"c":{"d":"x","e":[true,null]}},"f":[],"g/h":{},"n":null}'))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'FLATMAP', 'POINTER', '')),
+                  {"/a/b": 1, "/a/c/d": "x", "/a/c/e/0": True, "/a/c/e/1": None, "/f": [], "/g~1h": {}, "/n": None})
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'FLATMAP', 'SKIPNULL', 'STRINGNUMS', 'POINTER', '/a')),
+                  {"/b": "1", "/c/d": "x", "/c/e/0": True, "/c/e/1": None})
+    # Every match is flattened on its own
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'FLATMAP', '$.a.c', '$.a.b')),
+                  {"$.a.c": {"/d": "x", "/e/0": True, "/e/1": None}, "$.a.b": {"": 1}})
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'FLATMAP', '$..c')), [{"/d": "x", "/e/0": True, "/e/1": None}])
+    r.expect('JSON.GET', 'doc', 'FLATMAP', 'ONLYKEYS', '$').raiseError()
+
+def testGetWithIndices(env):
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"arr":["a",{"b":1},[2]],"o":{"arr":[]},"s":"x"}'))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'WITHINDICES', 'POINTER', '/arr')), [[0, "a"], [1, {"b": 1}], [2, [2]]])
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'WITHINDICES', '$..arr')), [[[0, "a"], [1, {"b": 1}], [2, [2]]], []])
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'WITHINDICES', 'STRINGNUMS', '$.arr')), [[[0, "a"], [1, {"b": "1"}], [2, ["2"]]]])
+    r.expect('JSON.GET', 'doc', 'WITHINDICES', 'POINTER', '/s').raiseError().contains('WITHINDICES requires array matches')
+    r.expect('JSON.GET', 'doc', 'WITHINDICES', '$').raiseError()
+    r.expect('JSON.GET', 'doc', 'WITHINDICES', 'FLATMAP', '$.arr').raiseError()
+
+def testGetRedact(env):
+    """Test JSON.GET REDACT"""
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"name":"Ann","email":"ann@example.com","age":30,"contacts":[{"email":"bob@example.com","note":"at home"}]}'))
+    email = '^[^@\\s]+@[^@\\s]+$'
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'REDACT', email, 'POINTER', '')),
+                  {'name': 'Ann', 'email': '***', 'age': 30, 'contacts': [{'email': '***', 'note': 'at home'}]})
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', '$..email', 'redact', email)), ['***', '***'])
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'POINTER', '/contacts', 'REDACT', email, 'PRETTY')),
+                  [{'email': '***', 'note': 'at home'}])
+    # Unanchored patterns match parts of strings
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'POINTER', '/contacts/0/note', 'REDACT', 'home')), '***')
+    # The stored value is unchanged
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', '.email')), 'ann@example.com')
+    r.expect('JSON.GET', 'doc', 'REDACT', '(', '$').raiseError().contains('invalid REDACT regex')
+
+def testGetCsv(env):
+    """Test JSON.GET CSV"""
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"n":1,"rows":[{"name":"Ann","age":30},{"name":"Bob, Jr.","age":null,"tags":["a"]}]}'))
+    table = 'age,name,tags\r\n30,Ann,\r\n,"Bob, Jr.","[""a""]"\r\n'
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'POINTER', '/rows', 'CSV'), table)
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'csv', '$.rows'), table)
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.empty', '[]'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'POINTER', '/empty', 'CSV'), '')
+
+    r.expect('JSON.GET', 'doc', 'POINTER', '/n', 'CSV').raiseError().contains('CSV requires an array of objects')
+    r.expect('JSON.GET', 'doc', '$..name', 'CSV').raiseError().contains('CSV requires a single array match')
+    r.expect('JSON.GET', 'doc', '$.rows', '$.n', 'CSV').raiseError()
+    r.expect('JSON.GET', 'doc', '$.rows', 'CSV', 'NDJSON').raiseError()
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.rows[1]', '2'))
+    r.expect('JSON.GET', 'doc', 'POINTER', '/rows', 'CSV').raiseError().contains('found integer')
+
+def testGetStats(env):
+    """Test JSON.GET STATS"""
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"scores":[90,75.5,100],"ints":[3,1,2],"empty":[],"mixed":[1,"a"],"s":"x"}'))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', '$.scores', 'STATS')),
+                  [{'count': 3, 'sum': 265.5, 'min': 75.5, 'max': 100, 'avg': 88.5}])
+    stats = json.loads(r.execute_command('JSON.GET', 'doc', 'STATS', 'POINTER', '/ints'))
+    r.assertEqual(stats, {'count': 3, 'sum': 6, 'min': 1, 'max': 3, 'avg': 2.0})
+    r.assertTrue(isinstance(stats['sum'], int))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'STATS', 'POINTER', '/empty')),
+                  {'count': 0, 'sum': 0, 'min': None, 'max': None, 'avg': None})
+    # The stored arrays are unchanged
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', '.ints')), [3, 1, 2])
+    r.expect('JSON.GET', 'doc', 'STATS', 'POINTER', '/mixed').raiseError().contains('STATS requires arrays of numbers, found string')
+    r.expect('JSON.GET', 'doc', 'STATS', 'POINTER', '/s').raiseError().contains('STATS requires array matches')
+    r.expect('JSON.GET', 'doc', 'STATS', 'WITHINDICES', '$.ints').raiseError()
+
+def testGetEtag(env):
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"b":{"x":1,"y":[true]},"a":"s"}'))
+    r.assertOk(r.execute_command('JSON.SET', 'other', '$', '{"a":"s","b":{"y":[true],"x":1}}'))
+    etag = r.execute_command('JSON.GET', 'doc', 'ETAG', 'POINTER', '')
+    # 64-bit FNV-1a of the compact serialization
+    h = 0xcbf29ce484222325
+    for b in r.execute_command('JSON.GET', 'doc').encode():
+        h = ((h ^ b) * 0x100000001b3) % 2**64
+    r.assertEqual(etag, '%016x' % h)
+    # Equal values have equal etags, whatever their formatting or the order of their keys
+    r.assertEqual(r.execute_command('JSON.GET', 'other', 'ETAG', 'PRETTY', 'POINTER', ''), etag)
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'ETAG', '$.b'), r.execute_command('JSON.GET', 'other', 'ETAG', '$.b'))
+    r.assertNotEqual(r.execute_command('JSON.GET', 'doc', 'ETAG', '$.b'), etag)
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.b.x', '2'))
+    r.assertNotEqual(r.execute_command('JSON.GET', 'doc', 'ETAG', 'POINTER', ''), etag)
+    r.expect('JSON.GET', 'doc', 'ETAG', '$.a', '$.b').raiseError()
+    r.expect('JSON.GET', 'doc', 'ETAG', 'SIZE', '$').raiseError()
+
+def testGetBinaryPath(env):
+    """Test JSON.GET with paths that aren't plain ASCII"""
+    r = env
+
+    r.expect('JSON.SET', 'test', '$', '{"100%25":1,"100%":2,"caf\u00e9":3}').ok()
+    # Percent escapes are not decoded
+    r.expect('JSON.GET', 'test', "$['100%25']").equal('[1]')
+    r.expect('JSON.GET', 'test', "$['100%']").equal('[2]')
+    r.expect('JSON.GET', 'test', "$['caf\u00e9']".encode('utf-8')).equal('[3]')
+
+    # A byte that isn't valid UTF-8 can't be part of a key
+    r.expect('JSON.GET', 'test', b"$['caf\xe9']").error().contains('not valid UTF-8')
+    r.expect('JSON.GET', 'test', '$', b"$['\xff']").error().contains('not valid UTF-8')
+    r.expect('JSON.GET', 'test', 'BASE', b"$['\xff']", 'THEN', '$').error().contains('not valid UTF-8')
+
+
+def testGetCoerceSchema(env):
+    """Test JSON.GET COERCESCHEMA"""
+    r = env
+
+    doc = {'id': 42, 'price': '9.5', 'qty': 3.0, 'ok': 'true', 'n': None, 'tags': [1, 2],
+           'dims': {'w': '10', 'h': 20}, 'items': [{'id': 1}, {'id': 2.5}]}
+    r.expect('JSON.SET', 'test', '$', json.dumps(doc)).ok()
+    res = r.execute_command('JSON.GET', 'test', 'POINTER', '', 'COERCESCHEMA', '{"id":"string","price":"number","qty":"integer","ok":"boolean","n":"string"}')
+    res = json.loads(res)
+    r.assertEqual(res['id'], '42')
+    r.assertEqual(res['price'], 9.5)
+    r.assertEqual(res['qty'], 3)
+    r.assertEqual(res['ok'], True)
+    r.assertEqual(res['n'], None)
+    # Members missing from the schema are kept as they are
+    r.assertEqual(res['tags'], [1, 2])
+
+    # Nested objects and arrays of objects
+    schema = '{"dims":{"w":"integer","h":"string"},"items":{"id":"string"},"missing":"number"}'
+    res = json.loads(r.execute_command('JSON.GET', 'test', '$', 'COERCESCHEMA', schema))
+    r.assertEqual(res[0]['dims'], {'w': 10, 'h': '20'})
+    r.assertEqual(res[0]['items'], [{'id': '1'}, {'id': '2.5'}])
+    r.expect('JSON.GET', 'test', '$.dims', 'COERCESCHEMA', '{"w":"number"}').equal('[{"h":20,"w":10}]')
+
+    # Incompatible casts fail
+    r.expect('JSON.GET', 'test', 'POINTER', '', 'COERCESCHEMA', '{"dims":"number"}').error().contains("can't coerce object to number")
+    r.expect('JSON.GET', 'test', 'POINTER', '', 'COERCESCHEMA', '{"items":{"id":"integer"}}').error().contains("can't coerce number to integer")
+    r.expect('JSON.GET', 'test', 'POINTER', '', 'COERCESCHEMA', '{"id":{"x":"string"}}').error().contains("can't coerce integer to object")
+    r.expect('JSON.GET', 'test', 'COERCESCHEMA', '{"id":"date"}', '$').raiseError()
+    r.expect('JSON.GET', 'test', 'COERCESCHEMA', '["string"]', '$').raiseError()
+    r.expect('JSON.GET', 'test', 'COERCESCHEMA', '{"id":"string"}', 'ONLYKEYS', '$').raiseError()
+    # The stored value is unchanged
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test')), doc)
+
+
+def testGetWithVersion(env):
+    """Test JSON.GET WITHVERSION"""
+    r = env
+
+    r.cmd('DEL', 'doc', 'missing')
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"a":1}'))
+    r.expect('JSON.GET', 'doc', 'WITHVERSION', 'POINTER', '').equal([1, '{"a":1}'])
+    r.expect('JSON.GET', 'doc', '$.a', 'WITHVERSION').equal([1, '[1]'])
+    # Every write bumps the version
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.a', '2'))
+    r.expect('JSON.GET', 'doc', 'WITHVERSION', 'POINTER', '').equal([2, '{"a":2}'])
+    r.expect('JSON.NUMINCRBY', 'doc', '$.a', '1').equal('[3]')
+    r.expect('JSON.GET', 'doc', 'WITHVERSION', 'POINTER', '').equal([3, '{"a":3}'])
+    # Reads don't
+    r.expect('JSON.GET', 'doc', '$.a').equal('[3]')
+    r.expect('JSON.GET', 'doc', 'POINTER', '/a', 'WITHVERSION').equal([3, '3'])
+    # The version of a document without other state starts over when it is loaded
+    r.assertOk(r.execute_command('DEBUG', 'RELOAD'))
+    r.expect('JSON.GET', 'doc', 'WITHVERSION', 'POINTER', '').equal([0, '{"a":3}'])
+    # otherwise it survives a reload
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.a', '4'))
+    r.assertOk(r.execute_command('JSON.FREEZE', 'doc'))
+    version = r.execute_command('JSON.GET', 'doc', 'WITHVERSION', 'POINTER', '')
+    r.assertOk(r.execute_command('DEBUG', 'RELOAD'))
+    r.expect('JSON.GET', 'doc', 'WITHVERSION', 'POINTER', '').equal(version)
+    r.assertOk(r.execute_command('JSON.UNFREEZE', 'doc'))
+    # A recreated key starts over
+    r.expect('JSON.DEL', 'doc').equal(1)
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '[]'))
+    r.expect('JSON.GET', 'doc', 'WITHVERSION', 'POINTER', '').equal([1, '[]'])
+    r.expect('JSON.GET', 'missing', 'WITHVERSION', 'POINTER', '').equal(None)
+
+def testGetPointer(env):
+    """Test JSON.GET POINTER"""
+    r = env
+
+    r.expect('JSON.SET', 'test', '$', '{"user":{"name":"foo","a/b":1,"m~n":2},"items":[10,{"0":20}]}').ok()
+    r.expect('JSON.GET', 'test', 'POINTER', '/user/name').equal('"foo"')
+    r.expect('JSON.GET', 'test', 'POINTER', '/items/0').equal('10')
+    r.expect('JSON.GET', 'test', 'pointer', '/items/1/0').equal('20')
+    r.expect('JSON.GET', 'test', 'POINTER', '/user/a~1b').equal('1')
+    r.expect('JSON.GET', 'test', 'POINTER', '/user/m~0n').equal('2')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', 'POINTER', ''), r.execute_command('JSON.GET', 'test'))
+    r.expect('JSON.GET', 'test', 'POINTER', '/items', 'WRAP').equal('[[10,{"0":20}]]')
+
+    # Missing values, which includes array indexes that aren't plain numbers
+    r.expect('JSON.GET', 'test', 'POINTER', '/user/age').error().contains('does not exist')
+    r.expect('JSON.GET', 'test', 'POINTER', '/items/2').error().contains('does not exist')
+    r.expect('JSON.GET', 'test', 'POINTER', '/items/01').error().contains('does not exist')
+    r.expect('JSON.GET', 'test', 'POINTER', '/items/-').error().contains('does not exist')
+    # Followed by anything but a JSON Pointer it is a legacy path
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test', 'POINTER', 'user')),
+                  {'POINTER': None, 'user': {'name': 'foo', 'a/b': 1, 'm~n': 2}})
+    r.expect('JSON.GET', 'test', 'POINTER', '/user', '$.items').raiseError()
+    r.assertIsNone(r.execute_command('JSON.GET', 'missing', 'POINTER', '/user'))
+
+
+def testGetExclude(env):
+    """Test JSON.GET EXCLUDE"""
+    r = env
+
+    doc = {'name': 'foo', 'secret': 's3cr3t', 'internal': {'id': 1}, 'tags': ['a', 'b', 'c']}
+    r.expect('JSON.SET', 'test', '$', json.dumps(doc)).ok()
+    r.expect('JSON.GET', 'test', 'EXCLUDE', 2, '$.secret', '$.internal').equal('{"name":"foo","tags":["a","b","c"]}')
+    res = json.loads(r.execute_command('JSON.GET', 'test', '$', 'EXCLUDE', 2, '$.secret', '$.internal'))
+    r.assertEqual(res, [{'name': 'foo', 'tags': ['a', 'b', 'c']}])
+    r.assertFalse('secret' in res[0] or 'internal' in res[0])
+
+    # Composes with a path, evaluated on the pruned document
+    r.expect('JSON.GET', 'test', '$.secret', 'EXCLUDE', 1, '$.secret').equal('[]')
+    r.expect('JSON.GET', 'test', 'POINTER', '/internal', 'EXCLUDE', 1, '.internal.id').equal('{}')
+    r.expect('JSON.GET', 'test', 'BASE', '$', 'THEN', '$.tags', 'EXCLUDE', 1, '$.tags[1]').equal('[["a","c"]]')
+
+    # Several and overlapping matches
+    r.expect('JSON.GET', 'test', '$.tags', 'EXCLUDE', 2, '$.tags[0]', '$.tags[2]').equal('[["b"]]')
+    r.expect('JSON.GET', 'test', '$.tags', 'EXCLUDE', 2, '$.tags[*]', '$.tags[0]').equal('[[]]')
+    r.expect('JSON.GET', 'test', '.name', 'EXCLUDE', 3, '$.internal', '$.internal.id', '$.missing').equal('"foo"')
+
+    # The stored document is unchanged
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test')), doc)
+
+    # Arguments after the excluded paths are read as usual
+    r.assertEqual(r.execute_command('JSON.GET', 'test', 'EXCLUDE', 1, '$.internal', '$.name', 'WITHCOUNT'), [1, '["foo"]'])
+    r.expect('JSON.GET', 'test', 'EXCLUDE', 1, '$.secret', '.name').equal('"foo"')
+
+    r.expect('JSON.GET', 'test', 'EXCLUDE', 1, '$').raiseError()
+    r.expect('JSON.GET', 'test', 'EXCLUDE', '$').raiseError()
+    r.expect('JSON.GET', 'test', 'EXCLUDE', '$.secret').raiseError()
+    r.expect('JSON.GET', 'test', 'EXCLUDE', 0, '$').raiseError()
+    r.expect('JSON.GET', 'test', 'EXCLUDE', 2, '$.secret').raiseError()
+    r.expect('JSON.GET', 'missing', 'EXCLUDE', 1, '$.secret').equal(None)
+
+
+def testGetSubcommandNamedKeys(env):
+    """Test that JSON.GET reads bare subcommand names as legacy paths outside JSONPath mode"""
+    r = env
+
+    r.expect('JSON.SET', 'doc', '$', '{"size":1,"csv":"a","wrap":[2],"etag":null}').ok()
+    # Without a JSONPath or a JSON Pointer a bare name is a legacy path, as it always was
+    r.expect('JSON.GET', 'doc', 'size').equal('1')
+    r.expect('JSON.GET', 'doc', 'wrap').equal('[2]')
+    r.expect('JSON.GET', 'doc', 'etag').equal('null')
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'size', 'csv')), {'size': 1, 'csv': 'a'})
+    # With one it is the subcommand
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'size', '$'), len('[{"csv":"a","etag":null,"size":1,"wrap":[2]}]'))
+    r.expect('JSON.GET', 'doc', 'wrap', 'POINTER', '/csv').equal('["a"]')
+    # A leading dot or a JSONPath selects the key
+    r.expect('JSON.GET', 'doc', '.size').equal('1')
+    r.expect('JSON.GET', 'doc', '$.size').equal('[1]')
+    r.expect('JSON.GET', 'doc', '.csv').equal('"a"')
+
+
+def testGetOnlyKeys(env):
+    """Test JSON.GET ONLYKEYS"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$',
+                                 '{"a":{"user":{"id":1,"name":"x"}},"b":{"user":{"id":2,"email":"y","tags":{"t":1}}},"c":{"user":"anon"}}'))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'ONLYKEYS', '$..user')),
+                  [['id', 'name'], ['email', 'id', 'tags'], None])
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'onlykeys', 'POINTER', '/a/user')), ['id', 'name'])
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'ONLYKEYS', 'POINTER', '')), ['a', 'b', 'c'])
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'ONLYKEYS', 'POINTER', '/c/user'), 'null')
+
+def testGetWrap(env):
+    """Test JSON.GET WRAP"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"a":{"b":1},"c":[2,3]}'))
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.a.b'), '1')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', 'WRAP', 'POINTER', '/a/b'), '[1]')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', 'wrap', 'POINTER', ''), '[{"a":{"b":1},"c":[2,3]}]')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', 'WRAP', '$'), '[{"a":{"b":1},"c":[2,3]}]')
+    # JSONPaths already reply with arrays
+    r.assertEqual(r.execute_command('JSON.GET', 'test', 'WRAP', '$.c[*]'), '[2,3]')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', 'WRAP', '$.missing'), '[]')
+    r.expect('JSON.GET', 'test', 'WRAP', '$.a', '$.c').raiseError()
+
+def testGetRebased(env):
+    """Test JSON.GET BASE ... THEN ..."""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"user":{"name":"Alice","team":{"name":"core"}},"users":[{"name":"Bob"},{"name":"Carol"}]}'))
+    r.assertEqual(r.execute_command('JSON.GET', 'test', 'BASE', '$.user', 'THEN', '$.name'), '["Alice"]')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', 'base', '$.user', 'then', '$..name'), '["Alice","core"]')
+    # Every base match gets its own results
+    r.assertEqual(r.execute_command('JSON.GET', 'test', 'BASE', '$.users[*]', 'THEN', '$.name'), '[["Bob"],["Carol"]]')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', 'BASE', '$.missing', 'THEN', '$.name'), '[]')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', 'BASE', '$.user', 'THEN', '$.missing'), '[]')
+
+    r.expect('JSON.GET', 'test', 'BASE', '$.user').raiseError()
+    r.expect('JSON.GET', 'test', 'THEN', '$.name').raiseError()
+    r.expect('JSON.GET', 'test', '$.users', 'BASE', '$.user', 'THEN', '$.name').raiseError()
+    r.expect('JSON.GET', 'test', 'WITHCOUNT', 'BASE', '$.user', 'THEN', '$.name').raiseError()
+
+def testGetCompress(env):
+    """Test JSON.GET COMPRESS GZIP"""
+    import gzip
+    r = env
+
+    doc = {'items': [{'id': i, 'name': 'item {}'.format(i), 'tags': ['a', 'b', 'c']} for i in range(1000)]}
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', json.dumps(doc)))
+    plain = r.execute_command('JSON.GET', 'test', '.')
+
+    # The compressed reply is binary, so it is read without decoding
+    kwargs = dict(r.getConnection().connection_pool.connection_kwargs, decode_responses=False)
+    raw = redis.Redis(connection_pool=redis.ConnectionPool(**kwargs))
+    compressed = raw.execute_command('JSON.GET', 'test', 'POINTER', '', 'COMPRESS', 'GZIP')
+    r.assertLess(len(compressed), len(plain))
+    r.assertEqual(gzip.decompress(compressed).decode('utf-8'), plain)
+    r.assertEqual(json.loads(gzip.decompress(compressed)), doc)
+
+    compressed = raw.execute_command('JSON.GET', 'test', 'compress', 'gzip', '$.items[0].id')
+    r.assertEqual(gzip.decompress(compressed), b'[0]')
+    r.assertIsNone(raw.execute_command('JSON.GET', 'missing', 'COMPRESS', 'GZIP', '$'))
+    r.expect('JSON.GET', 'test', 'COMPRESS', 'ZSTD', '$').raiseError()
+
+def testGetWithCount(env):
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"x":1,"a":{"x":[2]},"b":[{"x":null}]}'))
+    count, values = r.execute_command('JSON.GET', 'doc', '$..x', 'WITHCOUNT')
+    r.assertEqual(count, len(json.loads(values)))
+    r.assertEqual(count, 3)
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'WITHCOUNT', '$.nope'), [0, '[]'])
+    r.expect('JSON.GET', 'doc', 'POINTER', '/x', 'WITHCOUNT').error().contains('WITHCOUNT')
+    r.expect('JSON.GET', 'doc', '$.x', '$.a', 'WITHCOUNT').error().contains('WITHCOUNT requires a single JSONPath')
+
+def testGetWithPaths(env):
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"name":"root","a":{"name":"x"},"b":[{"name":"y"},{"c":1}]}'))
+    res = json.loads(r.execute_command('JSON.GET', 'doc', '$..name', 'WITHPATHS'))
+    r.assertEqual(sorted([(m['path'], m['value']) for m in res]),
+                  [("$['a']['name']", 'x'), ("$['b'][0]['name']", 'y'), ("$['name']", 'root')])
+    # Every path resolves to its value
+    for m in res:
+        r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', m['path'])), [m['value']])
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.nope', 'WITHPATHS'), '[]')
+    r.expect('JSON.GET', 'doc', 'POINTER', '/name', 'WITHPATHS').raiseError()
+    r.expect('JSON.GET', 'doc', '$..name', 'WITHPATHS', 'WITHCOUNT').raiseError()
+
 def testBackwardRDB(env):
     env.skipOnCluster() 
     dbFileName = env.cmd('config', 'get', 'dbfilename')[1]
@@ -283,6 +989,91 @@ def testBackwardRDB(env):
     data = json.loads(r.execute_command('JSON.GET', 'complex'))
     r.assertEqual(data, {"a":{"b":[{"c":{"d":[1,'2'],"e":None}},True],"a":'a'},"b":1,"c":True,"d":None})
 
+def rdbLen(n):
+    """The RDB encoding of a length"""
+    if n < 1 << 6:
+        return bytes([n])
+    if n < 1 << 14:
+        return bytes([0x40 | n >> 8, n & 0xff])
+    if n < 1 << 32:
+        return b'\x80' + n.to_bytes(4, 'big')
+    return b'\x81' + n.to_bytes(8, 'big')
+
+def rdbJsonValue(doc):
+    """The RDB serialization of a JSON document by RedisJSON 2.0, whose encoding version is 3"""
+    charset = b'ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_'
+    type_id = 0
+    for c in b'ReJSON-RL':
+        type_id = type_id << 6 | charset.index(c)
+    type_id = type_id << 10 | 3
+    # A module value of the type, holding a single string
+    return b'\x07' + rdbLen(type_id) + b'\x05' + rdbLen(len(doc)) + doc + b'\x00'
+
+def testRDBVersion3(env):
+    """Test that documents without other state keep the RDB encoding of RedisJSON 2.0"""
+    env.skipOnCluster()
+    r = env
+
+    # Neither an integer nor long enough to be compressed, so that it is saved as it is
+    doc = b'{"a":[1,"b"]}'
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', doc))
+    kwargs = dict(r.getConnection().connection_pool.connection_kwargs, decode_responses=False)
+    raw = redis.Redis(connection_pool=redis.ConnectionPool(**kwargs))
+    # The payload is followed by the RDB version and a checksum
+    r.assertEqual(raw.execute_command('DUMP', 'doc')[:-10], rdbJsonValue(doc))
+    r.assertOk(r.execute_command('JSON.FREEZE', 'doc'))
+    r.assertNotEqual(raw.execute_command('DUMP', 'doc')[:-10], rdbJsonValue(doc))
+
+    # An RDB file saved by RedisJSON 2.0, without a checksum
+    dbFileName = env.cmd('config', 'get', 'dbfilename')[1]
+    dbDir = env.cmd('config', 'get', 'dir')[1]
+    rdbFilePath = os.path.join(dbDir, dbFileName)
+    env.stop()
+    try:
+        os.unlink(rdbFilePath)
+    except OSError:
+        pass
+    value = rdbJsonValue(doc)
+    with open(rdbFilePath, 'wb') as f:
+        f.write(b'REDIS0009\xfe\x00' + value[:1] + rdbLen(3) + b'old' + value[1:] + b'\xff' + b'\x00' * 8)
+    env.start()
+
+    r.assertEqual(r.execute_command('JSON.GET', 'old'), doc.decode())
+    r.expect('JSON.GET', 'old', 'WITHVERSION', 'POINTER', '').equal([0, doc.decode()])
+    r.assertOk(r.execute_command('JSON.SET', 'old', '$.a[0]', '2'))
+    r.assertEqual(r.execute_command('JSON.GET', 'old'), '{"a":[2,"b"]}')
+
+def testControlCharsEscaping(env):
+    """Test that control characters in strings are escaped as RFC 8259 requires"""
+    r = env
+
+    s = u'a\nb\tc\u0000d\u001fe\r\b\f"\\\u007f'
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', json.dumps({'s': s})))
+    expected = u'"a\\nb\\tc\\u0000d\\u001fe\\r\\b\\f\\"\\\\\u007f"'
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.s'), expected)
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '$.s'), '[' + expected + ']')
+    # Formatting options don't change how strings are escaped
+    res = r.execute_command('JSON.GET', 'test', 'INDENT', '\t', 'NEWLINE', '\n', 'SPACE', ' ', '.')
+    r.assertEqual(res, '{\n\t"s": ' + expected + '\n}')
+    r.assertEqual(json.loads(res)['s'], s)
+    # Keys are escaped the same way
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', json.dumps({'k\n\u0001': 1})))
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.'), '{"k\\n\\u0001":1}')
+
+def testInvalidUTF8(env):
+    """Test that invalid UTF-8 input never makes it to a reply"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', b'{"s":"a\xff\xfeb"}'))
+    # Invalid bytes are replaced when the input is read, so replies are valid UTF-8
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.s'), u'"a\ufffd\ufffdb"')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.s', 'NOESCAPE'), u'"a\ufffd\ufffdb"')
+    r.assertEqual(r.execute_command('JSON.STRLEN', 'test', '.s'), len(u'a\ufffd\ufffdb'.encode('utf-8')))
+    # Non-ASCII characters are never escaped, with or without NOESCAPE
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', json.dumps({'s': u'caf\u00e9 \u2603'})))
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.s'), u'"caf\u00e9 \u2603"')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.s', 'NOESCAPE'), u'"caf\u00e9 \u2603"')
+
 def testSetBSON(env):
     r = env
     bson = open(os.path.join(JSON_PATH , 'bson_bytes_1.bson'), 'rb').read()
@@ -321,6 +1112,32 @@ def testMgetCommand(env):
     r.assertEqual(raw[0], None)
     r.assertEqual(raw[1], None)
 
+def testMgetMultiPathCommand(env):
+    """Test JSON.MGET with PATHS"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'a', '.', '{"x":1,"y":{"z":"a"}}'))
+    r.assertOk(r.execute_command('JSON.SET', 'b', '.', '{"x":2,"y":{"z":"b"}}'))
+    raw = r.execute_command('JSON.MGET', 'a', 'b', 'missing', 'PATHS', 2, '.x', '.y.z')
+    r.assertEqual(len(raw), 3)
+    r.assertEqual(json.loads(raw[0]), {'.x': 1, '.y.z': 'a'})
+    r.assertEqual(json.loads(raw[1]), {'.x': 2, '.y.z': 'b'})
+    r.assertEqual(raw[2], None)
+    # Same reply as JSON.GET with the same paths
+    raw = r.execute_command('JSON.MGET', 'a', 'paths', 2, '$..z', '.x')
+    r.assertEqual(len(raw), 1)
+    r.assertEqual(json.loads(raw[0]), json.loads(r.execute_command('JSON.GET', 'a', '$..z', '.x')))
+    r.expect('JSON.MGET', 'PATHS', 2, '.x', '.y').raiseError()
+
+    # Without a matching count, "paths" is a key or a path
+    r.assertOk(r.execute_command('JSON.SET', 'paths', '.', '{"x":3,"paths":4}'))
+    r.expect('JSON.MGET', 'a', 'b', 'paths').equal([None, None])
+    r.expect('JSON.MGET', 'paths', 'a', 'paths').equal(['4', None])
+    r.expect('JSON.MGET', 'a', 'paths', '.x').equal(['1', '3'])
+    r.expect('JSON.MGET', 'a', 'PATHS', '.x', '.y').equal(['{"z":"a"}', None, None])
+    r.expect('JSON.MGET', 'a', 'PATHS', 3, '.x', '.y').equal(['{"z":"a"}', None, None, None])
+    r.expect('JSON.MGET', 'a', 'PATHS', 1, '.x').equal([r.execute_command('JSON.GET', 'a', '.x')])
+
     # Test that MGET fails on path errors
     r.cmd('DEL', 'test')
     r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"bull":4.2}'))
@@ -330,6 +1147,25 @@ def testMgetCommand(env):
     r.assertEqual(raw[1], None)
     r.assertTrue(json.loads(raw[2]))
 
+def testMgetStrict(env):
+    """Test JSON.MGET with STRICT"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc:0', '.', '{"bool":true}'))
+    r.cmd('DEL', 'str', 'missing')
+    r.cmd('SET', 'str', 'foo')
+    # Lenient by default
+    r.expect('JSON.MGET', 'doc:0', 'missing', '.bool').equal(['true', None])
+    r.expect('JSON.MGET', 'doc:0', 'doc:0', 'bad[path').equal([None, None])
+    # STRICT names the key that failed
+    r.expect('JSON.MGET', 'doc:0', 'missing', '.bool', 'STRICT').equal(['true', None])
+    r.expect('JSON.MGET', 'doc:0', 'str', '.bool', 'STRICT').error().contains("WRONGTYPE key 'str'")
+    r.expect('JSON.MGET', 'doc:0', 'str', 'PATHS', 2, '.bool', '.x', 'strict').error().contains("WRONGTYPE key 'str'")
+    r.expect('JSON.MGET', 'doc:0', 'bad[path', 'STRICT').error().contains("ERR key 'doc:0'")
+    r.expect('JSON.MGET', 'missing', 'doc:0', '.nosuchpath', 'STRICT').error().contains("ERR key 'doc:0'")
+    # A lone trailing STRICT is still the path
+    r.expect('JSON.MGET', 'doc:0', 'STRICT').equal([None])
+
 def testToggleCommand(env):
     """Test REJSON.TOGGLE command"""
     r = env
@@ -345,6 +1181,87 @@ def testToggleCommand(env):
     r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"foo":"bar"}'))
     r.expect('JSON.TOGGLE','test','.foo').raiseError()
 
+def testDelByFilter(env):
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"items":[{"id":0,"expired":true},{"id":1,"expired":false},{"id":2,"expired":true},{"id":3,"expired":true},{"id":4,"expired":false}]}'))
+    r.assertEqual(r.execute_command('JSON.DEL', 'doc', '$.items[?(@.expired==true)]'), 3)
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', '$.items[*].id')), [1, 4])
+
+    # Many elements of the same array, up to its end
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"a":[0,1,2,3,4,5,6,7,8,9,10,11]}'))
+    r.assertEqual(r.execute_command('JSON.DEL', 'doc', '$.a[?(@>=2)]'), 10)
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a'), '[[0,1]]')
+
+    # Matches nested in other matches are deleted along with them
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"a":{"a":{"b":1}},"c":[{"a":2},{"a":3}],"d":[1,{"a":4}]}'))
+    r.assertEqual(r.execute_command('JSON.DEL', 'doc', '$..a'), 4)
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', '$')), [{"c":[{},{}],"d":[1,{}]}])
+
+def testPatchCommand(env):
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"a":{"b":1},"arr":[1,2],"x/y":"z","t~":true}'))
+
+    r.assertOk(r.execute_command('JSON.PATCH', 'doc', json.dumps([
+        {"op": "add", "path": "/a/c", "value": [3]},
+        {"op": "add", "path": "/arr/1", "value": 1.5},
+        {"op": "add", "path": "/arr/-", "value": 3},
+        {"op": "remove", "path": "/x~1y"},
+        {"op": "replace", "path": "/t~0", "value": False},
+        {"op": "move", "from": "/a/b", "path": "/b"},
+        {"op": "copy", "from": "/a/c", "path": "/c"},
+        {"op": "test", "path": "/arr", "value": [1, 1.5, 2, 3]},
+    ])))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', '$')),
+                  [{"a": {"c": [3]}, "arr": [1, 1.5, 2, 3], "t~": False, "b": 1, "c": [3]}])
+    # test compares numbers by value, whether they are integers or not
+    r.assertOk(r.execute_command('JSON.PATCH', 'doc', '[{"op": "test", "path": "/b", "value": 1.0},'
+                                                      ' {"op": "test", "path": "/arr", "value": [1.0, 1.5, 2, 3.0]}]'))
+    r.expect('JSON.PATCH', 'doc', '[{"op": "test", "path": "/arr/1", "value": 1}]').error().contains('test failed')
+
+    # A failing operation leaves the document unchanged
+    before = r.execute_command('JSON.GET', 'doc', '$')
+    r.expect('JSON.PATCH', 'doc', json.dumps([
+        {"op": "replace", "path": "/b", "value": 2},
+        {"op": "test", "path": "/b", "value": 3},
+    ])).error().contains('operation 1 failed: test failed')
+    for patch in [{"op": "remove", "path": "/nosuch"}, {"op": "add", "path": "/nosuch/a", "value": 1},
+                  {"op": "add", "path": "/arr/5", "value": 1}, {"op": "replace", "path": "/arr/01", "value": 1},
+                  {"op": "move", "from": "/a", "path": "/a/c/0"}, {"op": "copy", "from": "/nosuch", "path": "/d"},
+                  {"op": "nosuch", "path": "/a"}, {"op": "add", "path": "a", "value": 1}, {"op": "add", "path": "/d"}]:
+        r.expect('JSON.PATCH', 'doc', json.dumps([patch])).error().contains('operation 0 failed')
+    r.expect('JSON.PATCH', 'doc', '{"op": "remove", "path": "/a"}').error().contains('array of operations')
+    r.expect('JSON.PATCH', 'doc', '[').error().contains('invalid JSON Patch')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$'), before)
+
+    # The root can be replaced
+    r.assertOk(r.execute_command('JSON.PATCH', 'doc', '[{"op": "replace", "path": "", "value": [1]}]'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$'), '[[1]]')
+    r.expect('JSON.PATCH', 'nosuch', '[]').raiseError()
+
+def testDiffCommand(env):
+    """Test JSON.DIFF command"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"a":1,"b":{"c":[1,2,3],"d":"x"},"e/f":null}'))
+    other = {"a": 2, "b": {"c": [1, 5], "g": True}, "e/f": None}
+    patch = json.loads(r.execute_command('JSON.DIFF', 'doc', '$', json.dumps(other)))
+    r.assertEqual(patch, [{"op": "replace", "path": "/a", "value": 2},
+                          {"op": "replace", "path": "/b/c/1", "value": 5},
+                          {"op": "remove", "path": "/b/c/2"},
+                          {"op": "remove", "path": "/b/d"},
+                          {"op": "add", "path": "/b/g", "value": True}])
+    # The patch turns the document into the other one
+    r.assertOk(r.execute_command('JSON.PATCH', 'doc', json.dumps(patch)))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc')), other)
+    r.assertEqual(r.execute_command('JSON.DIFF', 'doc', '.', json.dumps(other)), '[]')
+    # Pointers are relative to the path, and escaped
+    r.assertEqual(json.loads(r.execute_command('JSON.DIFF', 'doc', '.b', '{"c":[1,5],"g":true,"h/~":1}')),
+                  [{"op": "add", "path": "/h~1~0", "value": 1}])
+    r.assertEqual(json.loads(r.execute_command('JSON.DIFF', 'doc', '.a', '"2"')), [{"op": "replace", "path": "", "value": "2"}])
+    r.assertIsNone(r.execute_command('JSON.DIFF', 'missing', '.', '{}'))
+    r.expect('JSON.DIFF', 'doc', '.nope', '{}').raiseError()
+    r.expect('JSON.DIFF', 'doc', '.', 'bad').raiseError()
+
 def testDelCommand(env):
     """Test REJSON.DEL command"""
     r = env
@@ -484,14 +1401,41 @@ def testClear(env):
     # Clear root
     r.expect('JSON.SET', 'test', '.', r'{"n":42,"s":"42","arr":[{"n":44},"s",{"n":{"a":1,"b":2}},{"n2":{"x":3.02,"n":["to","be","cleared",4],"y":4.91}}]}') \
         .ok()
-    # TODO: switch order of the following paths and expect .equals(2) when supporting multi-paths in JSON.CLEAR
+    # The second path no longer matches anything to clear
     r.expect('JSON.CLEAR', 'test', '$', '$.arr[2].n').equal(1)
     r.expect('JSON.GET', 'test', '$').equal('[{}]')
 
+    r.expect('JSON.SET', 'test', '.', multi_content).ok()
+    r.expect('JSON.CLEAR', 'test', '$.arr[2].n', '$').equal(2)
+    r.expect('JSON.GET', 'test', '$').equal('[{}]')
+
     r.expect('JSON.SET', 'test', '$', obj_content_legacy).ok()
     r.expect('JSON.CLEAR', 'test').equal(1)
     r.expect('JSON.GET', 'test', '$').equal('[{}]')
 
+def testClearVerbose(env):
+    r = env
+    r.expect('JSON.SET', 'test', '$', '{"a":{"x":[1,2],"y":{"z":1},"s":"str"},"b":[3],"c":{}}').ok()
+    r.expect('JSON.CLEAR', 'test', 'VERBOSE', '$.a.*', '$.b', '$.c').equal([2, 1, 0])
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test', '$')), [{"a": {"x": [], "y": {}, "s": "str"}, "b": [], "c": {}}])
+    r.expect('JSON.CLEAR', 'test', 'verbose').equal([1])
+    # A missing path clears nothing
+    r.expect('JSON.SET', 'test', '$', '{"a":[1],"b":[2]}').ok()
+    r.expect('JSON.CLEAR', 'test', 'VERBOSE', '$.a', '$.nosuch').error().contains('does not exist')
+    r.expect('JSON.GET', 'test', '$').equal('[{"a":[1],"b":[2]}]')
+
+def testClearScalars(env):
+    """Test JSON.CLEAR over mixed value types"""
+    r = env
+
+    r.expect('JSON.SET', 'test', '$', '{"i":42,"f":-1.5,"z":0,"s":"str","b":true,"n":null,"a":[1,2],"e":[],"o":{"x":1},"eo":{}}').ok()
+    # Only the non-zero numbers and the non-empty containers change
+    r.expect('JSON.CLEAR', 'test', '$.*').equal(4)
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test', '$')),
+                  [{"i": 0, "f": 0, "z": 0, "s": "str", "b": True, "n": None, "a": [], "e": [], "o": {}, "eo": {}}])
+    # Nothing is left to change
+    r.expect('JSON.CLEAR', 'test', '$.*').equal(0)
+
 def testArrayCRUD(env):
     """Test JSON Array CRUDness"""
 
@@ -557,6 +1501,47 @@ def testArrayCRUD(env):
     r.assertEqual(1, r.execute_command('JSON.ARRLEN', 'test', '.'))
     r.assertEqual('true', r.execute_command('JSON.GET', 'test', '[0]'))
 
+def testArrAppendMultiPath(env):
+    """Test JSON.ARRAPPEND over multiple matched arrays"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '$', '{"a":[1],"b":{"a":[]},"c":{"a":[1,2]}}'))
+    # The reply is the length of the last matched array
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '$..a', '{"x":[3]}', '"y"'), 4)
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test', '$')),
+                  [{"a": [1, {"x": [3]}, "y"], "b": {"a": [{"x": [3]}, "y"]}, "c": {"a": [1, 2, {"x": [3]}, "y"]}}])
+    # Every array got its own copy of the values
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '$.b.a[0].x', '4'), 2)
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '$..x'), '[[3],[3,4],[3]]')
+
+def testArrTrimKeep(env):
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"items":[{"id":1,"active":true},{"id":2,"active":false},{"id":3,"active":true},{"id":4}],"b":{"items":[{"active":false}]},"s":"x"}'))
+    r.assertEqual(r.execute_command('JSON.ARRTRIM', 'doc', '.items', 'KEEP', '@.active == true'), 2)
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.items[*].id'), '[1,3]')
+    # Every matched array is filtered on its own
+    r.assertEqual(r.execute_command('JSON.ARRTRIM', 'doc', '$..items', 'keep', '@.active == true'), 0)
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$..items'), '[[{"id":1,"active":true},{"id":3,"active":true}],[]]')
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.nums', '[5,1,7,3]'))
+    r.assertEqual(r.execute_command('JSON.ARRTRIM', 'doc', '$.nums', 'KEEP', '@ > 2'), 3)
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.nums'), '[[5,7,3]]')
+    r.expect('JSON.ARRTRIM', 'doc', '$.s', 'KEEP', '@ > 2').error().contains('not an array')
+    r.expect('JSON.ARRTRIM', 'doc', '$.nums', 'KEEP').raiseError()
+
+def testArrIndexPredicate(env):
+    """Test JSON.ARRINDEX PREDICATE"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.',
+                                 '{"items":[{"price":10},{"price":150},{"price":50},{"price":200}],"nums":[1,5,2,7]}'))
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.items', 'PREDICATE', '@.price > 100'), 1)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.items', 'predicate', '@.price < 100', 1), 2)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.items', 'PREDICATE', '@.price > 100', 2), 3)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.items', 'PREDICATE', '@.price > 100', 2, 3), -1)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.items', 'PREDICATE', '@.price > 1000'), -1)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.nums', 'PREDICATE', '@ > 4'), 1)
+    r.expect('JSON.ARRINDEX', 'test', '.nums', 'PREDICATE').raiseError()
+
 def testArrIndexCommand(env):
     """Test JSON.ARRINDEX command"""
     r = env
@@ -600,6 +1585,22 @@ def testArrInsertCommand(env):
     r.expect('JSON.ARRINSERT', 'test', '.arr', -10, '10').raiseError()
     r.expect('JSON.ARRINSERT', 'test', '.arr', 10, '10').raiseError()
 
+def testArrInsertIndexOf(env):
+    """Test JSON.ARRINSERT INDEXOF"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '$', '{"a":["x",{"id":2},"y"],"b":{"a":[{"id":2}]}}'))
+    r.assertEqual(r.execute_command('JSON.ARRINSERT', 'test', '.a', 'INDEXOF', '"y"', '"new"'), 4)
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.a'), '["x",{"id":2},"new","y"]')
+    # Every array inserts before its own match
+    r.execute_command('JSON.ARRINSERT', 'test', '$..a', 'indexof', '{"id":2}', '1', '2')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.a'), '["x",1,2,{"id":2},"new","y"]')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.b.a'), '[1,2,{"id":2}]')
+    # Nothing is inserted unless every array has the needle
+    r.expect('JSON.ARRINSERT', 'test', '$..a', 'INDEXOF', '"x"', '0').raiseError().contains('not found')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.b.a'), '[1,2,{"id":2}]')
+    r.expect('JSON.ARRINSERT', 'test', '.a', 'INDEXOF', 'bad', '0').raiseError()
+
 def testArrIndexMixCommand(env):
     """Test JSON.ARRINDEX command with mixed values"""
     r = env
@@ -616,6 +1617,8 @@ def testArrIndexMixCommand(env):
     r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', 0, 5, -1), 6)
     r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', 2, -2, 6), -1)
     r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '"foo"'), -1)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '3.0'), 3)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '[3.0,4,8]'), 9)
 
     r.assertEqual(r.execute_command('JSON.ARRINSERT', 'test', '.arr', 4, '[4]'), 12)
     r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', 3), 3)
@@ -624,6 +1627,24 @@ def testArrIndexMixCommand(env):
     r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '{\"val\":4}'), 8)
     r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '["a", "b", 8]'), 11)
 
+def testArrIndexContainers(env):
+    """Test JSON.ARRINDEX with JSON Objects and Arrays as the searched value"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"arr":[{"a":2},{"a":1,"b":[1]},{"a":1},[1,2],{"a":1}]}'))
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '{"a":1}'), 2)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '{"b":[1],"a":1}'), 1)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '{"a":1}', 3), 4)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '{"a":1}', 0, 2), -1)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '{"a":1}', 3, -1), -1)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '$.arr', '[1,2]'), 3)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '[2,1]'), -1)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '{"a":"1"}'), -1)
+    # A stop before -1 counts from the end like -1 does
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '{"a":1}', 0, -2), 2)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '{"a":1}', 0, -3), -1)
+    r.assertEqual(r.execute_command('JSON.ARRINDEX', 'test', '.arr', '{"a":2}', 0, -10), -1)
+
 def testArrTrimCommand(env):
     """Test JSON.ARRTRIM command"""
 
@@ -662,6 +1683,163 @@ def testArrPopCommand(env):
     r.assertIsNone(r.execute_command('JSON.ARRPOP', 'test', '.'))
     r.assertIsNone(r.execute_command('JSON.ARRPOP', 'test', '.', 2))
 
+def testArrAppendIdempotent(env):
+    """Test JSON.ARRAPPEND IDEMPOTENT"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"arr":[]}'))
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '.arr', 'IDEMPOTENT', 'tok1', 1, 2), 2)
+    # A retry with the same token is only acknowledged
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '.arr', 'idempotent', 'tok1', 1, 2), 2)
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.arr'), '[1,2]')
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '.arr', 'IDEMPOTENT', 'tok2', 3), 3)
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.arr'), '[1,2,3]')
+
+    # Tokens are scoped to their key
+    r.assertOk(r.execute_command('JSON.SET', 'other', '.', '[]'))
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'other', '.', 'IDEMPOTENT', 'tok1', 1), 1)
+    r.expect('JSON.ARRAPPEND', 'test', '.arr', 'IDEMPOTENT', 'tok3').raiseError()
+
+    # Tokens are saved with the key
+    r.assertOk(r.execute_command('DEBUG', 'RELOAD'))
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '.arr', 'IDEMPOTENT', 'tok2', 3), 3)
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.arr'), '[1,2,3]')
+    # and go away with it
+    r.assertEqual(r.execute_command('DEL', 'test'), 1)
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"arr":[]}'))
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '.arr', 'IDEMPOTENT', 'tok1', 1), 1)
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.arr'), '[1]')
+
+def testArrAppendMaxLen(env):
+    """Test JSON.ARRAPPEND MAXLEN"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'log', '.', '{"events":[1,2]}'))
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'log', '.events', 'MAXLEN', 3, 3), 3)
+    r.assertEqual(r.execute_command('JSON.GET', 'log', '.events'), '[1,2,3]')
+    # The oldest elements are dropped
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'log', '.events', 'MAXLEN', 3, 4, 5), 3)
+    r.assertEqual(r.execute_command('JSON.GET', 'log', '.events'), '[3,4,5]')
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'log', '.events', 'maxlen', 2, 6, 7, 8, 9), 2)
+    r.assertEqual(r.execute_command('JSON.GET', 'log', '.events'), '[8,9]')
+    # A retry isn't appended again
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'log', '.events', 'IDEMPOTENT', 'e10', 'MAXLEN', 2, 10), 2)
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'log', '.events', 'IDEMPOTENT', 'e10', 'MAXLEN', 2, 10), 2)
+    r.assertEqual(r.execute_command('JSON.GET', 'log', '.events'), '[9,10]')
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'log', '.new', 'UPSERT', 'MAXLEN', 2, 1, 2, 3), 2)
+    r.assertEqual(r.execute_command('JSON.GET', 'log', '.new'), '[2,3]')
+    r.expect('JSON.ARRAPPEND', 'log', '.events', 'MAXLEN', 0, 1).error().contains('MAXLEN must be positive')
+
+def testArrAppendTyped(env):
+    """Test JSON.ARRAPPEND TYPED"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"nums":[1,2],"empty":[]}'))
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '.nums', 'TYPED', 3, 4), 4)
+    r.expect('JSON.ARRAPPEND', 'test', '.nums', 'TYPED', '"five"').error().contains('element type mismatch')
+    r.expect('JSON.ARRAPPEND', 'test', '.nums', 'typed', 5, 5.5).error().contains('element type mismatch')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.nums'), '[1,2,3,4]')
+    # Without TYPED anything goes
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '.nums', '"five"'), 5)
+
+    # The first element sets the type of an empty array
+    r.expect('JSON.ARRAPPEND', 'test', '.empty', 'TYPED', '"a"', 1).error().contains('element type mismatch')
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '.empty', 'TYPED', '"a"'), 1)
+    r.expect('JSON.ARRAPPEND', 'test', '.empty', 'TYPED', '{}').error().contains('element type mismatch')
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '.empty', 'IDEMPOTENT', 't', 'TYPED', '"b"'), 2)
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.empty'), '["a","b"]')
+
+def testArrAppendUpsert(env):
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"a":{},"o":{"p":{},"q":{}},"s":"x"}'))
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'doc', '$.a.list', 'UPSERT', '1', '"two"'), 2)
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a.list'), '[[1,"two"]]')
+    # Existing arrays are appended to
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'doc', '.a.list', 'UPSERT', '3'), 3)
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '.a.list'), '[1,"two",3]')
+    # Every matched parent gets an array
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'doc', '$.o.*.tags', 'UPSERT', '"t"'), 1)
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', '$.o.*.tags')), [["t"], ["t"]])
+    r.expect('JSON.ARRAPPEND', 'doc', '$.s', 'UPSERT', '1').error().contains('does not exist')
+    r.expect('JSON.ARRAPPEND', 'doc', '$.nosuch.list', 'UPSERT', '1').error().contains('does not exist')
+    r.expect('JSON.ARRAPPEND', 'doc', '$.c', 'TYPED', 'UPSERT', '1', '"x"').error().contains('type mismatch')
+    r.expect('JSON.ARRAPPEND', 'doc', '$.c', '1').error().contains('does not exist')
+    r.assertIsNone(r.execute_command('JSON.TYPE', 'doc', '.c'))
+
+def testArrWhere(env):
+    """Test JSON.ARRAPPEND and JSON.ARRINSERT with a WHERE filter"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'cart', '.', '{"open":true,"items":[1,2]}'))
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'cart', '.items', 'WHERE', '.open', 3), 3)
+    r.assertEqual(r.execute_command('JSON.ARRINSERT', 'cart', '.items', 0, 'where', '$.items[?(@ > 2)]', 0), 4)
+    r.assertEqual(r.execute_command('JSON.GET', 'cart', '.items'), '[0,1,2,3]')
+
+    # The filter blocks the write
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'cart', '.items', 'WHERE', '$.items[?(@ > 10)]', 4), 4)
+    r.assertEqual(r.execute_command('JSON.ARRINSERT', 'cart', '.items', 0, 'WHERE', '$.closed', -1), 4)
+    r.assertEqual(r.execute_command('JSON.GET', 'cart', '.items'), '[0,1,2,3]')
+    r.expect('JSON.ARRAPPEND', 'cart', '.items', 'WHERE').raiseError()
+
+
+    """Test that bulk JSON.ARRAPPEND and JSON.ARRINSERT report the invalid item"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"arr":[]}'))
+    r.expect('JSON.ARRAPPEND', 'test', '.arr', 1, 2, '{bad', 4).error().contains('invalid JSON at argument 2')
+    r.expect('JSON.ARRAPPEND', 'test', '.arr', 'TYPED', 1, 'nope').error().contains('invalid JSON at argument 1')
+    r.expect('JSON.ARRINSERT', 'test', '.arr', 0, '[', 2).error().contains('invalid JSON at argument 0')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.arr'), '[]')
+
+def testArrWithValue(env):
+    """Test JSON.ARRAPPEND, JSON.ARRINSERT and JSON.ARRTRIM with WITHVALUE"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"a":[1],"b":{"a":["x"]}}'))
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '.a', 'WITHVALUE', 2, '{"c":3}'), '[1,2,{"c":3}]')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.a'), '[1,2,{"c":3}]')
+    r.assertEqual(r.execute_command('JSON.ARRINSERT', 'test', '.a', 0, 'WITHVALUE', 0), '[0,1,2,{"c":3}]')
+    r.assertEqual(r.execute_command('JSON.ARRTRIM', 'test', '.a', 1, 2, 'withvalue'), '[1,2]')
+
+    # An array per matched array
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '$..a', 'WITHVALUE', '"y"'), ['[1,2,"y"]', '["x","y"]'])
+    r.assertEqual(r.execute_command('JSON.ARRTRIM', 'test', '$..a', 'KEEP', '@ != "y"', 'WITHVALUE'), ['[1,2]', '["x"]'])
+
+    # The array as it was when the WHERE filter blocks the write, and as created by UPSERT
+    r.assertEqual(r.execute_command('JSON.ARRINSERT', 'test', '.a', 0, 'WHERE', '$.nothing', 'WITHVALUE', 0), '[1,2]')
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '.c', 'UPSERT', 'WITHVALUE', 1, 2), '[1,2]')
+
+    # The default reply is the length
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '.a', 3), 3)
+    r.expect('JSON.ARRAPPEND', 'test', '.a', 'IDEMPOTENT', 'tok', 'WITHVALUE', 4).raiseError()
+    r.expect('JSON.ARRTRIM', 'test', '.a', 0, 1, 'NOPE').raiseError()
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.a'), '[1,2,3]')
+
+def testArrPopCount(env):
+    """Test JSON.ARRPOP with a count"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '[1,2,3,4,5,6]'))
+    # Pop from the middle
+    r.assertEqual(r.execute_command('JSON.ARRPOP', 'test', '.', 2, 2), ['3', '4'])
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.'), '[1,2,5,6]')
+    # Negative start index
+    r.assertEqual(r.execute_command('JSON.ARRPOP', 'test', '.', -3, 1), ['2'])
+    # Popping more than available clamps to the end of the array
+    r.assertEqual(r.execute_command('JSON.ARRPOP', 'test', '.', 1, 10), ['5', '6'])
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.'), '[1]')
+    r.assertEqual(r.execute_command('JSON.ARRPOP', 'test', '.', 0, 0), [])
+    r.assertEqual(r.execute_command('JSON.ARRPOP', 'test', '.', 0, 5), ['1'])
+    r.assertEqual(r.execute_command('JSON.ARRPOP', 'test', '.', 0, 5), [])
+    r.expect('JSON.ARRPOP', 'test', '.', 0, -1).error().contains('count')
+
+    # The document changes when any of the arrays does, even if the last one is empty
+    r.assertOk(r.execute_command('JSON.SET', 'mix', '.', '{"a":[1,2],"b":[]}'))
+    r.assertEqual(r.execute_command('JSON.ARRPOP', 'mix', '$[*]', 0, 1), [])
+    r.expect('JSON.GET', 'mix', 'WITHVERSION', 'POINTER', '').equal([2, '{"a":[2],"b":[]}'])
+    r.assertIsNone(r.execute_command('JSON.ARRPOP', 'mix', '$[*]'))
+    r.expect('JSON.GET', 'mix', 'WITHVERSION', 'POINTER', '').equal([3, '{"a":[],"b":[]}'])
+
 def testArrPopErrors(env):
     r = env
 
@@ -683,6 +1861,76 @@ def testTypeCommand(env):
         reply = r.execute_command('JSON.TYPE', 'test', '.')
         r.assertEqual(reply, k)
 
+def testTypeMultiplePaths(env):
+    """Test JSON.TYPE with several paths"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"s":"a","n":[1,2.5],"o":{}}'))
+    r.assertEqual(r.execute_command('JSON.TYPE', 'test', '.s', '$.n[*]', '.o'),
+                  ['string', ['integer', 'number'], 'object'])
+    # Each path is replied to on its own
+    r.assertEqual(r.execute_command('JSON.TYPE', 'test', '.missing', '$.missing', '$.s'),
+                  [None, [], ['string']])
+    r.assertIsNone(r.execute_command('JSON.TYPE', 'nokey', '.s', '.o'))
+
+def testTypeSimpleTypes(env):
+    """Test JSON.TYPE with SIMPLETYPES"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"i":1,"f":2.5,"s":"a"}'))
+    r.expect('JSON.TYPE', 'test', '.i').equal('integer')
+    r.expect('JSON.TYPE', 'test', '.f').equal('number')
+    r.expect('JSON.TYPE', 'test', '$.i').equal('integer')
+    r.expect('JSON.TYPE', 'test', '$.i', 'SIMPLETYPES').equal('number')
+    r.expect('JSON.TYPE', 'test', 'simpletypes', '$.f').equal('number')
+    r.expect('JSON.TYPE', 'test', '$.*', '$.s', 'SIMPLETYPES').equal([['number', 'number', 'string'], ['string']])
+    r.expect('JSON.TYPE', 'test', '.i', '$.f', 'SIMPLETYPES').equal(['number', ['number']])
+    # Without a JSONPath it is a legacy path, as it always was
+    r.expect('JSON.TYPE', 'test', 'SIMPLETYPES').equal(None)
+    r.expect('JSON.TYPE', 'test', '.i', 'SIMPLETYPES').equal(['integer', None])
+
+def testExistsCommand(env):
+    """Test JSON.EXISTS command"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"a":{"b":null},"arr":[1,{"c":2}]}'))
+    r.assertEqual(r.execute_command('JSON.EXISTS', 'test', '$'), 1)
+    r.assertEqual(r.execute_command('JSON.EXISTS', 'test', '.a.b'), 1)
+    r.assertEqual(r.execute_command('JSON.EXISTS', 'test', '$.arr[1].c'), 1)
+    r.assertEqual(r.execute_command('JSON.EXISTS', 'test', '$..c'), 1)
+    r.assertEqual(r.execute_command('JSON.EXISTS', 'test', '$.arr[?(@ > 0)]'), 1)
+    r.assertEqual(r.execute_command('JSON.EXISTS', 'test', '.a.x'), 0)
+    r.assertEqual(r.execute_command('JSON.EXISTS', 'test', '$.arr[5]'), 0)
+    r.assertEqual(r.execute_command('JSON.EXISTS', 'test', '$..d'), 0)
+    r.assertEqual(r.execute_command('JSON.EXISTS', 'missing', '$'), 0)
+    r.expect('JSON.EXISTS', 'test').raiseError()
+    r.expect('JSON.EXISTS', 'test', '$', '$').raiseError()
+
+def testCountCommand(env):
+    """Test JSON.COUNT command"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"x":1,"a":{"x":2,"b":{"x":3}},"arr":[{"x":4},{"y":5}]}'))
+    r.assertEqual(r.execute_command('JSON.COUNT', 'test', '$..x'), 4)
+    r.assertEqual(r.execute_command('JSON.COUNT', 'test', '$.a..x'), 2)
+    r.assertEqual(r.execute_command('JSON.COUNT', 'test', '$.arr[*]'), 2)
+    r.assertEqual(r.execute_command('JSON.COUNT', 'test', '$.arr[?(@.x)]'), 1)
+    r.assertEqual(r.execute_command('JSON.COUNT', 'test', '$'), 1)
+    r.assertEqual(r.execute_command('JSON.COUNT', 'test', '.a.b'), 1)
+    r.assertEqual(r.execute_command('JSON.COUNT', 'test', '$..z'), 0)
+    r.assertEqual(r.execute_command('JSON.COUNT', 'missing', '$..x'), 0)
+    r.expect('JSON.COUNT', 'test').raiseError()
+
+def testWrongRedisType(env):
+    """Test that keys of other Redis types fail with WRONGTYPE"""
+    r = env
+
+    r.assertOk(r.execute_command('SET', 'plain', 'str'))
+    r.expect('JSON.GET', 'plain').error().contains('WRONGTYPE Operation against a key holding the wrong kind of value')
+    r.expect('JSON.TYPE', 'plain', '.').error().contains('WRONGTYPE')
+    r.expect('JSON.SET', 'plain', '.', '1').error().contains('WRONGTYPE')
+    r.assertEqual(r.execute_command('GET', 'plain'), 'str')
+
 def testLenCommands(env):
     """Test the JSON.ARRLEN, JSON.OBJLEN and JSON.STRLEN commands"""
     r = env
@@ -711,6 +1959,19 @@ def testLenCommands(env):
     # test an infinite index
     r.expect('JSON.LEN', 'test', '.arr[-inf]').raiseError()
 
+def testObjLenDeep(env):
+    """Test JSON.OBJLEN DEEP"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"a":1,"b":{"c":2,"d":{"e":3}},"f":[{"g":4},5],"h":{}}'))
+    r.assertEqual(r.execute_command('JSON.OBJLEN', 'test', '.'), 4)
+    # a, b, c, d, e, f, g and h
+    r.assertEqual(r.execute_command('JSON.OBJLEN', 'test', '.', 'DEEP'), 8)
+    r.assertEqual(r.execute_command('JSON.OBJLEN', 'test', '.b', 'deep'), 3)
+    r.assertEqual(r.execute_command('JSON.OBJLEN', 'test', '.h', 'DEEP'), 0)
+    r.expect('JSON.OBJLEN', 'test', '.f', 'DEEP').error().contains('expected object')
+    r.expect('JSON.OBJLEN', 'test', '.', 'SHALLOW').raiseError()
+
 def testObjKeysCommand(env):
     """Test JSON.OBJKEYS command"""
     r = env
@@ -724,6 +1985,197 @@ def testObjKeysCommand(env):
     # test a wrong type
     r.expect('JSON.OBJKEYS', 'test', '.null').raiseError()
 
+def testWrongTypeErrors(env):
+    """Test that wrong type errors name the actual type"""
+    r = env
+
+    r.expect('JSON.SET', 'test', '.', '{"s":"str","a":[1],"o":{},"n":null,"i":1,"f":1.5}').ok()
+    r.expect('JSON.STRLEN', 'test', '.a').error().contains('path value is array, expected string')
+    r.expect('JSON.ARRLEN', 'test', '.o').error().contains('path value is object, expected array')
+    r.expect('JSON.ARRLEN', 'test', '.f').error().contains('path value is number, expected array')
+    r.expect('JSON.OBJLEN', 'test', '.s').error().contains('path value is string, expected object')
+    r.expect('JSON.OBJLEN', 'test', '.i').error().contains('path value is integer, expected object')
+    r.expect('JSON.OBJKEYS', 'test', '.n').error().contains('path value is null, expected object')
+
+def testObjRenameCommand(env):
+    """Test JSON.OBJRENAME command"""
+    r = env
+
+    r.expect('JSON.SET', 'test', '.', '{"a":1,"b":{"c":[2]},"d":"e"}').ok()
+    r.expect('JSON.OBJRENAME', 'test', '.', 'b', 'x').ok()
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test')), {'a': 1, 'x': {'c': [2]}, 'd': 'e'})
+    # The renamed member keeps its position
+    r.assertEqual(r.execute_command('JSON.GET', 'test'), '{"a":1,"x":{"c":[2]},"d":"e"}')
+
+    # Missing source key
+    r.expect('JSON.OBJRENAME', 'test', '.', 'b', 'y').error().contains("does not exist")
+
+    # Conflicting destination key
+    r.expect('JSON.OBJRENAME', 'test', '.', 'a', 'd').error().contains("already exists")
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test')), {'a': 1, 'x': {'c': [2]}, 'd': 'e'})
+    r.expect('JSON.OBJRENAME', 'test', '.', 'a', 'd', 'OVERWRITE').ok()
+    r.assertEqual(r.execute_command('JSON.GET', 'test'), '{"d":1,"x":{"c":[2]}}')
+
+    # Non-object path
+    r.expect('JSON.OBJRENAME', 'test', '.d', 'a', 'b').raiseError()
+    r.expect('JSON.OBJRENAME', 'test', '.', 'd', 'a', 'FOO').raiseError()
+
+def testNumIncrGetOld(env):
+    """Test JSON.NUMINCRBY GETOLD"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"id":1,"a":{"id":2.5},"s":"x"}'))
+    r.assertEqual(r.execute_command('JSON.NUMINCRBY', 'test', '.id', 1, 'GETOLD'), '1')
+    r.assertEqual(r.execute_command('JSON.NUMINCRBY', 'test', '.id', 1, 'getold'), '2')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.id'), '3')
+    # Every matched number is reported
+    r.assertEqual(r.execute_command('JSON.NUMINCRBY', 'test', '$..id', 10, 'GETOLD'), ['3', '2.5'])
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '$..id'), '[13,12.5]')
+    r.expect('JSON.NUMINCRBY', 'test', '.id', 1, 'GETNEW').raiseError()
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.id'), '13')
+
+def testNumOpDecimal(env):
+    """Test JSON.NUMINCRBY and JSON.NUMMULTBY DECIMAL"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"exact":0,"float":0,"price":19.99}'))
+    for _ in range(10):
+        r.execute_command('JSON.NUMINCRBY', 'test', '.exact', '0.1', 'DECIMAL')
+        r.execute_command('JSON.NUMINCRBY', 'test', '.float', '0.1')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.exact'), '1.0')
+    r.assertNotEqual(r.execute_command('JSON.GET', 'test', '.float'), '1.0')
+    r.assertEqual(r.execute_command('JSON.NUMMULTBY', 'test', '.price', 3, 'decimal'), '59.97')
+    r.assertEqual(r.execute_command('JSON.NUMINCRBY', 'test', '.exact', '-0.25', 'DECIMAL', 'GETOLD'), '1.0')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.exact'), '0.75')
+    r.expect('JSON.NUMINCRBY', 'test', '.exact', 'abc', 'DECIMAL').raiseError()
+    r.expect('JSON.NUMPOWBY', 'test', '.exact', 2, 'DECIMAL').raiseError()
+
+    # Results a double can't hold keep all their digits, also across a reload
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.big', '12345678901234567'))
+    r.assertEqual(r.execute_command('JSON.NUMINCRBY', 'test', '.big', '0.89', 'DECIMAL'), '12345678901234567.89')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.big'), '12345678901234567.89')
+    r.assertEqual(r.execute_command('JSON.NUMINCRBY', 'test', '.big', '0.01', 'DECIMAL'), '12345678901234567.90')
+    r.assertEqual(r.execute_command('JSON.NUMMULTBY', 'test', '.big', '10000', 'DECIMAL'), '123456789012345679000.00')
+    r.assertOk(r.execute_command('DEBUG', 'RELOAD'))
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.big'), '123456789012345679000.00')
+    r.assertEqual(r.execute_command('JSON.TYPE', 'test', '.big'), 'number')
+
+def testSetKeepHistory(env):
+    """Test JSON.SET KEEPHISTORY with JSON.HISTORY and JSON.UNDO"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"v":1}'))
+    r.assertEqual(r.execute_command('JSON.HISTORY', 'test'), [])
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"v":2}', 'KEEPHISTORY', 2))
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.v', '3', 'keephistory', 2))
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.v', '4', 'KEEPHISTORY', 2))
+    # Only the 2 most recent versions are kept
+    r.assertEqual(r.execute_command('JSON.HISTORY', 'test'), ['{"v":3}', '{"v":2}'])
+    r.assertGreater(r.execute_command('JSON.DEBUG', 'MEMORY', 'test'), len('{"v":3}{"v":2}'))
+
+    r.assertOk(r.execute_command('JSON.UNDO', 'test'))
+    r.assertEqual(r.execute_command('JSON.GET', 'test'), '{"v":3}')
+    r.assertEqual(r.execute_command('JSON.HISTORY', 'test'), ['{"v":2}'])
+    # The history survives a reload
+    r.assertOk(r.execute_command('DEBUG', 'RELOAD'))
+    r.assertOk(r.execute_command('JSON.UNDO', 'test'))
+    r.assertEqual(r.execute_command('JSON.GET', 'test'), '{"v":2}')
+    r.assertIsNone(r.execute_command('JSON.UNDO', 'test'))
+    r.assertEqual(r.execute_command('JSON.GET', 'test'), '{"v":2}')
+
+    # Writes without KEEPHISTORY don't touch the history
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.v', '5'))
+    r.assertEqual(r.execute_command('JSON.HISTORY', 'test'), [])
+    r.expect('JSON.SET', 'test', '.v', '6', 'KEEPHISTORY', 0).raiseError()
+    # Writes that fail or change nothing don't keep a version
+    r.assertIsNone(r.execute_command('JSON.SET', 'test', '.missing', '1', 'XX', 'KEEPHISTORY', 2))
+    r.assertIsNone(r.execute_command('JSON.SET', 'test', '.v', '1', 'NX', 'KEEPHISTORY', 2))
+    r.expect('JSON.SET', 'test', '.v', '[1]', 'MERGEARRAY', 'KEEPHISTORY', 2).raiseError()
+    r.assertEqual(r.execute_command('JSON.HISTORY', 'test'), [])
+    # MEMORY USAGE counts the history of the key
+    big = json.dumps({'v': 'x' * 1000})
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', big))
+    before = r.execute_command('MEMORY', 'USAGE', 'test')
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', big, 'KEEPHISTORY', 2))
+    r.assertGreaterEqual(r.execute_command('MEMORY', 'USAGE', 'test') - before, 1000)
+    r.expect('JSON.UNDO', 'missing').raiseError()
+    r.assertIsNone(r.execute_command('JSON.HISTORY', 'missing'))
+
+def testSetAppendOnly(env):
+    """Test JSON.SET APPENDONLY rejecting writes that remove or replace array elements"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"events":[1,2],"n":1}', 'APPENDONLY'))
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '.events', 3), 3)
+    r.assertEqual(r.execute_command('JSON.ARRINSERT', 'test', '.events', 3, 4), 4)
+    r.expect('JSON.ARRPOP', 'test', '.events').error().contains('append-only array')
+    r.expect('JSON.ARRTRIM', 'test', '.events', 0, 1).error().contains('append-only array')
+    r.expect('JSON.ARRINSERT', 'test', '.events', 0, 0).error().contains('append-only array')
+    r.expect('JSON.SET', 'test', '$.events[0]', 0).error().contains('append-only array')
+    r.expect('JSON.SET', 'test', '.events', '[]').error().contains('append-only array')
+    r.expect('JSON.DEL', 'test', '.events').error().contains('append-only array')
+    r.expect('JSON.SET', 'test', '.', '{}').error().contains('append-only array')
+    r.expect('JSON.ARRAPPEND', 'test', '.events', 'MAXLEN', 4, 5).error().contains('append-only array')
+    r.expect('JSON.CLEAR', 'test', '.events').error().contains('append-only array')
+    r.expect('JSON.CLEAR', 'test').error().contains('append-only array')
+    r.expect('JSON.NUMINCRBY', 'test', '.events[0]', 1).error().contains('append-only array')
+    r.expect('JSON.OBJRENAME', 'test', '.', 'events', 'log').error().contains('append-only array')
+    r.expect('JSON.PATCH', 'test', '[{"op": "remove", "path": "/events/0"}]').error().contains('append-only array')
+    r.expect('JSON.PATCH', 'test', '[{"op": "replace", "path": "/n", "value": 2}]').error().contains('append-only array')
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.events'), '[1,2,3,4]')
+
+    # Values outside of arrays can still change
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.n', 2))
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.tag', '"a"'))
+    r.assertEqual(r.execute_command('JSON.GET', 'test'), '{"events":[1,2,3,4],"n":2,"tag":"a"}')
+
+    # The mode survives a reload
+    r.assertOk(r.execute_command('DEBUG', 'RELOAD'))
+    r.expect('JSON.ARRPOP', 'test', '.events').error().contains('append-only array')
+
+    # Only the key's deletion clears it
+    r.assertEqual(r.execute_command('JSON.DEL', 'test'), 1)
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '[1,2]'))
+    r.assertEqual(r.execute_command('JSON.ARRPOP', 'test'), '2')
+
+    r.expect('JSON.SET', 'test', '.a', 1, 'APPENDONLY').error().contains('root')
+
+    # Nothing is changed when any of the matches is in an array
+    r.assertOk(r.execute_command('JSON.SET', 'nums', '.', '{"a":1,"b":[2]}', 'APPENDONLY'))
+    r.expect('JSON.NUMINCRBY', 'nums', '$..*', 1).error().contains('append-only array')
+    r.expect('JSON.CLEAR', 'nums', '$.*').error().contains('append-only array')
+    r.assertEqual(r.execute_command('JSON.GET', 'nums'), '{"a":1,"b":[2]}')
+
+    # An earlier version can't be restored over the arrays
+    r.assertOk(r.execute_command('JSON.SET', 'log', '.', '{"events":[1],"n":1}', 'APPENDONLY'))
+    r.assertIsNone(r.execute_command('JSON.UNDO', 'log'))
+    r.assertOk(r.execute_command('JSON.SET', 'log', '.n', 2, 'KEEPHISTORY', 5))
+    r.expect('JSON.UNDO', 'log').error().contains('append-only array')
+    r.assertEqual(r.execute_command('JSON.GET', 'log'), '{"events":[1],"n":2}')
+
+def testFreezeCommand(env):
+    """Test JSON.FREEZE and JSON.UNFREEZE commands"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"arr":[1],"n":1}'))
+    r.assertOk(r.execute_command('JSON.FREEZE', 'test'))
+    r.expect('JSON.ARRAPPEND', 'test', '.arr', 2).error().contains('immutable')
+    r.expect('JSON.SET', 'test', '.n', 2).error().contains('immutable')
+    r.expect('JSON.NUMINCRBY', 'test', '.n', 1).error().contains('immutable')
+    r.expect('JSON.DEL', 'test', '.arr').error().contains('immutable')
+    # Reads are still served
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.arr'), '[1]')
+    r.assertOk(r.execute_command('JSON.UNFREEZE', 'test'))
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'test', '.arr', 2), 2)
+    r.assertEqual(r.execute_command('JSON.GET', 'test', '.'), '{"arr":[1,2],"n":1}')
+
+    # The flag survives a reload
+    r.assertOk(r.execute_command('JSON.FREEZE', 'test'))
+    r.expect('JSON.FREEZE', 'missing').raiseError()
+    r.expect('JSON.FREEZE', 'test', 'extra').raiseError()
+    r.assertOk(r.execute_command('DEBUG', 'RELOAD'))
+    r.expect('JSON.ARRAPPEND', 'test', '.arr', 3).error().contains('immutable')
+
 def testNumIncrCommand(env):
     """Test JSON.NUMINCRBY command"""
     r = env
@@ -756,6 +2208,89 @@ def testNumIncrCommand(env):
     r.assertEqual(84, res['bar'])
 
 
+def testNumIncrByMulti(env):
+    """Test JSON.NUMINCRBY with several path and number pairs"""
+    r = env
+
+    r.expect('JSON.SET', 'test', '$', '{"hits":1,"misses":10,"name":"foo","arr":[1,2]}').ok()
+    r.expect('JSON.NUMINCRBY', 'test', '$.hits', 1, '$.misses', 5).equal(['2', '15'])
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test', '$.hits', '$.misses')), {'$.hits': [2], '$.misses': [15]})
+
+    # Pairs are applied in order, also to the same path
+    r.expect('JSON.NUMINCRBY', 'test', '.hits', 1, '.hits', 2).equal(['3', '5'])
+    r.expect('JSON.NUMINCRBY', 'test', '$.arr[*]', 1, '.misses', 1, 'GETOLD').equal([['1', '2'], '15'])
+    r.expect('JSON.NUMINCRBY', 'test', '$.hits', '0.1', '$.misses', '0.2', 'DECIMAL').equal(['5.1', '16.2'])
+
+    # A missing or non-numeric path changes nothing
+    r.expect('JSON.NUMINCRBY', 'test', '$.hits', 1, '$.nope', 1).raiseError()
+    r.expect('JSON.NUMINCRBY', 'test', '$.hits', 1, '$.name', 1).raiseError()
+    r.expect('JSON.NUMINCRBY', 'test', '$.hits', 1, '$.misses').raiseError()
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test', '$.hits', '$.misses')), {'$.hits': [5.1], '$.misses': [16.2]})
+
+    # A failing increment keeps the pairs before it
+    r.expect('JSON.NUMINCRBY', 'test', '$.hits', 1, '$.misses', 'abc').raiseError()
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test', '$.hits', '$.misses')), {'$.hits': [6.1], '$.misses': [16.2]})
+
+    # Only JSON.NUMINCRBY takes more pairs
+    r.expect('JSON.NUMMULTBY', 'test', '$.hits', 2, '$.misses', 2).raiseError()
+    # but GETOLD is taken by JSON.NUMMULTBY too
+    r.expect('JSON.NUMMULTBY', 'test', '$.misses', 2, 'GETOLD').equal('16.2')
+    r.expect('JSON.GET', 'test', '$.misses').equal('[32.4]')
+
+def testCounterCommand(env):
+    """Test JSON.COUNTER"""
+    r = env
+
+    r.expect('JSON.SET', 'metrics', '$', '{"hits":{}}').ok()
+    # The first call creates the counter, the next ones increment it
+    r.expect('JSON.COUNTER', 'metrics', '$.views').equal('0')
+    r.expect('JSON.COUNTER', 'metrics', '$.views').equal('1')
+    r.expect('JSON.COUNTER', 'metrics', '.views').equal('2')
+    r.expect('JSON.COUNTER', 'metrics', '$.hits.home', 'BY', 5, 'DEFAULT', 10).equal('10')
+    r.expect('JSON.COUNTER', 'metrics', '$.hits.home', 'default', 10, 'by', 5).equal('15')
+    r.expect('JSON.COUNTER', 'metrics', '$.load', 'BY', '0.5').equal('0')
+    r.expect('JSON.COUNTER', 'metrics', '$.load', 'BY', '0.5').equal('0.5')
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'metrics')), {'hits': {'home': 15}, 'views': 2, 'load': 0.5})
+
+    # A missing key is created at the root only
+    r.expect('JSON.COUNTER', 'count', '$', 'DEFAULT', 7).equal('7')
+    r.expect('JSON.COUNTER', 'count', '.').equal('8')
+    r.expect('JSON.GET', 'count').equal('8')
+    r.expect('JSON.COUNTER', 'missing', '$.views').raiseError()
+    r.assertEqual(r.execute_command('EXISTS', 'missing'), 0)
+
+    r.expect('JSON.COUNTER', 'metrics', '$.hits').raiseError()
+    r.expect('JSON.COUNTER', 'metrics', '$.nothing.views').raiseError()
+    r.expect('JSON.COUNTER', 'metrics', '$.views', 'BY', 'one').raiseError()
+    r.expect('JSON.COUNTER', 'metrics', '$.views', 'DEFAULT', '"0"').raiseError()
+    r.expect('JSON.COUNTER', 'metrics', '$.views', 'NX').raiseError()
+    r.expect('JSON.GET', 'metrics', '$.views').equal('[2]')
+
+
+def testLargeIntegerPrecision(env):
+    """Test that integers beyond 2^53 keep their full precision"""
+    r = env
+
+    big = 9007199254740993  # 2^53 + 1, not representable as a double
+    r.expect('JSON.SET', 'test', '.', json.dumps({'n': big, 'arr': [big - 1, big]})).ok()
+    r.expect('JSON.GET', 'test', '.n').equal(str(big))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test')), {'n': big, 'arr': [big - 1, big]})
+    r.expect('JSON.TYPE', 'test', '.n').equal('integer')
+
+    # Equality checks must not go through doubles
+    r.expect('JSON.ARRINDEX', 'test', '.arr', str(big)).equal(1)
+    r.expect('JSON.ARRINDEX', 'test', '.arr', str(big - 1)).equal(0)
+
+    # Integer arithmetic stays exact
+    r.expect('JSON.NUMINCRBY', 'test', '.n', 1).equal(str(big + 1))
+    r.expect('JSON.NUMMULTBY', 'test', '.n', 1).equal(str(big + 1))
+    r.expect('JSON.GET', 'test', '.n').equal(str(big + 1))
+    r.expect('JSON.TYPE', 'test', '.n').equal('integer')
+
+    # Negative exponents are not integer operations
+    r.expect('JSON.SET', 'test', '.n', '2').ok()
+    r.expect('JSON.NUMPOWBY', 'test', '.n', -1).equal('0.5')
+
 def testStrCommands(env):
     """Test JSON.STRAPPEND and JSON.STRLEN commands"""
     r = env
@@ -766,6 +2301,366 @@ def testStrCommands(env):
     r.assertEqual(6, r.execute_command('JSON.STRAPPEND', 'test', '.', '"bar"'))
     r.assertEqual('"foobar"', r.execute_command('JSON.GET', 'test', '.'))
 
+def testItemsArity(env):
+    """Test the commands adding items or strings reject a missing or extra item"""
+    r = env
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"arr":[1],"s":"a"}'))
+    for cmd in [['JSON.ARRAPPEND', 'doc', '.arr'],
+                ['JSON.ARRAPPEND', 'doc', '.arr', 'TYPED'],
+                ['JSON.ARRAPPEND', 'doc', '.arr', 'WHERE', '$.s'],
+                ['JSON.ARRINSERT', 'doc', '.arr', '0'],
+                ['JSON.ARRINSERT', 'doc', '.arr', '0', 'WHERE', '$.s'],
+                ['JSON.STRAPPEND', 'doc'],
+                ['JSON.STRPREPEND', 'doc'],
+                ['JSON.STRAPPEND', 'doc', '.s', '"b"', '"c"'],
+                ['JSON.STRPREPEND', 'doc', '.s', '"b"', '"c"']]:
+        r.expect(*cmd).error().contains('wrong number of arguments')
+    # Nothing changed
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$'), '[{"arr":[1],"s":"a"}]')
+
+def testStrPrependCommand(env):
+    """Test JSON.STRPREPEND command"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '"bar"'))
+    r.assertEqual(6, r.execute_command('JSON.STRPREPEND', 'test', '"foo"'))
+    r.assertEqual('"foobar"', r.execute_command('JSON.GET', 'test', '.'))
+
+    r.assertOk(r.execute_command('JSON.SET', 'test', '.', '{"a":{"id":"1"},"b":{"id":"22"},"c":{"id":3}}'))
+    r.assertEqual(4, r.execute_command('JSON.STRPREPEND', 'test', '.a.id', '"id:"'))
+    # Every matched string is prepended to, non strings are skipped
+    r.assertEqual(6, r.execute_command('JSON.STRPREPEND', 'test', '$..id', '"#"'))
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'test', '$..id')), ['#id:1', '#22', 3])
+    r.expect('JSON.STRPREPEND', 'test', '.c.id', '"x"').raiseError()
+    r.expect('JSON.STRPREPEND', 'test', '.a.id', '1').raiseError()
+
+def testDebugParseCommand(env):
+    """Test JSON.DEBUG PARSE subcommand"""
+    r = env
+
+    r.expect('JSON.DEBUG', 'PARSE', '{"foo":[1,2,{"bar":null}]}').ok()
+    r.expect('JSON.DEBUG', 'PARSE', '{"foo":[1,2,}').error().contains('line 1 column')
+    r.expect('JSON.DEBUG', 'PARSE').raiseError()
+    # Nothing is stored
+    r.assertEqual(r.execute_command('DBSIZE'), 0)
+
+def testDebugValidateCommand(env):
+    """Test JSON.DEBUG VALIDATE"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.', json.dumps(docs['basic'])))
+    r.assertOk(r.execute_command('JSON.DEBUG', 'VALIDATE', 'doc'))
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.', '{"a":[1,2.5,"x",{"b":null}],"c\'d":{}}'))
+    r.assertOk(r.execute_command('JSON.DEBUG', 'validate', 'doc'))
+    r.assertOk(r.execute_command('DEBUG', 'RELOAD'))
+    r.assertOk(r.execute_command('JSON.DEBUG', 'VALIDATE', 'doc'))
+    r.assertIsNone(r.execute_command('JSON.DEBUG', 'VALIDATE', 'missing'))
+    r.expect('JSON.DEBUG', 'VALIDATE').raiseError()
+    r.assertTrue('VALIDATE' in ' '.join(r.execute_command('JSON.DEBUG', 'HELP')))
+
+def testDebugJsonPathCommand(env):
+    """Test JSON.DEBUG JSONPATH"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.', '{"a":[{"x":1},{"x":2}],"b":{"x":3}}'))
+    r.assertEqual(r.execute_command('JSON.DEBUG', 'JSONPATH', 'doc', '$.a[*].x'),
+                  ["$['a'][0]['x']", "$['a'][1]['x']"])
+    r.assertEqual(sorted(r.execute_command('JSON.DEBUG', 'jsonpath', 'doc', '$..x')),
+                  ["$['a'][0]['x']", "$['a'][1]['x']", "$['b']['x']"])
+    r.assertEqual(r.execute_command('JSON.DEBUG', 'JSONPATH', 'doc', '.b'), ["$['b']"])
+    r.assertEqual(r.execute_command('JSON.DEBUG', 'JSONPATH', 'doc', '$.nothing'), [])
+    r.assertIsNone(r.execute_command('JSON.DEBUG', 'JSONPATH', 'missing', '$'))
+    r.expect('JSON.DEBUG', 'JSONPATH', 'doc').raiseError()
+    r.assertTrue('JSONPATH' in ' '.join(r.execute_command('JSON.DEBUG', 'HELP')))
+
+def testDebugReprCommand(env):
+    """Test JSON.DEBUG REPR"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.', '{"i":1,"f":1.0,"s":"1","n":null,"b":true,"a":[2,2.5,{}]}'))
+    res = json.loads(r.execute_command('JSON.DEBUG', 'REPR', 'doc'))
+    r.assertEqual(res, {'i': 'long', 'f': 'double', 's': 'string', 'n': 'null', 'b': 'bool', 'a': ['long', 'double', {}]})
+    r.assertNotEqual(res['i'], res['f'])
+    r.assertEqual(r.execute_command('JSON.DEBUG', 'repr', 'doc', '$.a'), '["long","double",{}]')
+    r.assertEqual(r.execute_command('JSON.DEBUG', 'REPR', 'doc', '.f'), '"double"')
+    r.assertIsNone(r.execute_command('JSON.DEBUG', 'REPR', 'missing'))
+    r.expect('JSON.DEBUG', 'REPR', 'doc', '$.nothing').raiseError()
+    r.assertTrue('REPR' in ' '.join(r.execute_command('JSON.DEBUG', 'HELP')))
+
+def testDebugSizeSummaryCommand(env):
+    """Test JSON.DEBUG SIZESUMMARY"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.', json.dumps({'small': 1, 'big': ['x' * 100] * 10})))
+    res = r.execute_command('JSON.DEBUG', 'SIZESUMMARY', 'doc')
+    r.assertEqual(len(res), 4)
+    sizes = dict(zip(res[::2], res[1::2]))
+    r.assertEqual(sorted(sizes.keys()), ['big', 'small'])
+    r.assertEqual(sizes['small'], r.execute_command('JSON.DEBUG', 'MEMORY', 'doc', '.small'))
+    r.assertEqual(sizes['big'], r.execute_command('JSON.DEBUG', 'MEMORY', 'doc', '.big'))
+    r.assertGreater(sizes['big'], sizes['small'])
+
+    r.assertOk(r.execute_command('JSON.SET', 'arr', '.', '["a",{"b":[1,2,3]}]'))
+    res = r.execute_command('JSON.DEBUG', 'sizesummary', 'arr')
+    r.assertEqual(res[::2], [0, 1])
+    r.assertEqual(res[3], r.execute_command('JSON.DEBUG', 'MEMORY', 'arr', '$[1]'))
+    r.assertIsNone(r.execute_command('JSON.DEBUG', 'SIZESUMMARY', 'missing'))
+    r.assertOk(r.execute_command('JSON.SET', 'num', '.', '1'))
+    r.expect('JSON.DEBUG', 'SIZESUMMARY', 'num').raiseError()
+    r.assertTrue('SIZESUMMARY' in ' '.join(r.execute_command('JSON.DEBUG', 'HELP')))
+
+def testDebugKeyspaceCommand(env):
+    """Test JSON.DEBUG KEYSPACE subcommand"""
+    r = env
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc:1', '.', '{"a":1}'))
+    r.assertOk(r.execute_command('JSON.SET', 'doc:2', '.', '[1,2,3]'))
+    r.assertOk(r.execute_command('JSON.SET', 'other', '.', 'true'))
+    r.assertTrue(r.execute_command('SET', 'doc:3', 'not json'))
+
+    found = {}
+    cursor = '0'
+    while True:
+        cursor, res = r.execute_command('JSON.DEBUG', 'KEYSPACE', cursor, 'MATCH', 'doc:*', 'COUNT', 1)
+        found.update(zip(res[::2], res[1::2]))
+        if cursor == '0':
+            break
+    r.assertEqual(sorted(found.keys()), ['doc:1', 'doc:2'])
+    r.assertEqual(found['doc:1'], r.execute_command('JSON.DEBUG', 'MEMORY', 'doc:1', '.'))
+    r.assertEqual(found['doc:2'], r.execute_command('JSON.DEBUG', 'MEMORY', 'doc:2', '.'))
+
+    r.expect('JSON.DEBUG', 'KEYSPACE', 'COUNT', 0).raiseError()
+    r.expect('JSON.DEBUG', 'KEYSPACE', 'FOO').raiseError()
+
+    # Key names that aren't UTF-8 are listed as they are
+    kwargs = dict(r.getConnection().connection_pool.connection_kwargs, decode_responses=False)
+    raw = redis.Redis(connection_pool=redis.ConnectionPool(**kwargs))
+    r.assertTrue(raw.execute_command('JSON.SET', b'doc:\xff', '.', '{"b":2}'))
+    found = {}
+    cursor = b'0'
+    while True:
+        cursor, res = raw.execute_command('JSON.DEBUG', 'KEYSPACE', cursor, 'MATCH', b'doc:\xff*')
+        found.update(zip(res[::2], res[1::2]))
+        if cursor == b'0':
+            break
+    r.assertEqual(list(found.keys()), [b'doc:\xff'])
+
+    # Only the subcommands that take a key declare it
+    r.assertEqual(r.execute_command('COMMAND', 'GETKEYS', 'JSON.DEBUG', 'MEMORY', 'doc:1', '.'), ['doc:1'])
+    r.assertEqual(r.execute_command('COMMAND', 'GETKEYS', 'JSON.DEBUG', 'validate', 'doc:2'), ['doc:2'])
+    r.expect('COMMAND', 'GETKEYS', 'JSON.DEBUG', 'KEYSPACE', '0').raiseError()
+
+def testMaxReplyBytes():
+    """Test the json-max-reply-bytes module argument"""
+    r = Env(moduleArgs='json-max-reply-bytes 20')
+
+    r.assertOk(r.execute_command('JSON.SET', 'small', '.', '{"foo":"bar"}'))
+    r.assertEqual(r.execute_command('JSON.GET', 'small', '.'), '{"foo":"bar"}')
+    r.assertOk(r.execute_command('JSON.SET', 'big', '.', json.dumps({'foo': 'x' * 100})))
+    r.expect('JSON.GET', 'big', '.').error().contains('reply too large')
+    # Paths which select small enough parts of the document are allowed
+    r.assertEqual(r.execute_command('JSON.GET', 'small', 'foo'), '"bar"')
+
+def testSerializationErrors():
+    """Test that failing to serialize a reply is a clean error"""
+    r = Env(moduleArgs='json-max-reply-bytes 20')
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.', json.dumps({'a': ['x' * 10] * 3, 'b': 1})))
+    # Every reply shape fails while serializing, not before or after
+    r.expect('JSON.GET', 'doc', '$..a').error().contains('reply too large')
+    r.expect('JSON.GET', 'doc', '.a', '.b').error().contains('reply too large')
+    r.expect('JSON.GET', 'doc', '$..a', 'WITHCOUNT').error().contains('reply too large')
+    r.expect('JSON.GET', 'doc', '$..a', 'WITHPATHS').error().contains('reply too large')
+    r.expect('JSON.GET', 'doc', 'POINTER', '/a', 'STRINGNUMS', 'PRETTY').error().contains('reply too large')
+    # Bytes that aren't valid UTF-8 are an error in a path, in any reply shape
+    r.expect('JSON.GET', 'doc', b'\xff').error().contains('not valid UTF-8')
+    r.expect('JSON.GET', 'doc', '.b', b'$.\xff').error().contains('not valid UTF-8')
+    r.expect('JSON.GET', 'doc', b'$..\xff', 'WITHPATHS').error().contains('not valid UTF-8')
+    # and are replaced in a value, so its reply is still valid UTF-8
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.c', b'"\xff"'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '.c'), u'"\ufffd"')
+    # The server keeps serving
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '.b'), '1')
+    r.assertTrue(r.execute_command('PING'))
+
+def testMissingKeyError(env):
+    """Test the default reply of write commands on missing keys"""
+    r = env
+
+    r.expect('JSON.ARRAPPEND', 'missing', '.', 1).raiseError()
+    r.expect('JSON.NUMINCRBY', 'missing', '.', 1).raiseError()
+    r.assertEqual(r.execute_command('JSON.DEL', 'missing', '.'), 0)
+    r.assertIsNone(r.execute_command('JSON.GET', 'missing'))
+    r.assertEqual(r.execute_command('EXISTS', 'missing'), 0)
+
+def testMissingKeyNull():
+    """Test the json-missing-key-behavior module argument"""
+    r = Env(moduleArgs='json-missing-key-behavior null')
+
+    r.assertIsNone(r.execute_command('JSON.ARRAPPEND', 'missing', '.', 1))
+    r.assertIsNone(r.execute_command('JSON.NUMINCRBY', 'missing', '.', 1))
+    r.assertIsNone(r.execute_command('JSON.CLEAR', 'missing', '.'))
+    # Nothing is created
+    r.assertEqual(r.execute_command('EXISTS', 'missing'), 0)
+    # Keys that exist are not affected
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.', '[]'))
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'doc', '.', 1), 1)
+
+def testDefaultFormatting():
+    """Test the json-default-indent, json-default-newline and json-default-space module arguments"""
+    r = Env(moduleArgs='json-default-indent ++ json-default-newline ~ json-default-space =')
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.', '{"a":[1]}'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc'), '{~++"a":=[~++++1~++]~}')
+    # Options given to the command win
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'INDENT', '', 'NEWLINE', ''), '{"a":=[1]}')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'SPACE', ' ', 'NEWLINE', '', 'INDENT', ''), '{"a": [1]}')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'PRETTY', 'POINTER', ''), '{\n  "a": [\n    1\n  ]\n}')
+
+def testQueryCache():
+    """Test the json-query-cache module argument"""
+    r = Env(moduleArgs='json-query-cache yes')
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.', '{"a":[1,2],"b":"x"}'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a'), '[[1,2]]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a'), '[[1,2]]')
+    # Every write invalidates the cached reply
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'doc', '.a', 3), 3)
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a'), '[[1,2,3]]')
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.a', '[]'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a'), '[[]]')
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.', '{"a":"new"}'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a'), '["new"]')
+    # Different queries are not mixed up
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a', 'PRETTY'), '[\n  "new"\n]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a'), '["new"]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$'), '[{"a":"new"}]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '.a'), '"new"')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a', 'STRINGNUMS'), '["new"]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a', 'WRAP'), '["new"]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a', 'pretty'), '[\n  "new"\n]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'a'), '"new"')
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', '.a', '.')), {'.a': 'new', '.': {'a': 'new'}})
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', 'a', '.')), {'a': 'new', '.': {'a': 'new'}})
+    # A recreated key starts afresh
+    r.assertEqual(r.execute_command('JSON.DEL', 'doc', '.'), 1)
+    r.assertIsNone(r.execute_command('JSON.GET', 'doc', '$'))
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.', '{"a":1}'))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$'), '[{"a":1}]')
+
+    # The cached reply is counted in the memory of the key, and large replies aren't cached
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.', json.dumps({'s': 'x' * 1000})))
+    before = r.execute_command('MEMORY', 'USAGE', 'doc')
+    r.execute_command('JSON.GET', 'doc', '$')
+    r.assertGreaterEqual(r.execute_command('MEMORY', 'USAGE', 'doc') - before, 1000)
+    # Only writes that change the document drop it
+    cached = r.execute_command('MEMORY', 'USAGE', 'doc')
+    r.assertIsNone(r.execute_command('JSON.SET', 'doc', '$', '{}', 'NX'))
+    r.assertEqual(r.execute_command('MEMORY', 'USAGE', 'doc'), cached)
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.t', '1'))
+    r.assertLess(r.execute_command('MEMORY', 'USAGE', 'doc'), cached)
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '.', json.dumps({'s': 'x' * 100000})))
+    before = r.execute_command('MEMORY', 'USAGE', 'doc')
+    r.execute_command('JSON.GET', 'doc', '$')
+    r.assertLess(r.execute_command('MEMORY', 'USAGE', 'doc') - before, 100000)
+
+def testIterativeSerializer():
+    """Test the json-iterative-serializer module argument"""
+    r = Env(moduleArgs='json-iterative-serializer yes')
+
+    doc = {"a": [1, 2.5, {"b": None, "c": "x\"y"}], "d": {}, "e": [], "f": True}
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', json.dumps(doc)))
+    # The replies are the same as with the recursive serializer
+    r.assertEqual(r.execute_command('JSON.GET', 'doc'), json.dumps(doc, separators=(',', ':')))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'PRETTY', 'POINTER', ''), json.dumps(doc, indent=2, separators=(',', ': ')))
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'SKIPNULL', 'STRINGNUMS', '$.a'), '[["1","2.5",{"c":"x\\"y"}]]')
+    r.assertEqual(json.loads(r.execute_command('JSON.GET', 'doc', '.a[2].c', '.f')), {'.a[2].c': 'x"y', '.f': True})
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'WITHPATHS', '$.d'), '[{"path":"$[\'d\']","value":{}}]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'WITHINDICES', 'POINTER', '/a'), '[[0,1],[1,2.5],[2,{"b":null,"c":"x\\"y"}]]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'FLATMAP', 'POINTER', '/a'), '{"/0":1,"/1":2.5,"/2/b":null,"/2/c":"x\\"y"}')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'ONLYKEYS', 'POINTER', ''), '["a","d","e","f"]')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', 'NDJSON', '$.a[*]'), '1\n2.5\n{"b":null,"c":"x\\"y"}\n')
+    r.assertEqual(r.execute_command('JSON.MGET', 'doc', 'missing', '$.e'), ['[]', None])
+    r.assertEqual(r.execute_command('JSON.RESP', 'doc', '.a[2]'), ['{', 'b', None, 'c', 'x"y'])
+
+    # Documents deeper than the parser allows are built by nesting a copy in a 100 levels template
+    r.assertOk(r.execute_command('JSON.SET', 'deep', '$', '[' * 100 + ']' * 100))
+    for _ in range(99):
+        r.assertOk(r.execute_command('JSON.SET', 'tmp', '$', '[' * 100 + 'null' + ']' * 100))
+        r.assertOk(r.execute_command('JSON.SET', 'tmp', '$' + '[0]' * 100, 'FROMKEY', 'deep', '$'))
+        r.assertOk(r.execute_command('JSON.SET', 'deep', '$', 'FROMKEY', 'tmp', '$'))
+    r.assertEqual(r.execute_command('JSON.GET', 'deep'), '[' * 10000 + ']' * 10000)
+    r.assertEqual(r.execute_command('JSON.GET', 'deep', '$'), '[' * 10001 + ']' * 10001)
+    r.assertEqual(r.execute_command('JSON.MGET', 'deep', '.'), ['[' * 10000 + ']' * 10000])
+    # Not left for the RDB reloads of the tests, which couldn't parse it
+    r.assertEqual(r.execute_command('DEL', 'deep', 'tmp'), 2)
+
+def testIterativeSerializerReplies():
+    """Test that the iterative serializer replies byte for byte as the recursive one"""
+    doc = {"a": [1, -2.5, 1e300, 12345678901234567890, {"b": None, "c": "x\"y\\\n\u00e9\U0001f600"}],
+           "d": {}, "e": [], "f": True, "g": {"h": [[], [{}], {"i": "1.5"}]},
+           "nums": [3, 1.25, -7], "rows": [{"n": "a,b", "v": 1}, {"v": [1, {"w": None}]}]}
+    queries = [
+        ('JSON.GET', 'doc'),
+        ('JSON.GET', 'doc', '$'),
+        ('JSON.GET', 'doc', '.a', '.g'),
+        ('JSON.GET', 'doc', 'INDENT', '\t', 'NEWLINE', '\r\n', 'SPACE', '  ', '$'),
+        ('JSON.GET', 'doc', 'PRETTY', '$..h'),
+        ('JSON.GET', 'doc', 'INDENT', '--', '.g'),
+        ('JSON.GET', 'doc', 'ETAG', '$'),
+        ('JSON.GET', 'doc', 'SIZE', '$'),
+        ('JSON.GET', 'doc', 'SIZE', 'PRETTY', 'POINTER', '/g'),
+        ('JSON.GET', 'doc', 'CSV', '$.rows'),
+        ('JSON.GET', 'doc', 'STRINGNUMS', 'SKIPNULL', '$'),
+        ('JSON.GET', 'doc', 'COERCENUM', '$.g'),
+        ('JSON.GET', 'doc', 'WRAP', 'POINTER', '/a/4'),
+        ('JSON.GET', 'doc', 'WITHPATHS', '$..c'),
+        ('JSON.GET', 'doc', 'WITHCOUNT', '$.a[*]'),
+        ('JSON.GET', 'doc', 'WITHINDICES', '$.a'),
+        ('JSON.GET', 'doc', 'FLATMAP', '$'),
+        ('JSON.GET', 'doc', 'ONLYKEYS', '$..*'),
+        ('JSON.GET', 'doc', 'STATS', '$.nums'),
+        ('JSON.GET', 'doc', 'NDJSON', '$.a[*]'),
+        ('JSON.GET', 'doc', 'COMPRESS', 'GZIP', '$'),
+        ('JSON.MGET', 'doc', 'missing', '$.g'),
+        ('JSON.RESP', 'doc'),
+        ('JSON.RESP', 'doc', '.g'),
+        ('JSON.ARRPOP', 'doc', '.a', '4'),
+        ('JSON.ARRPOP', 'doc', '$.rows'),
+    ]
+
+    replies = []
+    for args in (None, 'json-iterative-serializer yes'):
+        env = Env(moduleArgs=args)
+        env.assertOk(env.execute_command('JSON.SET', 'doc', '$', json.dumps(doc)))
+        kwargs = dict(env.getConnection().connection_pool.connection_kwargs, decode_responses=False)
+        raw = redis.Redis(connection_pool=redis.ConnectionPool(**kwargs))
+        replies.append([raw.execute_command(*query) for query in queries])
+    for query, recursive, iterative in zip(queries, *replies):
+        env.assertEqual(iterative, recursive, message=' '.join(query))
+
+def testMaxArrayLen():
+    """Test the json-max-array-len module argument"""
+    r = Env(moduleArgs='json-max-array-len 4')
+
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$', '{"a":[1,2],"b":{"a":[1]}}'))
+    # Up to the boundary
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'doc', '.a', '3', '4'), 4)
+    r.expect('JSON.ARRAPPEND', 'doc', '.a', '5').error().contains('array length exceeds max')
+    r.expect('JSON.ARRINSERT', 'doc', '.a', '0', '0').error().contains('array length exceeds max')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.a'), '[[1,2,3,4]]')
+    # Every matched array must fit, otherwise none is changed
+    r.expect('JSON.ARRAPPEND', 'doc', '$..a', '5').error().contains('array length exceeds max')
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.b.a'), '[[1]]')
+    r.assertEqual(r.execute_command('JSON.ARRINSERT', 'doc', '$.b.a', '0', '-2', '-1', '0'), 4)
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.c', '[]'))
+    r.assertOk(r.execute_command('JSON.SET', 'doc', '$.c[3]', '1', 'EXTEND'))
+    r.expect('JSON.SET', 'doc', '$.c[4]', '1', 'EXTEND').error().contains('array length exceeds max')
+    # A new array only has to fit once capped by MAXLEN
+    r.expect('JSON.ARRAPPEND', 'doc', '$.d', 'UPSERT', '1', '2', '3', '4', '5').error().contains('array length exceeds max')
+    r.assertEqual(r.execute_command('JSON.ARRAPPEND', 'doc', '$.d', 'UPSERT', 'MAXLEN', '3', '1', '2', '3', '4', '5'), 3)
+    r.assertEqual(r.execute_command('JSON.GET', 'doc', '$.d'), '[[3,4,5]]')
+
 def testRespCommand(env):
     """Test JSON.RESP command"""
     r = env
@@ -864,6 +2759,17 @@ def testIssue_80(env):
     r.execute_command('JSON.GET', 'test', '$.[?(@.code=="2")]')
 
 
+def testRootArrayFilter(env):
+    """Test JSONPath filters over the elements of a root array"""
+    r = env
+    r.expect('JSON.SET', 'test', '$', '[{"id":1,"qty":0},{"id":2,"qty":3},{"id":3,"qty":7}]').ok()
+    r.expect('JSON.GET', 'test', '$[?(@.qty > 0)]').equal('[{"id":2,"qty":3},{"id":3,"qty":7}]')
+    r.expect('JSON.GET', 'test', '$[?(@.qty > 0)].id').equal('[2,3]')
+    r.expect('JSON.GET', 'test', '$[?(@.qty > 100)]').equal('[]')
+    r.expect('JSON.GET', 'test', '$[?(@.id == 1)]').equal('[{"id":1,"qty":0}]')
+    # Legacy paths return the first match
+    r.expect('JSON.GET', 'test', '.[?(@.qty > 0)].id').equal('2')
+
 def testMultiPathResults(env):
     env.expect("JSON.SET", "k", '$', '[1,2,3]').ok()
     env.expect("JSON.GET", "k", '$[*]').equal('[1,2,3]')